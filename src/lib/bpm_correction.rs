@@ -0,0 +1 @@
+pub mod bpm_rules;
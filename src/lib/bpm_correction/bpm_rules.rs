@@ -0,0 +1,387 @@
+pub mod bpm_rules {
+    use std::{fmt, path::Path};
+
+    use regex::Regex;
+    use serde::Deserialize;
+
+    use crate::{
+        error::error::LibHelperError,
+        track_categorization::{genre::genre::EDM_FAMILY, taxonomy::taxonomy::GenreTaxonomy},
+    };
+
+    /// A set of BPM corrections, read from a TOML file with one `[[rule]]` table per rule:
+    ///
+    /// ```toml
+    /// [[rule]]
+    /// name = "Drum & bass detected at half tempo"
+    /// genre = "drum ?(&|and|n) ?bass|dnb"
+    /// min_bpm = 80
+    /// max_bpm = 95
+    /// multiplier = "2"
+    /// ```
+    ///
+    /// A track is corrected by the first rule that matches it. Instead of `genre`,
+    /// a rule can name a family of the genre taxonomy, such as
+    /// `genre_family = "Drum & Bass"`. A top-level `taxonomy = "genres.toml"`
+    /// extends the built-in taxonomy with a file relative to the rules.
+    #[derive(Debug)]
+    pub struct BpmRuleSet {
+        pub rules: Vec<BpmRule>,
+        pub taxonomy: GenreTaxonomy,
+    }
+
+    #[derive(Debug)]
+    pub struct BpmRule {
+        pub name: Option<String>,
+        pub genre: GenreMatcher,
+        /// Inclusive lower bound
+        pub min_bpm: f64,
+        /// Exclusive upper bound
+        pub max_bpm: f64,
+        pub multiplier: Multiplier,
+    }
+
+    #[derive(Debug)]
+    pub enum GenreMatcher {
+        /// A case-insensitive regular expression, from `genre = "..."`
+        Pattern(Regex),
+        /// A genre of the taxonomy and everything below it, from `genre_family = "..."`
+        Family(String),
+    }
+
+    pub const DEFAULT_MIN_AUDIO_CONFIDENCE: f64 = 0.6;
+
+    /// How [`BpmRuleSet`]s are applied.
+    #[derive(Clone, Debug)]
+    pub struct FixBpmOptions {
+        /// Also change tracks whose BPM was locked in Mixxx
+        pub include_locked: bool,
+        /// Decode every matched track and only correct it if its audio favours the new tempo
+        pub verify_audio: bool,
+        /// How clearly the audio has to favour the new tempo over the current one,
+        /// from 0.5 (any lean) to 1 (only the new tempo fits)
+        pub min_confidence: f64,
+        /// Write the files whose tags could not be read to this CSV file, or JSON if it ends in `.json`
+        pub tag_report: Option<String>,
+        /// Also write the corrected BPMs to the tags of the files
+        pub write_tags: bool,
+        /// Keep the timestamps of the files whose tags are written
+        pub preserve_mtime: bool,
+    }
+
+    impl Default for FixBpmOptions {
+        fn default() -> Self {
+            FixBpmOptions {
+                include_locked: false,
+                verify_audio: false,
+                min_confidence: DEFAULT_MIN_AUDIO_CONFIDENCE,
+                tag_report: None,
+                write_tags: false,
+                preserve_mtime: false,
+            }
+        }
+    }
+
+    /// A tempo ratio such as 3/2, kept as a fraction so it prints the way it was written.
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct Multiplier {
+        pub numerator: u32,
+        pub denominator: u32,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct RuleFile {
+        taxonomy: Option<String>,
+        #[serde(default)]
+        rule: Vec<RuleEntry>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct RuleEntry {
+        name: Option<String>,
+        genre: Option<String>,
+        genre_family: Option<String>,
+        min_bpm: Option<f64>,
+        max_bpm: Option<f64>,
+        multiplier: MultiplierEntry,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum MultiplierEntry {
+        Integer(u32),
+        Text(String),
+    }
+
+    impl BpmRuleSet {
+        /// The correction this tool has always made: EDM tracks below 100 BPM
+        /// were detected at 2/3 of their tempo.
+        pub fn edm_default() -> BpmRuleSet {
+            BpmRuleSet {
+                rules: vec![BpmRule {
+                    name: Some(String::from("EDM detected at 2/3 of its tempo")),
+                    genre: GenreMatcher::Family(String::from(EDM_FAMILY)),
+                    min_bpm: 0.0,
+                    max_bpm: 100.0,
+                    multiplier: Multiplier {
+                        numerator: 3,
+                        denominator: 2,
+                    },
+                }],
+                taxonomy: GenreTaxonomy::default(),
+            }
+        }
+
+        pub fn load(path: &str) -> Result<BpmRuleSet, LibHelperError> {
+            let contents = std::fs::read_to_string(path)?;
+            let rules_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+            BpmRuleSet::parse(&contents, rules_dir)
+                .map_err(|err| LibHelperError::Config(format!("{path}: {err}")))
+        }
+
+        /// Reads rules, resolving a `taxonomy` file relative to the working directory.
+        pub fn from_toml(contents: &str) -> Result<BpmRuleSet, LibHelperError> {
+            BpmRuleSet::parse(contents, Path::new(""))
+        }
+
+        fn parse(contents: &str, rules_dir: &Path) -> Result<BpmRuleSet, LibHelperError> {
+            let file: RuleFile =
+                toml::from_str(contents).map_err(|err| LibHelperError::Config(err.to_string()))?;
+
+            let taxonomy = match file.taxonomy {
+                Some(taxonomy_path) => {
+                    GenreTaxonomy::load(&rules_dir.join(taxonomy_path).to_string_lossy())?
+                }
+                None => GenreTaxonomy::default(),
+            };
+
+            let mut rules = vec![];
+            for (index, entry) in file.rule.into_iter().enumerate() {
+                let rule = BpmRule::from_entry(entry, &taxonomy)
+                    .map_err(|err| LibHelperError::Config(format!("rule {}: {err}", index + 1)))?;
+                rules.push(rule);
+            }
+
+            Ok(BpmRuleSet { rules, taxonomy })
+        }
+
+        /// The first rule that applies to a track with this BPM and any of these genres.
+        pub fn find_rule(&self, bpm: f64, genres: &[&str]) -> Option<&BpmRule> {
+            self.rules.iter().find(|rule| {
+                rule.matches_bpm(bpm)
+                    && genres
+                        .iter()
+                        .any(|genre| rule.matches_genre(genre, &self.taxonomy))
+            })
+        }
+    }
+
+    impl BpmRule {
+        fn from_entry(
+            entry: RuleEntry,
+            taxonomy: &GenreTaxonomy,
+        ) -> Result<BpmRule, LibHelperError> {
+            let genre = match (entry.genre, entry.genre_family) {
+                (Some(pattern), None) => {
+                    GenreMatcher::Pattern(Regex::new(&format!("(?i){pattern}"))?)
+                }
+                (None, Some(family)) => match taxonomy.find(&family) {
+                    Some(node) => GenreMatcher::Family(node.name.clone()),
+                    None => {
+                        return Err(LibHelperError::Config(format!(
+                            "unknown genre_family {family}"
+                        )))
+                    }
+                },
+                _ => {
+                    return Err(LibHelperError::Config(String::from(
+                        "set either genre or genre_family",
+                    )))
+                }
+            };
+
+            let min_bpm = entry.min_bpm.unwrap_or(0.0);
+            let max_bpm = entry.max_bpm.unwrap_or(f64::INFINITY);
+            if min_bpm >= max_bpm {
+                return Err(LibHelperError::Config(format!(
+                    "min_bpm {min_bpm} is not below max_bpm {max_bpm}"
+                )));
+            }
+
+            Ok(BpmRule {
+                name: entry.name,
+                genre,
+                min_bpm,
+                max_bpm,
+                multiplier: Multiplier::from_entry(entry.multiplier)?,
+            })
+        }
+
+        pub fn matches_bpm(&self, bpm: f64) -> bool {
+            bpm >= self.min_bpm && bpm < self.max_bpm
+        }
+
+        pub fn matches_genre(&self, genre: &str, taxonomy: &GenreTaxonomy) -> bool {
+            match &self.genre {
+                GenreMatcher::Pattern(pattern) => pattern.is_match(genre),
+                GenreMatcher::Family(family) => taxonomy.is_in_family(genre, family),
+            }
+        }
+    }
+
+    impl fmt::Display for BpmRule {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if let Some(name) = &self.name {
+                write!(f, "{name}: ")?;
+            }
+            match &self.genre {
+                GenreMatcher::Pattern(pattern) => {
+                    let pattern = pattern.as_str().trim_start_matches("(?i)");
+                    write!(f, "genre /{pattern}/")?
+                }
+                GenreMatcher::Family(family) => write!(f, "{family} genres")?,
+            }
+            if self.max_bpm.is_finite() {
+                write!(f, ", {}-{} BPM", self.min_bpm, self.max_bpm)?;
+            } else {
+                write!(f, ", from {} BPM", self.min_bpm)?;
+            }
+            write!(f, ", BPM * {}", self.multiplier)
+        }
+    }
+
+    impl Multiplier {
+        fn from_entry(entry: MultiplierEntry) -> Result<Multiplier, LibHelperError> {
+            let (numerator, denominator) = match entry {
+                MultiplierEntry::Integer(numerator) => (numerator, 1),
+                MultiplierEntry::Text(text) => {
+                    let invalid = || {
+                        LibHelperError::Config(format!(
+                            "multiplier {text} is not a number or fraction like 3/2"
+                        ))
+                    };
+                    match text.split_once('/') {
+                        Some((numerator, denominator)) => (
+                            numerator.trim().parse().map_err(|_| invalid())?,
+                            denominator.trim().parse().map_err(|_| invalid())?,
+                        ),
+                        None => (text.trim().parse().map_err(|_| invalid())?, 1),
+                    }
+                }
+            };
+            if numerator == 0 || denominator == 0 {
+                return Err(LibHelperError::Config(String::from(
+                    "multiplier must not be zero",
+                )));
+            }
+
+            Ok(Multiplier {
+                numerator,
+                denominator,
+            })
+        }
+
+        /// Applies the ratio, rounded to two decimals like Mixxx shows it.
+        pub fn apply(&self, bpm: f64) -> f64 {
+            let new_bpm = bpm * f64::from(self.numerator) / f64::from(self.denominator);
+            (new_bpm * 100.0).round() / 100.0
+        }
+    }
+
+    impl fmt::Display for Multiplier {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            if self.denominator == 1 {
+                write!(f, "{}", self.numerator)
+            } else {
+                write!(f, "{}/{}", self.numerator, self.denominator)
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn from_toml_reads_rules() {
+            // setup
+            let contents = r#"
+                [[rule]]
+                name = "DnB"
+                genre = "drum ?(&|and|n) ?bass|dnb"
+                min_bpm = 80
+                max_bpm = 95
+                multiplier = "2"
+
+                [[rule]]
+                genre = "hip.?hop"
+                min_bpm = 160
+                multiplier = "1/2"
+            "#;
+
+            // run
+            let rule_set = BpmRuleSet::from_toml(contents).unwrap();
+
+            // verify
+            assert_eq!(rule_set.rules.len(), 2);
+            let dnb = rule_set.find_rule(87.0, &["Drum & Bass"]).unwrap();
+            assert_eq!(dnb.multiplier.apply(87.0), 174.0);
+            let hip_hop = rule_set.find_rule(180.0, &["", "Hip-Hop"]).unwrap();
+            assert_eq!(hip_hop.multiplier.to_string(), "1/2");
+            assert!(rule_set.find_rule(174.0, &["Drum & Bass"]).is_none());
+        }
+
+        #[test]
+        fn from_toml_rejects_invalid_multiplier() {
+            // setup
+            let contents = r#"
+                [[rule]]
+                genre = "house"
+                multiplier = "3/0"
+            "#;
+
+            // run
+            let result = BpmRuleSet::from_toml(contents);
+
+            // verify
+            assert!(matches!(result, Err(LibHelperError::Config(_))));
+        }
+
+        #[test]
+        fn edm_default_converts_low_trance_bpm() {
+            // run
+            let rule_set = BpmRuleSet::edm_default();
+
+            // verify
+            let rule = rule_set.find_rule(92.0, &["Trance"]).unwrap();
+            assert_eq!(rule.multiplier.apply(92.0), 138.0);
+            assert!(rule_set.find_rule(138.0, &["Trance"]).is_none());
+            assert!(rule_set.find_rule(92.0, &["Dancehall"]).is_none());
+        }
+
+        #[test]
+        fn from_toml_matches_genre_family() {
+            // setup
+            let contents = r#"
+                [[rule]]
+                genre_family = "dnb"
+                min_bpm = 80
+                max_bpm = 95
+                multiplier = 2
+            "#;
+
+            // run
+            let rule_set = BpmRuleSet::from_toml(contents).unwrap();
+
+            // verify
+            assert!(rule_set.find_rule(87.0, &["Neurofunk"]).is_some());
+            assert!(rule_set.find_rule(87.0, &["House"]).is_none());
+            assert_eq!(
+                rule_set.rules[0].to_string(),
+                "Drum & Bass genres, 80-95 BPM, BPM * 2"
+            );
+        }
+    }
+}
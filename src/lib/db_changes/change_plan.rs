@@ -0,0 +1,475 @@
+pub mod change_plan {
+    use std::fmt;
+
+    use rusqlite::{
+        types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef},
+        Connection, ToSql, TransactionBehavior,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+    use crate::error::error::LibHelperError;
+    use crate::track_duplicates::track_merge::track_merge::{
+        merge_track_rows, require_merge_columns, TrackMerge,
+    };
+    use crate::track_movement::move_fns::movefns::{move_file, undo_moves, FileMove};
+
+    pub const DEFAULT_KEEP_BACKUPS: usize = 10;
+
+    /// How a database-mutating operation should be carried out.
+    #[derive(Clone, Debug)]
+    pub struct WriteOptions {
+        /// Print the planned changes instead of writing them.
+        pub dry_run: bool,
+        /// Save the planned changes to this JSON file instead of writing them.
+        pub plan_out: Option<String>,
+        /// Where to keep database backups, defaults to a directory next to the database.
+        pub backup_dir: Option<String>,
+        /// How many backups to keep before the oldest ones are deleted.
+        pub keep_backups: usize,
+        /// Wait for Mixxx to close instead of failing when it is running.
+        pub wait: bool,
+        /// Apply without asking for confirmation.
+        pub assume_yes: bool,
+    }
+
+    impl Default for WriteOptions {
+        fn default() -> Self {
+            WriteOptions {
+                dry_run: false,
+                plan_out: None,
+                backup_dir: None,
+                keep_backups: DEFAULT_KEEP_BACKUPS,
+                wait: false,
+                assume_yes: false,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ChangeTable {
+        Library,
+        TrackLocations,
+        Directories,
+    }
+
+    impl ChangeTable {
+        fn name(&self) -> &'static str {
+            match self {
+                ChangeTable::Library => "library",
+                ChangeTable::TrackLocations => "track_locations",
+                ChangeTable::Directories => "directories",
+            }
+        }
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ColumnValue {
+        Null,
+        Integer(i64),
+        Real(f64),
+        Text(String),
+        Blob(Vec<u8>),
+    }
+
+    impl ToSql for ColumnValue {
+        fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+            Ok(ToSqlOutput::Borrowed(match self {
+                ColumnValue::Null => ValueRef::Null,
+                ColumnValue::Integer(value) => ValueRef::Integer(*value),
+                ColumnValue::Real(value) => ValueRef::Real(*value),
+                ColumnValue::Text(value) => ValueRef::Text(value.as_bytes()),
+                ColumnValue::Blob(value) => ValueRef::Blob(value),
+            }))
+        }
+    }
+
+    impl FromSql for ColumnValue {
+        fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+            Ok(match value {
+                ValueRef::Null => ColumnValue::Null,
+                ValueRef::Integer(value) => ColumnValue::Integer(value),
+                ValueRef::Real(value) => ColumnValue::Real(value),
+                ValueRef::Text(_) => ColumnValue::Text(value.as_str()?.to_string()),
+                ValueRef::Blob(value) => ColumnValue::Blob(value.to_vec()),
+            })
+        }
+    }
+
+    impl fmt::Display for ColumnValue {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ColumnValue::Null => write!(f, "NULL"),
+                ColumnValue::Integer(value) => write!(f, "{value}"),
+                ColumnValue::Real(value) => write!(f, "{value}"),
+                ColumnValue::Text(value) => write!(f, "{value:?}"),
+                ColumnValue::Blob(value) => write!(f, "<{} bytes>", value.len()),
+            }
+        }
+    }
+
+    /// A single column of a single row that an operation wants to change.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct ColumnChange {
+        pub table: ChangeTable,
+        pub track_id: Option<usize>,
+        pub location: String,
+        pub column: String,
+        pub old_value: ColumnValue,
+        pub new_value: ColumnValue,
+    }
+
+    /// Everything an operation would change, in the order it would change it.
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    pub struct ChangePlan {
+        #[serde(default)]
+        pub file_moves: Vec<FileMove>,
+        #[serde(default)]
+        pub changes: Vec<ColumnChange>,
+        #[serde(default)]
+        pub track_merges: Vec<TrackMerge>,
+    }
+
+    impl ChangePlan {
+        pub fn is_empty(&self) -> bool {
+            self.file_moves.is_empty() && self.changes.is_empty() && self.track_merges.is_empty()
+        }
+
+        /// The number of distinct tracks the column changes and merges touch.
+        pub fn track_count(&self) -> usize {
+            let mut track_ids: Vec<usize> = self
+                .changes
+                .iter()
+                .filter_map(|change| change.track_id)
+                .chain(self.track_merges.iter().flat_map(|merge| {
+                    std::iter::once(merge.keep_id).chain(merge.drop_ids.iter().copied())
+                }))
+                .collect();
+            track_ids.sort();
+            track_ids.dedup();
+            track_ids.len()
+        }
+
+        pub fn load(path: &str) -> Result<ChangePlan, LibHelperError> {
+            let file_as_string = std::fs::read_to_string(path)?;
+            Ok(serde_json::from_str(&file_as_string)?)
+        }
+
+        pub fn save(&self, path: &str) -> Result<(), LibHelperError> {
+            std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+            Ok(())
+        }
+    }
+
+    impl fmt::Display for ChangePlan {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            for file_move in &self.file_moves {
+                writeln!(
+                    f,
+                    "move {} -> {}",
+                    file_move.source.display(),
+                    file_move.target.display()
+                )?;
+            }
+            for merge in &self.track_merges {
+                let drop_ids: Vec<String> =
+                    merge.drop_ids.iter().map(|id| id.to_string()).collect();
+                writeln!(
+                    f,
+                    "merge track {} into track {}",
+                    drop_ids.join(", "),
+                    merge.keep_id
+                )?;
+            }
+            for change in &self.changes {
+                let track_id = change
+                    .track_id
+                    .map(|id| id.to_string())
+                    .unwrap_or(String::from("-"));
+                writeln!(
+                    f,
+                    "track {track_id}\t{}\t{}.{}: {} -> {}",
+                    change.location,
+                    change.table.name(),
+                    change.column,
+                    change.old_value,
+                    change.new_value
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Plans setting `column` of the `library` or `track_locations` row of a track,
+    /// reading the current value from the database. Returns `None` if the value is already set.
+    pub fn plan_track_change(
+        db: &MixxxDb,
+        table: ChangeTable,
+        track_id: usize,
+        column: &str,
+        new_value: ColumnValue,
+    ) -> Result<Option<ColumnChange>, LibHelperError> {
+        check_column_name(column)?;
+        let alias = match table {
+            ChangeTable::Library => "l",
+            ChangeTable::TrackLocations => "tl",
+            ChangeTable::Directories => {
+                return Err(LibHelperError::Plan(String::from(
+                    "directories are not track rows",
+                )))
+            }
+        };
+
+        db.require_columns("library", &["id", "location"])?;
+        db.require_columns("track_locations", &["id", "location"])?;
+        db.require_columns(table.name(), &[column])?;
+
+        let (location, old_value): (String, ColumnValue) = db.connection().query_row(
+            &format!(
+                "SELECT tl.location, {alias}.{column} FROM library l
+                 INNER JOIN track_locations tl
+                 ON tl.id = l.location
+                 WHERE l.id = ?1;"
+            ),
+            [track_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        if old_value == new_value {
+            return Ok(None);
+        }
+
+        Ok(Some(ColumnChange {
+            table,
+            track_id: Some(track_id),
+            location,
+            column: column.to_string(),
+            old_value,
+            new_value,
+        }))
+    }
+
+    pub fn plan_directory_change(old_directory: &str, new_directory: &str) -> ColumnChange {
+        ColumnChange {
+            table: ChangeTable::Directories,
+            track_id: None,
+            location: old_directory.to_string(),
+            column: String::from("directory"),
+            old_value: ColumnValue::Text(old_directory.to_string()),
+            new_value: ColumnValue::Text(new_directory.to_string()),
+        }
+    }
+
+    /// Carries out a plan: moves the files, then merges tracks and writes all column
+    /// changes in one transaction.
+    ///
+    /// Every change only applies if the column still holds the value it had when
+    /// the plan was made, so a stale plan fails instead of overwriting newer edits.
+    /// On failure the transaction is rolled back, moved files are moved back and
+    /// the directories created for them are removed.
+    pub fn apply_plan(db: &mut MixxxDb, plan: &ChangePlan) -> Result<(), LibHelperError> {
+        for change in &plan.changes {
+            check_column_name(&change.column)?;
+            db.require_columns(change.table.name(), &[&change.column])?;
+        }
+        if !plan.track_merges.is_empty() {
+            require_merge_columns(db)?;
+        }
+
+        let mut moved_files = vec![];
+        let mut created_dirs = vec![];
+        for file_move in &plan.file_moves {
+            if let Err(err) = move_file(&file_move.source, &file_move.target, &mut created_dirs) {
+                undo_moves(&moved_files, &created_dirs);
+                return Err(err.into());
+            }
+            moved_files.push(file_move.clone());
+        }
+
+        if let Err(err) = write_changes(db.connection_mut(), &plan.changes, &plan.track_merges) {
+            undo_moves(&moved_files, &created_dirs);
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    fn write_changes(
+        connection: &mut Connection,
+        changes: &[ColumnChange],
+        track_merges: &[TrackMerge],
+    ) -> Result<(), LibHelperError> {
+        let transaction = connection.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+        for merge in track_merges {
+            merge_track_rows(&transaction, merge)?;
+        }
+        for change in changes {
+            let table = change.table.name();
+            let column = &change.column;
+            let row_filter = match change.table {
+                ChangeTable::Library => "id = ?3",
+                ChangeTable::TrackLocations => "id = (SELECT location FROM library WHERE id = ?3)",
+                ChangeTable::Directories => "?3 IS NULL",
+            };
+
+            let updated = transaction.execute(
+                &format!("UPDATE {table} SET {column} = ?1 WHERE {row_filter} AND {column} IS ?2"),
+                (&change.new_value, &change.old_value, change.track_id),
+            )?;
+            if updated != 1 {
+                return Err(LibHelperError::Plan(format!(
+                    "{table}.{column} of {} is no longer {}, the plan is outdated",
+                    change.location, change.old_value
+                )));
+            }
+        }
+        transaction.commit()?;
+
+        Ok(())
+    }
+
+    fn check_column_name(column: &str) -> Result<(), LibHelperError> {
+        let is_identifier = !column.is_empty()
+            && column
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+        if !is_identifier {
+            return Err(LibHelperError::Plan(format!(
+                "Invalid column name: {column}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_mixxx_db};
+
+        fn bpm_of(connection: &Connection, id: usize) -> f64 {
+            connection
+                .query_row("SELECT bpm FROM library WHERE id = ?1", [id], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+        }
+
+        #[test]
+        fn plan_track_change_reads_old_value() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/track.mp3", "Trance", 92.0);
+
+            // run
+            let change = plan_track_change(
+                &db,
+                ChangeTable::Library,
+                1,
+                "bpm",
+                ColumnValue::Real(138.0),
+            )
+            .unwrap()
+            .unwrap();
+
+            // verify
+            assert_eq!(change.location, "/music/track.mp3");
+            assert_eq!(change.old_value, ColumnValue::Real(92.0));
+            assert_eq!(change.new_value, ColumnValue::Real(138.0));
+        }
+
+        #[test]
+        fn plan_track_change_rejects_invalid_column_names() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/track.mp3", "Trance", 92.0);
+
+            // run
+            let result = plan_track_change(
+                &db,
+                ChangeTable::Library,
+                1,
+                "bpm = 0; --",
+                ColumnValue::Real(138.0),
+            );
+
+            // verify
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn plan_survives_json_round_trip_and_applies() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/track.mp3", "Trance", 92.0);
+            let change = plan_track_change(
+                &db,
+                ChangeTable::Library,
+                1,
+                "bpm",
+                ColumnValue::Real(138.0),
+            )
+            .unwrap()
+            .unwrap();
+            let plan = ChangePlan {
+                file_moves: vec![],
+                changes: vec![change],
+                ..ChangePlan::default()
+            };
+            let json = serde_json::to_string(&plan).unwrap();
+
+            // run
+            let loaded: ChangePlan = serde_json::from_str(&json).unwrap();
+            apply_plan(&mut db, &loaded).unwrap();
+
+            // verify
+            assert_eq!(loaded, plan);
+            assert_eq!(bpm_of(db.connection(), 1), 138.0);
+        }
+
+        #[test]
+        fn apply_plan_fails_on_outdated_plan() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/track.mp3", "Trance", 92.0);
+            insert_track(db.connection(), 2, "/music/other.mp3", "Trance", 92.0);
+            let plan = ChangePlan {
+                file_moves: vec![],
+                changes: vec![
+                    plan_track_change(
+                        &db,
+                        ChangeTable::Library,
+                        1,
+                        "bpm",
+                        ColumnValue::Real(138.0),
+                    )
+                    .unwrap()
+                    .unwrap(),
+                    plan_track_change(
+                        &db,
+                        ChangeTable::Library,
+                        2,
+                        "bpm",
+                        ColumnValue::Real(138.0),
+                    )
+                    .unwrap()
+                    .unwrap(),
+                ],
+                ..ChangePlan::default()
+            };
+            db.connection()
+                .execute("UPDATE library SET bpm = 140 WHERE id = 2", [])
+                .unwrap();
+
+            // run
+            let result = apply_plan(&mut db, &plan);
+
+            // verify
+            assert!(result.is_err());
+            assert_eq!(bpm_of(db.connection(), 1), 92.0);
+            assert_eq!(bpm_of(db.connection(), 2), 140.0);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+pub mod backup {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use rusqlite::{Connection, DatabaseName, OpenFlags};
+
+    use crate::error::error::LibHelperError;
+
+    const BACKUP_PREFIX: &str = "mixxxdb-";
+    const BACKUP_EXTENSION: &str = ".sqlite";
+    const DEFAULT_BACKUP_DIR_NAME: &str = "libhelper-backups";
+
+    #[derive(Clone, Debug)]
+    pub struct BackupInfo {
+        pub path: PathBuf,
+        /// `YYYY-MM-DD HH:MM:SS` in UTC, taken from the file name
+        pub created: String,
+        /// `None` if the backup could not be read
+        pub track_count: Option<usize>,
+    }
+
+    /// The directory backups go to unless configured otherwise: next to the database.
+    pub fn default_backup_dir(db_path: &Path) -> PathBuf {
+        db_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .join(DEFAULT_BACKUP_DIR_NAME)
+    }
+
+    /// Copies the database into `backup_dir` with SQLite's online backup API,
+    /// which yields a consistent snapshot even while the WAL holds uncommitted
+    /// pages. Only the newest `keep` backups are kept afterwards.
+    pub fn create_backup(
+        connection: &Connection,
+        backup_dir: &Path,
+        keep: usize,
+    ) -> Result<PathBuf, LibHelperError> {
+        fs::create_dir_all(backup_dir)?;
+
+        let timestamp: String =
+            connection.query_row("SELECT strftime('%Y%m%d-%H%M%f', 'now')", [], |row| {
+                row.get(0)
+            })?;
+        let backup_path = backup_dir.join(format!("{BACKUP_PREFIX}{timestamp}{BACKUP_EXTENSION}"));
+        connection.backup(DatabaseName::Main, &backup_path, None)?;
+
+        prune_backups(backup_dir, keep)?;
+
+        Ok(backup_path)
+    }
+
+    /// Lists the backups in `backup_dir`, oldest first.
+    pub fn list_backups(backup_dir: &Path) -> Result<Vec<BackupInfo>, std::io::Error> {
+        let mut backups = vec![];
+        for path in list_backup_files(backup_dir)? {
+            let created = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(format_backup_timestamp)
+                .unwrap_or_default();
+            backups.push(BackupInfo {
+                track_count: count_tracks(&path).ok(),
+                path,
+                created,
+            });
+        }
+
+        Ok(backups)
+    }
+
+    /// Replaces the contents of the database with a backup, again through the
+    /// online backup API so other connections see a consistent state.
+    pub fn restore_backup(
+        connection: &mut Connection,
+        backup_path: &Path,
+    ) -> Result<(), LibHelperError> {
+        count_tracks(backup_path).map_err(|err| {
+            LibHelperError::SchemaMismatch(format!(
+                "{} is not a Mixxx database: {err}",
+                backup_path.display()
+            ))
+        })?;
+        connection.restore(DatabaseName::Main, backup_path, None::<fn(_)>)?;
+
+        Ok(())
+    }
+
+    fn list_backup_files(backup_dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        if !backup_dir.is_dir() {
+            return Ok(vec![]);
+        }
+
+        let mut files = vec![];
+        for entry in fs::read_dir(backup_dir)? {
+            let path = entry?.path();
+            let is_backup = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with(BACKUP_PREFIX) && name.ends_with(BACKUP_EXTENSION)
+                });
+            if is_backup {
+                files.push(path);
+            }
+        }
+
+        // the timestamp in the name sorts chronologically
+        files.sort();
+        Ok(files)
+    }
+
+    fn prune_backups(backup_dir: &Path, keep: usize) -> Result<(), std::io::Error> {
+        let files = list_backup_files(backup_dir)?;
+        // the newest backup is the one just taken, it is kept whatever `keep` says
+        let surplus = files.len().saturating_sub(keep.max(1));
+        for file in &files[..surplus] {
+            fs::remove_file(file)?;
+        }
+
+        Ok(())
+    }
+
+    fn count_tracks(db_path: &Path) -> Result<usize, rusqlite::Error> {
+        let connection = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        connection.query_row(
+            "SELECT COUNT(*) FROM library WHERE mixxx_deleted IS NOT 1",
+            [],
+            |row| row.get(0),
+        )
+    }
+
+    /// Turns `mixxxdb-20240131-235959.123.sqlite` into `2024-01-31 23:59:59`.
+    fn format_backup_timestamp(file_name: &str) -> Option<String> {
+        let timestamp = file_name
+            .strip_prefix(BACKUP_PREFIX)?
+            .strip_suffix(BACKUP_EXTENSION)?;
+        let (date, time) = timestamp.split_once('-')?;
+        if date.len() != 8 || time.len() < 6 {
+            return None;
+        }
+
+        Some(format!(
+            "{}-{}-{} {}:{}:{}",
+            &date[0..4],
+            &date[4..6],
+            &date[6..8],
+            &time[0..2],
+            &time[2..4],
+            &time[4..6]
+        ))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_db};
+
+        #[test]
+        fn format_backup_timestamp_works() {
+            assert_eq!(
+                format_backup_timestamp("mixxxdb-20240131-235959.123.sqlite"),
+                Some(String::from("2024-01-31 23:59:59"))
+            );
+            assert_eq!(format_backup_timestamp("notes.txt"), None);
+        }
+
+        #[test]
+        fn create_backup_keeps_only_newest_backups() {
+            // setup
+            let backup_dir = tempfile::tempdir().unwrap();
+            let connection = open_test_db();
+            insert_track(&connection, 1, "/music/track.mp3", "Trance", 138.0);
+
+            // run
+            let mut created = vec![];
+            for _ in 0..3 {
+                created.push(create_backup(&connection, backup_dir.path(), 2).unwrap());
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+
+            // verify
+            let backups = list_backups(backup_dir.path()).unwrap();
+            let paths: Vec<PathBuf> = backups.iter().map(|backup| backup.path.clone()).collect();
+            assert_eq!(paths, created[1..].to_vec());
+            assert_eq!(backups[0].track_count, Some(1));
+        }
+
+        #[test]
+        fn create_backup_never_prunes_the_new_backup() {
+            // setup
+            let backup_dir = tempfile::tempdir().unwrap();
+            let connection = open_test_db();
+
+            // run
+            let created = create_backup(&connection, backup_dir.path(), 0).unwrap();
+
+            // verify
+            assert!(created.is_file());
+        }
+
+        #[test]
+        fn restore_backup_brings_back_old_values() {
+            // setup
+            let backup_dir = tempfile::tempdir().unwrap();
+            let mut connection = open_test_db();
+            insert_track(&connection, 1, "/music/track.mp3", "Trance", 92.0);
+            let backup_path = create_backup(&connection, backup_dir.path(), 5).unwrap();
+            connection
+                .execute("UPDATE library SET bpm = 138 WHERE id = 1", [])
+                .unwrap();
+
+            // run
+            restore_backup(&mut connection, &backup_path).unwrap();
+
+            // verify
+            let bpm: f64 = connection
+                .query_row("SELECT bpm FROM library WHERE id = 1", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(bpm, 92.0);
+        }
+    }
+}
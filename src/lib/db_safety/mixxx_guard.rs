@@ -0,0 +1,109 @@
+pub mod mixxx_guard {
+    use std::{fs, path::Path, thread, time::Duration};
+
+    use rusqlite::Connection;
+
+    use crate::error::error::LibHelperError;
+
+    const MIXXX_PROCESS_NAME: &str = "mixxx";
+    const PROC_DIR: &str = "/proc";
+    const WAIT_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// Makes sure nobody else uses the database before it is written to: no
+    /// Mixxx process may be running and an exclusive SQLite lock must be
+    /// obtainable. With `wait`, blocks until both hold instead of failing.
+    pub fn ensure_mixxx_not_running(db_path: &str, wait: bool) -> Result<(), LibHelperError> {
+        let mut announced_wait = false;
+        loop {
+            let problem =
+                match find_processes_named(Path::new(PROC_DIR), MIXXX_PROCESS_NAME).first() {
+                    Some(pid) => Some(format!("Mixxx is running (pid {pid})")),
+                    None => check_db_not_locked(db_path)
+                        .err()
+                        .map(|err| format!("{db_path} is locked by another program: {err}")),
+                };
+
+            let Some(problem) = problem else {
+                return Ok(());
+            };
+            if !wait {
+                return Err(LibHelperError::MixxxRunning(format!(
+                    "{problem}. Close Mixxx before changing its database or pass --wait"
+                )));
+            }
+            if !announced_wait {
+                println!("{problem}, waiting until it is closed");
+                announced_wait = true;
+            }
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+
+    /// Returns the ids of the processes whose name matches `name`, ignoring case.
+    /// Finds nothing on systems without a `/proc` file system.
+    pub fn find_processes_named(proc_dir: &Path, name: &str) -> Vec<u32> {
+        let Ok(entries) = fs::read_dir(proc_dir) else {
+            return vec![];
+        };
+
+        let mut pids: Vec<u32> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let pid = entry.file_name().to_str()?.parse::<u32>().ok()?;
+                let comm = fs::read_to_string(entry.path().join("comm")).ok()?;
+                comm.trim().eq_ignore_ascii_case(name).then_some(pid)
+            })
+            .collect();
+        pids.sort();
+        pids
+    }
+
+    /// Tries to take and immediately release an exclusive lock on the database.
+    pub fn check_db_not_locked(db_path: &str) -> Result<(), rusqlite::Error> {
+        let connection = Connection::open(db_path)?;
+        connection.busy_timeout(Duration::ZERO)?;
+        connection.execute_batch("BEGIN EXCLUSIVE; ROLLBACK;")
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn find_processes_named_reads_comm_files() {
+            // setup
+            let proc_dir = tempfile::tempdir().unwrap();
+            for (pid, comm) in [("12", "bash\n"), ("345", "mixxx\n"), ("self", "mixxx\n")] {
+                fs::create_dir_all(proc_dir.path().join(pid)).unwrap();
+                fs::write(proc_dir.path().join(pid).join("comm"), comm).unwrap();
+            }
+
+            // run
+            let pids = find_processes_named(proc_dir.path(), "mixxx");
+
+            // verify
+            assert_eq!(pids, vec![345]);
+        }
+
+        #[test]
+        fn check_db_not_locked_fails_while_another_connection_writes() {
+            // setup
+            let db_dir = tempfile::tempdir().unwrap();
+            let db_path = db_dir.path().join("mixxxdb.sqlite");
+            let db_path = db_path.to_str().unwrap();
+            let other = Connection::open(db_path).unwrap();
+            other
+                .execute_batch("CREATE TABLE library (id INTEGER); BEGIN EXCLUSIVE;")
+                .unwrap();
+
+            // run
+            let while_locked = check_db_not_locked(db_path);
+            other.execute_batch("COMMIT;").unwrap();
+            let after_unlock = check_db_not_locked(db_path);
+
+            // verify
+            assert!(while_locked.is_err());
+            assert!(after_unlock.is_ok());
+        }
+    }
+}
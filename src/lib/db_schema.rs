@@ -0,0 +1,2 @@
+pub mod from_row;
+pub mod mixxx_db_handle;
@@ -0,0 +1,108 @@
+pub mod from_row {
+    use rusqlite::Row;
+
+    use crate::db_changes::change_plan::change_plan::ColumnValue;
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+
+    /// A value built from one result row of a query over the Mixxx schema.
+    ///
+    /// The columns are named by [`FromRow::select_list`], which selects the ones
+    /// missing from older schemas as `NULL`, so [`FromRow::from_row`] can look
+    /// every column up by name regardless of the Mixxx version.
+    pub trait FromRow: Sized {
+        /// The `SELECT` list the query has to use for [`FromRow::from_row`].
+        fn select_list(db: &MixxxDb) -> String;
+
+        fn from_row(row: &Row) -> rusqlite::Result<Self>;
+    }
+
+    /// `{alias}.{column} AS {column}` for each of `columns` the table has, `NULL AS {column}` for the others.
+    pub fn select_columns(db: &MixxxDb, table: &str, alias: &str, columns: &[&str]) -> Vec<String> {
+        columns
+            .iter()
+            .map(|column| {
+                if db.has_column(table, column) {
+                    format!("{alias}.{column} AS {column}")
+                } else {
+                    format!("NULL AS {column}")
+                }
+            })
+            .collect()
+    }
+
+    /// The column as text. Numbers are formatted, blobs and blank text are `None`.
+    pub fn text(row: &Row, column: &str) -> rusqlite::Result<Option<String>> {
+        Ok(match row.get::<_, ColumnValue>(column)? {
+            ColumnValue::Text(value) if !value.trim().is_empty() => Some(value),
+            ColumnValue::Integer(value) => Some(value.to_string()),
+            ColumnValue::Real(value) => Some(value.to_string()),
+            _ => None,
+        })
+    }
+
+    /// The column as an integer. Text is parsed, values that do not fit `T` are `None`.
+    pub fn integer<T: TryFrom<i64>>(row: &Row, column: &str) -> rusqlite::Result<Option<T>> {
+        let value = match row.get::<_, ColumnValue>(column)? {
+            ColumnValue::Integer(value) => Some(value),
+            ColumnValue::Real(value) if value.fract() == 0.0 => Some(value as i64),
+            ColumnValue::Text(value) => value.trim().parse().ok(),
+            _ => None,
+        };
+        Ok(value.and_then(|value| T::try_from(value).ok()))
+    }
+
+    /// The column as a floating point number. Text is parsed.
+    pub fn real(row: &Row, column: &str) -> rusqlite::Result<Option<f64>> {
+        Ok(match row.get::<_, ColumnValue>(column)? {
+            ColumnValue::Integer(value) => Some(value as f64),
+            ColumnValue::Real(value) => Some(value),
+            ColumnValue::Text(value) => value.trim().parse().ok(),
+            _ => None,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::open_test_mixxx_db;
+
+        #[test]
+        fn conversions_are_lenient() {
+            // setup
+            let db = open_test_mixxx_db();
+
+            // run
+            let values = db
+                .connection()
+                .query_row(
+                    "SELECT '  ' AS blank, 320 AS bitrate, '44100' AS samplerate,
+                            -1 AS negative, '128.5' AS bpm, x'00' AS blob",
+                    (),
+                    |row| {
+                        Ok((
+                            text(row, "blank")?,
+                            text(row, "bitrate")?,
+                            integer::<u32>(row, "samplerate")?,
+                            integer::<u32>(row, "negative")?,
+                            real(row, "bpm")?,
+                            text(row, "blob")?,
+                        ))
+                    },
+                )
+                .unwrap();
+
+            // verify
+            assert_eq!(
+                values,
+                (
+                    None,
+                    Some(String::from("320")),
+                    Some(44100),
+                    None,
+                    Some(128.5),
+                    None
+                )
+            );
+        }
+    }
+}
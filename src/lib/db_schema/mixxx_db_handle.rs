@@ -0,0 +1,229 @@
+pub mod mixxx_db_handle {
+    use std::collections::{HashMap, HashSet};
+
+    use rusqlite::{Connection, OpenFlags};
+
+    use crate::error::error::LibHelperError;
+
+    const SCHEMA_VERSION_SETTING: &str = "mixxx.schema.version";
+
+    /// The oldest schema revision the queries of this library have been checked against.
+    /// Older databases have to be upgraded by opening them with a current Mixxx first.
+    pub const OLDEST_SUPPORTED_SCHEMA_VERSION: u32 = 24;
+
+    /// The newest schema revision the queries of this library have been checked against.
+    /// Newer databases are accepted as long as every column a query needs is present.
+    pub const NEWEST_KNOWN_SCHEMA_VERSION: u32 = 39;
+
+    /// An open Mixxx database together with the schema it was found to have.
+    ///
+    /// Every query of this library states the columns it relies on through
+    /// [`MixxxDb::require_columns`], so a database from a different Mixxx
+    /// version fails with [`LibHelperError::SchemaMismatch`] instead of
+    /// returning wrong results.
+    pub struct MixxxDb {
+        connection: Connection,
+        path: String,
+        schema_version: u32,
+        columns: HashMap<String, HashSet<String>>,
+    }
+
+    impl MixxxDb {
+        /// Opens an existing database; a wrong path fails instead of creating an empty one.
+        pub fn open(path: &str) -> Result<MixxxDb, LibHelperError> {
+            let connection = Connection::open_with_flags(
+                path,
+                OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_URI,
+            )
+            .map_err(|source| LibHelperError::DbOpen {
+                path: path.to_string(),
+                source,
+            })?;
+            MixxxDb::from_connection(connection, path)
+        }
+
+        /// Wraps an already open connection, e.g. an in-memory database.
+        pub fn from_connection(
+            connection: Connection,
+            path: &str,
+        ) -> Result<MixxxDb, LibHelperError> {
+            let schema_version = read_schema_version(&connection)?;
+            if schema_version < OLDEST_SUPPORTED_SCHEMA_VERSION {
+                return Err(LibHelperError::SchemaMismatch(format!(
+                    "schema version {schema_version} is older than the oldest supported version \
+                     {OLDEST_SUPPORTED_SCHEMA_VERSION}, open the database with a current Mixxx to upgrade it"
+                )));
+            }
+
+            let columns = read_columns(&connection)?;
+            Ok(MixxxDb {
+                connection,
+                path: path.to_string(),
+                schema_version,
+                columns,
+            })
+        }
+
+        pub fn path(&self) -> &str {
+            &self.path
+        }
+
+        pub fn schema_version(&self) -> u32 {
+            self.schema_version
+        }
+
+        /// Whether the schema version is one this library has been checked against.
+        pub fn is_known_schema_version(&self) -> bool {
+            self.schema_version <= NEWEST_KNOWN_SCHEMA_VERSION
+        }
+
+        pub fn has_column(&self, table: &str, column: &str) -> bool {
+            self.columns
+                .get(table)
+                .is_some_and(|columns| columns.contains(column))
+        }
+
+        /// Fails with [`LibHelperError::SchemaMismatch`] unless `table` has all of `columns`.
+        pub fn require_columns(&self, table: &str, columns: &[&str]) -> Result<(), LibHelperError> {
+            let Some(existing) = self.columns.get(table) else {
+                return Err(LibHelperError::SchemaMismatch(format!(
+                    "table {table} is missing (schema version {})",
+                    self.schema_version
+                )));
+            };
+
+            let missing: Vec<&str> = columns
+                .iter()
+                .copied()
+                .filter(|column| !existing.contains(*column))
+                .collect();
+            if !missing.is_empty() {
+                return Err(LibHelperError::SchemaMismatch(format!(
+                    "table {table} has no column {} (schema version {})",
+                    missing.join(", "),
+                    self.schema_version
+                )));
+            }
+
+            Ok(())
+        }
+
+        pub fn connection(&self) -> &Connection {
+            &self.connection
+        }
+
+        pub fn connection_mut(&mut self) -> &mut Connection {
+            &mut self.connection
+        }
+    }
+
+    fn read_schema_version(connection: &Connection) -> Result<u32, LibHelperError> {
+        let has_settings: bool = connection.query_row(
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'settings')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_settings {
+            return Err(LibHelperError::SchemaMismatch(String::from(
+                "there is no settings table, this is not a Mixxx database",
+            )));
+        }
+
+        let mut stmt = connection.prepare("SELECT value FROM settings WHERE name = ?1")?;
+        let versions = stmt
+            .query_map([SCHEMA_VERSION_SETTING], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+        let Some(version) = versions.first() else {
+            return Err(LibHelperError::SchemaMismatch(format!(
+                "{SCHEMA_VERSION_SETTING} is not set, this is not a Mixxx database"
+            )));
+        };
+
+        version.trim().parse().map_err(|_| {
+            LibHelperError::SchemaMismatch(format!(
+                "{SCHEMA_VERSION_SETTING} is {version}, expected a number"
+            ))
+        })
+    }
+
+    fn read_columns(
+        connection: &Connection,
+    ) -> Result<HashMap<String, HashSet<String>>, rusqlite::Error> {
+        let mut stmt = connection.prepare(
+            "SELECT m.name, p.name FROM sqlite_master m, pragma_table_info(m.name) p
+             WHERE m.type = 'table'",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get(1)?)))?;
+
+        let mut columns: HashMap<String, HashSet<String>> = HashMap::new();
+        for row in rows {
+            let (table, column) = row?;
+            columns.entry(table).or_default().insert(column);
+        }
+
+        Ok(columns)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::open_test_db;
+
+        #[test]
+        fn from_connection_reads_schema_version_and_columns() {
+            // run
+            let db = MixxxDb::from_connection(open_test_db(), ":memory:").unwrap();
+
+            // verify
+            assert_eq!(db.schema_version(), NEWEST_KNOWN_SCHEMA_VERSION);
+            assert!(db.has_column("track_locations", "fs_deleted"));
+            assert!(db.require_columns("library", &["id", "bpm"]).is_ok());
+            assert!(matches!(
+                db.require_columns("library", &["id", "no_such_column"]),
+                Err(LibHelperError::SchemaMismatch(_))
+            ));
+        }
+
+        #[test]
+        fn open_does_not_create_missing_database() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("mixxxdb.sqlite");
+
+            // run
+            let result = MixxxDb::open(&path.to_string_lossy());
+
+            // verify
+            assert!(matches!(result, Err(LibHelperError::DbOpen { .. })));
+            assert!(!path.exists());
+        }
+
+        #[test]
+        fn from_connection_refuses_old_schema() {
+            // setup
+            let connection = open_test_db();
+            connection
+                .execute(
+                    "UPDATE settings SET value = '7' WHERE name = 'mixxx.schema.version'",
+                    [],
+                )
+                .unwrap();
+
+            // run
+            let result = MixxxDb::from_connection(connection, ":memory:");
+
+            // verify
+            assert!(matches!(result, Err(LibHelperError::SchemaMismatch(_))));
+        }
+
+        #[test]
+        fn from_connection_refuses_database_without_settings() {
+            // run
+            let result =
+                MixxxDb::from_connection(Connection::open_in_memory().unwrap(), ":memory:");
+
+            // verify
+            assert!(matches!(result, Err(LibHelperError::SchemaMismatch(_))));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+pub mod error {
+    use std::fmt;
+
+    /// Everything that can go wrong in this library.
+    #[derive(Debug)]
+    #[non_exhaustive]
+    pub enum LibHelperError {
+        /// The database file could not be opened.
+        DbOpen {
+            path: String,
+            source: rusqlite::Error,
+        },
+        /// The database does not have the tables or columns an operation needs.
+        SchemaMismatch(String),
+        /// A query or update failed.
+        Db(rusqlite::Error),
+        /// The tags of a file could not be read.
+        TagRead {
+            path: String,
+            message: String,
+        },
+        /// The tags of a file could not be written.
+        TagWrite {
+            path: String,
+            message: String,
+        },
+        Io(std::io::Error),
+        /// A change plan could not be read, written or applied as planned.
+        Plan(String),
+        /// A file could not be decoded for audio analysis.
+        AudioDecode {
+            path: String,
+            message: String,
+        },
+        /// The audio data of a file could not be found for fingerprinting.
+        Fingerprint {
+            path: String,
+            message: String,
+        },
+        /// The serialized beats of a track could not be read.
+        InvalidBeats(String),
+        /// A configuration file such as a rule set is invalid.
+        Config(String),
+        /// A regular expression, e.g. from a configuration file, is invalid.
+        InvalidPattern(regex::Error),
+        /// The user declined to continue.
+        UserAbort,
+        /// Mixxx is running or another program holds the database lock.
+        MixxxRunning(String),
+    }
+
+    impl fmt::Display for LibHelperError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                LibHelperError::DbOpen { path, source } => {
+                    write!(f, "Could not open database {path}: {source}")
+                }
+                LibHelperError::SchemaMismatch(message) => {
+                    write!(f, "Unexpected database schema: {message}")
+                }
+                LibHelperError::Db(source) => write!(f, "Database error: {source}"),
+                LibHelperError::TagRead { path, message } => {
+                    write!(f, "Could not read tags of {path}: {message}")
+                }
+                LibHelperError::TagWrite { path, message } => {
+                    write!(f, "Could not write tags to {path}: {message}")
+                }
+                LibHelperError::Io(source) => write!(f, "{source}"),
+                LibHelperError::Plan(message) => write!(f, "{message}"),
+                LibHelperError::AudioDecode { path, message } => {
+                    write!(f, "Could not decode {path}: {message}")
+                }
+                LibHelperError::Fingerprint { path, message } => {
+                    write!(f, "Could not fingerprint {path}: {message}")
+                }
+                LibHelperError::InvalidBeats(message) => {
+                    write!(f, "Could not read beats: {message}")
+                }
+                LibHelperError::Config(message) => write!(f, "Invalid configuration: {message}"),
+                LibHelperError::InvalidPattern(source) => write!(f, "Invalid pattern: {source}"),
+                LibHelperError::UserAbort => write!(f, "Aborted"),
+                LibHelperError::MixxxRunning(message) => write!(f, "{message}"),
+            }
+        }
+    }
+
+    impl std::error::Error for LibHelperError {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            match self {
+                LibHelperError::DbOpen { source, .. } => Some(source),
+                LibHelperError::Db(source) => Some(source),
+                LibHelperError::Io(source) => Some(source),
+                LibHelperError::InvalidPattern(source) => Some(source),
+                _ => None,
+            }
+        }
+    }
+
+    impl From<rusqlite::Error> for LibHelperError {
+        fn from(err: rusqlite::Error) -> Self {
+            LibHelperError::Db(err)
+        }
+    }
+
+    impl From<std::io::Error> for LibHelperError {
+        fn from(err: std::io::Error) -> Self {
+            LibHelperError::Io(err)
+        }
+    }
+
+    impl From<serde_json::Error> for LibHelperError {
+        fn from(err: serde_json::Error) -> Self {
+            LibHelperError::Plan(format!("Invalid plan file: {err}"))
+        }
+    }
+
+    impl From<regex::Error> for LibHelperError {
+        fn from(err: regex::Error) -> Self {
+            LibHelperError::InvalidPattern(err)
+        }
+    }
+}
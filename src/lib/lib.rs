@@ -1,13 +1,27 @@
+mod bpm_correction;
+mod db_changes;
+mod db_safety;
+mod db_schema;
+mod error;
 pub mod logfile_anonymize;
+#[cfg(test)]
+mod test_db;
 mod track;
+mod track_analysis;
+mod track_beats;
 mod track_categorization;
+mod track_duplicates;
 mod track_fingerprinting;
 mod track_movement;
+mod track_selection;
+mod track_sync;
+
+pub use crate::error::error::LibHelperError;
 
 pub mod mixxx_logfile {
-    use crate::logfile_anonymize::logfile_anonymize::anonymize;
+    use crate::{error::error::LibHelperError, logfile_anonymize::logfile_anonymize::anonymize};
 
-    pub fn anonymize_logfile(path: &str) -> Result<String, Box<dyn std::error::Error>> {
+    pub fn anonymize_logfile(path: &str) -> Result<String, LibHelperError> {
         let file_path = std::path::Path::new(&path);
         let file_as_string = std::fs::read_to_string(file_path)?;
 
@@ -16,180 +30,1175 @@ pub mod mixxx_logfile {
     }
 }
 
+pub mod mixxx_settings {
+    use std::path::PathBuf;
+
+    const DB_FILE_NAME: &str = "mixxxdb.sqlite";
+    const SETTINGS_PATH_VARIABLE: &str = "MIXXX_SETTINGS_PATH";
+    const MACOS_SANDBOX_DIR: &str =
+        "Library/Containers/org.mixxx.mixxx/Data/Library/Application Support/Mixxx";
+    const MACOS_DIR: &str = "Library/Application Support/Mixxx";
+
+    /// The directory Mixxx keeps its settings and library database in.
+    /// `$MIXXX_SETTINGS_PATH` takes precedence over the platform default.
+    pub fn settings_dir() -> Option<PathBuf> {
+        settings_dir_from(|name| std::env::var(name).ok())
+    }
+
+    pub fn default_db_path() -> Option<PathBuf> {
+        settings_dir().map(|dir| dir.join(DB_FILE_NAME))
+    }
+
+    fn settings_dir_from(env: impl Fn(&str) -> Option<String>) -> Option<PathBuf> {
+        if let Some(settings_path) = env(SETTINGS_PATH_VARIABLE).filter(|path| !path.is_empty()) {
+            return Some(PathBuf::from(settings_path));
+        }
+
+        if cfg!(target_os = "windows") {
+            env("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("Mixxx"))
+        } else if cfg!(target_os = "macos") {
+            let home = PathBuf::from(env("HOME")?);
+            let sandbox_dir = home.join(MACOS_SANDBOX_DIR);
+            if sandbox_dir.is_dir() {
+                Some(sandbox_dir)
+            } else {
+                Some(home.join(MACOS_DIR))
+            }
+        } else {
+            env("HOME").map(|home| PathBuf::from(home).join(".mixxx"))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn settings_dir_prefers_environment_variable() {
+            // run
+            let settings_dir = settings_dir_from(|name| match name {
+                "MIXXX_SETTINGS_PATH" => Some(String::from("/custom/mixxx")),
+                _ => Some(String::from("/home/dj")),
+            });
+
+            // verify
+            assert_eq!(settings_dir, Some(PathBuf::from("/custom/mixxx")));
+        }
+
+        #[cfg(target_os = "linux")]
+        #[test]
+        fn settings_dir_defaults_to_dot_mixxx_in_home() {
+            // run
+            let settings_dir = settings_dir_from(|name| match name {
+                "HOME" => Some(String::from("/home/dj")),
+                _ => None,
+            });
+
+            // verify
+            assert_eq!(settings_dir, Some(PathBuf::from("/home/dj/.mixxx")));
+        }
+    }
+}
+
 pub mod mixxx_db {
+    use std::collections::{HashMap, HashSet};
     use std::io::{stdin, stdout, Write};
+    use std::time::{Duration, Instant};
 
-    use id3::{Tag, TagLike};
-    use std::path::Path;
+    use std::path::{Path, PathBuf};
 
+    pub use crate::bpm_correction::bpm_rules::bpm_rules::{
+        BpmRule, BpmRuleSet, FixBpmOptions, GenreMatcher, Multiplier, DEFAULT_MIN_AUDIO_CONFIDENCE,
+    };
+    pub use crate::db_changes::change_plan::change_plan::{
+        ChangePlan, WriteOptions, DEFAULT_KEEP_BACKUPS,
+    };
+    pub use crate::db_safety::backup::backup::BackupInfo;
+    pub use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+    pub use crate::track::metadata::metadata::TagField;
+    pub use crate::track::track::track::{CoverArt, Track};
+    pub use crate::track::track_query::track_query::TrackQuery;
+    pub use crate::track_categorization::{
+        genre::genre::is_edm,
+        taxonomy::taxonomy::{GenreNode, GenreTaxonomy},
+    };
+    pub use crate::track_duplicates::{
+        duplicates::duplicates::{DuplicateMatch, FUZZY_DURATION_TOLERANCE_SECONDS},
+        track_merge::track_merge::MergePolicy,
+    };
+    pub use crate::track_fingerprinting::{
+        acoustic::acoustic::SAME_RECORDING_SIMILARITY,
+        fingerprint_cache::fingerprint_cache::{CacheStats, FingerprintCache},
+        fingerprint_pool::fingerprint_pool::PoolOptions,
+        read_control::read_control::CancelToken,
+        track_fingerprinting::track_fingerprinting::{
+            get_track_fingerprint, get_track_fingerprints_for_file, Fingerprint,
+            FingerprintAlgorithm,
+        },
+    };
+    pub use crate::track_selection::selection::selection::TrackSelection;
+    pub use crate::track_sync::tag_sync::tag_sync::{SyncDirection, SyncOptions};
     use crate::{
-        track::track::track::Track,
-        track_categorization::genre::genre::is_edm,
-        track_fingerprinting::track_fingerprinting::track_fingerprinting::{
-            get_track_fingerprints_for_file, Fingerprint,
+        db_changes::change_plan::change_plan::{
+            apply_plan, plan_track_change, ChangeTable, ColumnValue,
+        },
+        db_safety::{
+            backup::backup::{create_backup, default_backup_dir, list_backups, restore_backup},
+            mixxx_guard::mixxx_guard::ensure_mixxx_not_running,
+        },
+        error::error::LibHelperError,
+        track::{
+            file_type::file_type::{detect_format, find_filetype_mismatches},
+            metadata::metadata::format_bpm,
+            tag_report::tag_report::TagReadReport,
+            tags::tags::{keep_file_times, supports_tag_writing, write_fields},
+        },
+        track_analysis::{
+            audio_decode::audio_decode::{decode_mono, ANALYSIS_SAMPLE_RATE},
+            tempo::tempo::OnsetEnvelope,
+        },
+        track_beats::beat_grid::beat_grid::plan_beats_change,
+        track_categorization::normalization::normalization::{
+            find_genre_mappings, plan_genre_normalization,
+        },
+        track_duplicates::{
+            duplicates::duplicates::{find_duplicates, plan_merges, DuplicateGroup},
+            track_merge::track_merge::plan_track_merge,
+        },
+        track_fingerprinting::{
+            fingerprint_cache::fingerprint_cache::FileStamp,
+            fingerprint_pool::fingerprint_pool::{
+                find_fingerprint, fingerprint_files, FingerprintJob, Progress,
+            },
         },
+        track_movement::{
+            move_fns::movefns::{plan_move_folder, remove_empty_subdirectories},
+            relocation::relocation::{find_relocations, plan_relocations},
+        },
+        track_selection::selection::selection::select_tracks,
+        track_sync::tag_sync::tag_sync::{find_differences, plan_sync_to_db, plan_tag_writes},
     };
 
-    pub fn relocate_tracks(mixxx_db_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let connection = get_connection(&mixxx_db_path);
+    /// How much of each track is decoded to verify a BPM correction.
+    const AUDIO_ANALYSIS_SECONDS: u32 = 60;
 
-        let mut stmt = connection.prepare(
-            "SELECT l.id, l.bpm, l.genre, tl.location FROM library l
-             INNER JOIN track_locations tl
-             ON tl.id = l.location
-             WHERE l.bpm IS NOT NULL
-             AND l.bpm > ?1;",
-        )?;
+    /// How many fingerprinted files are written to the cache at once.
+    const FINGERPRINT_WRITE_BATCH: usize = 256;
 
-        let rows = stmt.query_map([], |row| {
-            Ok(Track {
-                id: row.get(0)?,
-                bpm: row.get(1)?,
-                genre: row.get(2)?,
-                location: row.get(3)?,
-                id3: None,
-            })
-        })?;
+    /// How often the progress of long runs is updated.
+    const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+    /// Points missing tracks at their files found below `search_roots`. Candidates
+    /// are fingerprinted through the cache at `cache_path`, so later runs only
+    /// read files that changed.
+    pub fn relocate_tracks(
+        mixxx_db_path: &str,
+        search_roots: &[&str],
+        cache_path: &Path,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let cache = FingerprintCache::open(cache_path)?;
+        let report = find_relocations(&db, search_roots, &cache)?;
+
+        for track in &report.not_found {
+            println!(
+                "No candidate found for track {}: {}",
+                track.id, track.location
+            );
+        }
+        for (track, candidates) in &report.ambiguous {
+            println!(
+                "Skipping {} - several different files match:",
+                track.location
+            );
+            for candidate in candidates {
+                println!("    {}", candidate.display());
+            }
+        }
+
+        let plan = plan_relocations(&db, &report.relocations)?;
+        if plan.is_empty() {
+            println!("Found no missing tracks to relocate");
+            return Ok(());
+        }
+
+        println!("Will relocate the following tracks");
+        execute_plan(&mut db, &plan, options)?;
+
+        Ok(())
+    }
+
+    pub fn move_tracks(
+        mixxx_db_path: &str,
+        source_path: &str,
+        target_path: &str,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let plan = plan_move_folder(&db, source_path, target_path)?;
+
+        println!("Will move the following files");
+        if execute_plan(&mut db, &plan, options)? {
+            remove_empty_subdirectories(Path::new(source_path));
+            println!(
+                "Moved {} files and updated {} database values",
+                plan.file_moves.len(),
+                plan.changes.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn fix_edm_bpm(mixxx_db_path: &str, options: &WriteOptions) -> Result<(), LibHelperError> {
+        fix_bpm(
+            mixxx_db_path,
+            &BpmRuleSet::edm_default(),
+            &FixBpmOptions::default(),
+            options,
+        )
+    }
+
+    /// Corrects the BPM of every track a rule matches. The matches of each rule
+    /// are shown separately and, unless `options.assume_yes` is set, confirmed
+    /// one rule at a time before all confirmed changes are applied together.
+    /// Tracks with a locked BPM are left alone unless `bpm_options.include_locked` is set.
+    /// With `bpm_options.verify_audio`, only tracks whose audio agrees are corrected,
+    /// and with `bpm_options.write_tags` the new BPMs are written to the files as well.
+    pub fn fix_bpm(
+        mixxx_db_path: &str,
+        rule_set: &BpmRuleSet,
+        bpm_options: &FixBpmOptions,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let mut tag_report = TagReadReport::default();
+        let rule_plans = plan_bpm_rules(&db, rule_set, bpm_options, &mut tag_report)?;
+        let result = execute_rule_plans(&mut db, &rule_plans, options);
+
+        if !tag_report.problems.is_empty() {
+            println!("{tag_report}");
+        }
+        if let Some(report_path) = &bpm_options.tag_report {
+            tag_report.save(report_path)?;
+            println!("Saved the tag report to {report_path}");
+        }
+
+        if let Some(plan) = result? {
+            if bpm_options.write_tags {
+                write_tag_values(&plan_tag_values(&plan), bpm_options.preserve_mtime);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Locks or unlocks the BPM of the selected tracks. Mixxx does not re-analyze
+    /// tracks with a locked BPM, and [`fix_bpm`] skips them by default.
+    pub fn set_bpm_lock(
+        mixxx_db_path: &str,
+        selection: &TrackSelection,
+        locked: bool,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let tracks = select_tracks(&db, selection)?;
+
+        let mut plan = ChangePlan::default();
+        for track in &tracks {
+            let change = plan_track_change(
+                &db,
+                ChangeTable::Library,
+                track.id,
+                "bpm_lock",
+                ColumnValue::Integer(i64::from(locked)),
+            )?;
+            // a missing flag already means unlocked
+            plan.changes
+                .extend(change.filter(|change| locked || change.old_value != ColumnValue::Null));
+        }
+
+        let action = if locked { "lock" } else { "unlock" };
+        if plan.is_empty() {
+            println!("Nothing to {action}, {} tracks selected", tracks.len());
+            return Ok(());
+        }
+
+        println!("Will {action} the BPM of the following tracks");
+        execute_plan(&mut db, &plan, options)?;
+
+        Ok(())
+    }
+
+    /// Rewrites every genre of the library the taxonomy knows to its canonical
+    /// name, e.g. `trance` and `Trance (Main Floor)` to `Trance`. The mappings
+    /// are shown grouped by canonical name before anything is written. With
+    /// `write_tags`, the new genres are also written to the tags of the files.
+    pub fn normalize_genres(
+        mixxx_db_path: &str,
+        taxonomy: &GenreTaxonomy,
+        write_tags: bool,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let normalization = find_genre_mappings(&db, taxonomy)?;
+
+        if !normalization.unclassified.is_empty() {
+            println!(
+                "Leaving {} genres the taxonomy does not know alone:",
+                normalization.unclassified.len()
+            );
+            for (genre, track_count) in &normalization.unclassified {
+                println!("    {genre:?} ({track_count} tracks)");
+            }
+        }
+
+        let plan = plan_genre_normalization(&db, &normalization.mappings)?;
+        if plan.is_empty() {
+            println!("All known genres are already canonical");
+            return Ok(());
+        }
+
+        let mut current_group = None;
+        for mapping in &normalization.mappings {
+            if current_group != Some(&mapping.to) {
+                let group_count: usize = normalization
+                    .mappings
+                    .iter()
+                    .filter(|other| other.to == mapping.to)
+                    .map(|other| other.track_ids.len())
+                    .sum();
+                println!("{} ({group_count} tracks)", mapping.to);
+                current_group = Some(&mapping.to);
+            }
+            println!(
+                "    {:?} ({} tracks)",
+                mapping.from,
+                mapping.track_ids.len()
+            );
+        }
+
+        println!("Will change the genre of the following tracks");
+        if execute_plan(&mut db, &plan, options)? && write_tags {
+            write_tag_values(&plan_tag_values(&plan), false);
+        }
+
+        Ok(())
+    }
+
+    /// Compares artist, title, album, genre, BPM, key, comment and year in the
+    /// library with the tags of the files and brings both in line, in the
+    /// direction `sync_options` sets for each field. All differences are shown,
+    /// those that cannot be resolved are left alone. The tags are written after
+    /// the library changes were applied, or confirmed if there are none.
+    pub fn sync_tags(
+        mixxx_db_path: &str,
+        sync_options: &SyncOptions,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let mut tag_report = TagReadReport::default();
+        let differences = find_differences(&db, sync_options, &mut tag_report)?;
+        if !tag_report.problems.is_empty() {
+            println!("{tag_report}");
+        }
+
+        let mut current_track = None;
+        for difference in &differences {
+            if current_track != Some(difference.track_id) {
+                println!("track {}\t{}", difference.track_id, difference.location);
+                current_track = Some(difference.track_id);
+            }
+            println!("    {difference}");
+        }
+
+        let plan = plan_sync_to_db(&db, &differences)?;
+        let tag_writes = plan_tag_writes(&differences);
+        if plan.is_empty() && tag_writes.is_empty() {
+            println!(
+                "Nothing to synchronize, {} differences left alone",
+                differences.len()
+            );
+            return Ok(());
+        }
+
+        if !tag_writes.is_empty() {
+            println!("Will write the tags of {} files", tag_writes.len());
+            if options.plan_out.is_some() {
+                println!("Tag changes are not saved with the plan");
+            }
+        }
+        let write_files = if plan.is_empty() {
+            // nothing to apply, so confirm the tag writes on their own
+            if options.dry_run || options.plan_out.is_some() {
+                false
+            } else if options.assume_yes {
+                true
+            } else {
+                println!("Continue? y/n");
+                if !read_confirmation()? {
+                    return Err(LibHelperError::UserAbort);
+                }
+                true
+            }
+        } else {
+            println!("Will change the following tracks");
+            execute_plan(&mut db, &plan, options)?
+        };
+
+        if write_files {
+            write_tag_values(&tag_writes, false);
+        }
+
+        Ok(())
+    }
+
+    /// The `library` values a plan sets that are also tag fields, such as genre
+    /// and BPM, grouped by file.
+    fn plan_tag_values(plan: &ChangePlan) -> Vec<(String, Vec<(TagField, String)>)> {
+        let mut tag_values: Vec<(String, Vec<(TagField, String)>)> = vec![];
+        for change in &plan.changes {
+            if change.table != ChangeTable::Library {
+                continue;
+            }
+            let Ok(field) = change.column.parse::<TagField>() else {
+                continue;
+            };
+            let value = match &change.new_value {
+                ColumnValue::Text(value) => value.clone(),
+                ColumnValue::Real(bpm) if field == TagField::Bpm => format_bpm(*bpm),
+                _ => continue,
+            };
+            match tag_values.last_mut() {
+                Some((location, fields)) if *location == change.location => {
+                    fields.push((field, value))
+                }
+                _ => tag_values.push((change.location.clone(), vec![(field, value)])),
+            }
+        }
+
+        tag_values
+    }
 
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(row.unwrap());
+    /// Writes the fields of each file, reporting the files that could not be
+    /// written instead of failing. With `keep_times`, the files keep their timestamps.
+    fn write_tag_values(tag_values: &[(String, Vec<(TagField, String)>)], keep_times: bool) {
+        let mut written = 0;
+        let mut unsupported = 0;
+        for (location, fields) in tag_values {
+            let path = Path::new(location);
+            if !supports_tag_writing(path) {
+                unsupported += 1;
+                continue;
+            }
+            let result = if keep_times {
+                keep_file_times(path, || write_fields(path, fields))
+            } else {
+                write_fields(path, fields)
+            };
+            match result {
+                Ok(()) => written += 1,
+                Err(err) => println!("Skipping track {location}: {err}"),
+            }
         }
 
-        // iterate and collect locations
-        // TODO complete implementation
-        let mut _track_locations: Vec<Vec<Fingerprint>> = tracks
+        println!("Wrote the tags of {written} files");
+        if unsupported > 0 {
+            println!("Left the tags of {unsupported} files alone, their format is not supported");
+        }
+    }
+
+    /// Lists the tracks whose `library.filetype` disagrees with the content of
+    /// their files, such as FLAC files named `.mp3`. Nothing is changed.
+    pub fn check_filetypes(mixxx_db_path: &str) -> Result<(), LibHelperError> {
+        let db = open_db(mixxx_db_path)?;
+        let mismatches = find_filetype_mismatches(&db)?;
+        if mismatches.is_empty() {
+            println!("The file types of all tracks match their content");
+            return Ok(());
+        }
+
+        for mismatch in &mismatches {
+            if let Some(read_error) = &mismatch.read_error {
+                println!(
+                    "track {}\t{}\tcould not be read: {read_error}",
+                    mismatch.id, mismatch.location
+                );
+                continue;
+            }
+            let detected = mismatch
+                .detected
+                .map(|format| format.to_string())
+                .unwrap_or(String::from("no known audio format"));
+            println!(
+                "track {}\t{}\tfiletype {:?}, content is {detected}",
+                mismatch.id, mismatch.location, mismatch.filetype
+            );
+        }
+        let unreadable = mismatches
             .iter()
-            .cloned()
-            .map(|track| track.location)
-            .map(|location| get_track_fingerprints_for_file(&location).unwrap())
-            .collect();
+            .filter(|mismatch| mismatch.read_error.is_some())
+            .count();
+        println!(
+            "Found {} tracks whose file type does not match their content, {unreadable} could not be read",
+            mismatches.len() - unreadable
+        );
+
+        Ok(())
+    }
+
+    /// Fingerprints the files of the selected tracks, or of all tracks for an empty
+    /// selection, and stores the fingerprints in the cache at `cache_path`.
+    /// Files that did not change since an earlier run are not read again, the
+    /// others are fingerprinted in parallel as `pool_options` say.
+    pub fn index_fingerprints(
+        mixxx_db_path: &str,
+        cache_path: &Path,
+        algorithms: &[FingerprintAlgorithm],
+        selection: &TrackSelection,
+        pool_options: &PoolOptions,
+    ) -> Result<(), LibHelperError> {
+        let db = open_db(mixxx_db_path)?;
+        let locations: Vec<String> = if selection.is_empty() {
+            TrackQuery::new()
+                .load(&db)?
+                .into_iter()
+                .map(|track| track.location)
+                .collect()
+        } else {
+            select_tracks(&db, selection)?
+                .into_iter()
+                .map(|track| track.location)
+                .collect()
+        };
+        let cache = FingerprintCache::open(cache_path)?;
+        fingerprint_into_cache(&cache, &locations, algorithms, pool_options)
+    }
+
+    /// Computes the fingerprints of the files at `locations` that are not cached yet,
+    /// showing the progress and a summary.
+    fn fingerprint_into_cache(
+        cache: &FingerprintCache,
+        locations: &[String],
+        algorithms: &[FingerprintAlgorithm],
+        pool_options: &PoolOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut jobs = vec![];
+        let mut stamps: HashMap<PathBuf, FileStamp> = HashMap::new();
+        let mut missing = 0;
+        for location in locations {
+            let path = Path::new(location);
+            let Ok(stamp) = FileStamp::of(path) else {
+                missing += 1;
+                continue;
+            };
+            let mut outdated = vec![];
+            for algorithm in algorithms {
+                if cache.get(path, algorithm, &stamp)?.is_none() {
+                    outdated.push(algorithm.clone());
+                }
+            }
+            if !outdated.is_empty() {
+                stamps.insert(path.to_path_buf(), stamp);
+                jobs.push(FingerprintJob {
+                    path: path.to_path_buf(),
+                    algorithms: outdated,
+                });
+            }
+        }
+        let unchanged = locations.len() - missing - jobs.len();
+
+        // results are written in batches, a transaction per file would be slow
+        let mut pending: Vec<(FingerprintJob, Vec<Fingerprint>)> = vec![];
+        let write_pending = |pending: &mut Vec<(FingerprintJob, Vec<Fingerprint>)>| {
+            cache.batch(|cache| {
+                for (job, fingerprints) in pending.drain(..) {
+                    for algorithm in &job.algorithms {
+                        let fingerprint = find_fingerprint(&fingerprints, algorithm);
+                        cache.put(&job.path, algorithm, &stamps[&job.path], fingerprint)?;
+                    }
+                }
+                Ok(())
+            })
+        };
+        let (mut fingerprinted, mut failed) = (0, 0);
+        let mut write_result = Ok(());
+        let mut last_report = Instant::now();
+        fingerprint_files(&jobs, pool_options, |job, result, progress| {
+            match result {
+                Ok(fingerprints) => {
+                    fingerprinted += 1;
+                    pending.push((job.clone(), fingerprints));
+                }
+                Err(err) => {
+                    println!("\r{err}");
+                    failed += 1;
+                }
+            }
+            if pending.len() >= FINGERPRINT_WRITE_BATCH && write_result.is_ok() {
+                write_result = write_pending(&mut pending);
+            }
+            if last_report.elapsed() >= PROGRESS_INTERVAL || progress.done == progress.total {
+                print_progress(progress);
+                last_report = Instant::now();
+            }
+        });
+        write_result?;
+        write_pending(&mut pending)?;
+        if !jobs.is_empty() {
+            println!();
+        }
 
+        let not_done = jobs.len() - fingerprinted - failed;
+        println!(
+            "Fingerprinted {} tracks: {fingerprinted} read, {unchanged} unchanged, {failed} failed, \
+             {missing} files missing",
+            locations.len()
+        );
+        if not_done > 0 {
+            println!("Cancelled before {not_done} files were read");
+        }
         Ok(())
     }
 
-    pub fn fix_edm_bpm(mixxx_db_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let edm_tracks_low_bpm = find_edm_tracks_with_low_bpm(&mixxx_db_path)?;
-        let track_locations: Vec<String> = edm_tracks_low_bpm
+    fn print_progress(progress: &Progress) {
+        print!(
+            "\rRead {} of {} files, {} MiB",
+            progress.done,
+            progress.total,
+            progress.bytes_read / (1024 * 1024)
+        );
+        let _ = stdout().flush();
+    }
+
+    /// Lists the tracks of the library that are copies of each other, found by
+    /// the given matches, with their cues, plays and bitrate. The fingerprints
+    /// come from the cache at `cache_path`, which is brought up to date first.
+    ///
+    /// With a `merge` policy, the copies of each group are merged into the one listed
+    /// first as [`merge_tracks`] does. Their files stay where they are.
+    pub fn find_duplicate_tracks(
+        mixxx_db_path: &str,
+        cache_path: &Path,
+        matches: &[DuplicateMatch],
+        merge: Option<&MergePolicy>,
+        pool_options: &PoolOptions,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let tracks = TrackQuery::new().load(&db)?;
+
+        let algorithms: Vec<FingerprintAlgorithm> = matches
             .iter()
-            .cloned()
-            .map(|track| track.location)
+            .filter_map(|duplicate_match| duplicate_match.algorithm())
             .collect();
+        let mut fingerprints: HashMap<String, Vec<Fingerprint>> = HashMap::new();
+        if !algorithms.is_empty() {
+            let cache = FingerprintCache::open(cache_path)?;
+            let locations: Vec<String> =
+                tracks.iter().map(|track| track.location.clone()).collect();
+            fingerprint_into_cache(&cache, &locations, &algorithms, pool_options)?;
+            for location in locations {
+                let path = Path::new(&location);
+                let Ok(stamp) = FileStamp::of(path) else {
+                    continue;
+                };
+                let mut file_fingerprints = vec![];
+                for algorithm in &algorithms {
+                    if let Some(Some(fingerprint)) = cache.get(path, algorithm, &stamp)? {
+                        file_fingerprints.push(fingerprint);
+                    }
+                }
+                fingerprints.insert(location, file_fingerprints);
+            }
+        }
 
-        println!("Will convert BPM to 3/2*BPM for the following tracks - continue? y/n");
-        for location in track_locations {
-            println!("{location}");
+        let groups = find_duplicates(&db, tracks, &fingerprints, matches)?;
+        if groups.is_empty() {
+            println!("Found no duplicate tracks");
+            return Ok(());
+        }
+        for group in &groups {
+            print_duplicate_group(group);
+        }
+        let copy_count: usize = groups.iter().map(|group| group.copies.len() - 1).sum();
+        println!(
+            "Found {} tracks with {copy_count} extra copies, the copy marked * has the most cues, \
+             plays and the highest bitrate",
+            groups.len()
+        );
+        let Some(policy) = merge else {
+            return Ok(());
+        };
+
+        let plan = plan_merges(&db, &groups, policy)?;
+        println!("Will merge the copies into the one marked *");
+        if execute_plan(&mut db, &plan, options)? {
+            println!(
+                "Merged {copy_count} copies, their files were not removed and can be deleted by hand"
+            );
         }
 
-        let mut confirmation: String = String::new();
-        let _ = stdout().flush();
-        stdin()
-            .read_line(&mut confirmation)
-            .expect("Did not read a string");
-        if confirmation.eq("y\n") {
-            multiply_bpm(&edm_tracks_low_bpm, 3.0 / 2.0, &mixxx_db_path)?;
+        Ok(())
+    }
+
+    /// Merges the tracks `drop_ids` into `keep_id`, which represent the same recording:
+    /// crates, playlists, the history and cues of the dropped tracks move to the kept
+    /// one, play counts and ratings are combined as `policy` says, and the dropped
+    /// tracks are marked deleted, all in one transaction.
+    pub fn merge_tracks(
+        mixxx_db_path: &str,
+        keep_id: usize,
+        drop_ids: &[usize],
+        policy: &MergePolicy,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let mut db = open_db(mixxx_db_path)?;
+        let plan = plan_track_merge(&db, keep_id, drop_ids, policy)?;
+
+        println!("Will merge the following tracks");
+        if execute_plan(&mut db, &plan, options)? {
+            println!("Merged {} tracks into track {keep_id}", drop_ids.len());
+        }
+
+        Ok(())
+    }
+
+    fn print_duplicate_group(group: &DuplicateGroup) {
+        println!("{} copies ({} match)", group.copies.len(), group.matched_by);
+        for (index, copy) in group.copies.iter().enumerate() {
+            let marker = if index == 0 { "*" } else { " " };
+            println!(
+                "  {marker} track {}\t{} cues\t{} plays\t{} kbit/s\t{}",
+                copy.track.id,
+                copy.cue_count,
+                copy.plays(),
+                copy.bitrate(),
+                copy.track.location
+            );
+        }
+    }
+
+    /// Prints how many fingerprints the cache holds and how many files changed since.
+    pub fn print_fingerprint_stats(cache_path: &Path) -> Result<(), LibHelperError> {
+        if !cache_path.is_file() {
+            println!(
+                "No fingerprint cache at {}, create it with fingerprint index",
+                cache_path.display()
+            );
+            return Ok(());
+        }
+        let stats = FingerprintCache::open(cache_path)?.stats()?;
+
+        println!(
+            "Fingerprint cache {} ({} KiB)",
+            cache_path.display(),
+            stats.size / 1024
+        );
+        println!(
+            "{} files, {} of them changed or missing since they were fingerprinted",
+            stats.files, stats.stale_files
+        );
+        for (algorithm, count) in &stats.entries {
+            println!("    {algorithm}: {count}");
+        }
+        Ok(())
+    }
+
+    /// Applies a plan previously saved with [`WriteOptions::plan_out`].
+    pub fn apply_plan_file(
+        mixxx_db_path: &str,
+        plan_path: &str,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let plan = ChangePlan::load(plan_path)?;
+        let mut db = open_db(mixxx_db_path)?;
+
+        print!("{plan}");
+        apply_with_safeguards(&mut db, &plan, options)?;
+
+        Ok(())
+    }
+
+    /// Lists the backups taken before earlier writes, oldest first.
+    pub fn get_backups(
+        mixxx_db_path: &str,
+        options: &WriteOptions,
+    ) -> Result<Vec<BackupInfo>, LibHelperError> {
+        Ok(list_backups(&backup_dir(mixxx_db_path, options))?)
+    }
+
+    /// Restores the backup with the given number in [`get_backups`], counting from 1.
+    /// Without a number, lists the backups and asks which one to restore.
+    /// The current state is backed up first, so a restore can be undone as well.
+    pub fn restore_database(
+        mixxx_db_path: &str,
+        backup_number: Option<usize>,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        let backups = get_backups(mixxx_db_path, options)?;
+        if backups.is_empty() {
+            println!("No backups found");
+            return Ok(());
         }
 
+        let backup_number = match backup_number {
+            Some(backup_number) => backup_number,
+            None => {
+                for (index, backup) in backups.iter().enumerate() {
+                    let track_count = backup
+                        .track_count
+                        .map(|count| count.to_string())
+                        .unwrap_or(String::from("?"));
+                    println!(
+                        "{}: {} UTC, {track_count} tracks ({})",
+                        index + 1,
+                        backup.created,
+                        backup.path.display()
+                    );
+                }
+
+                println!("Which backup should be restored? Leave empty to abort");
+                let mut selection = String::new();
+                let _ = stdout().flush();
+                stdin().read_line(&mut selection)?;
+                match selection.trim().parse::<usize>() {
+                    Ok(backup_number) => backup_number,
+                    Err(_) => return Err(LibHelperError::UserAbort),
+                }
+            }
+        };
+        let backup = backups.get(backup_number.wrapping_sub(1)).ok_or_else(|| {
+            LibHelperError::Plan(format!("There is no backup number {backup_number}"))
+        })?;
+
+        ensure_mixxx_not_running(mixxx_db_path, options.wait)?;
+        let mut db = open_db(mixxx_db_path)?;
+        backup_database(&db, options)?;
+        restore_backup(db.connection_mut(), &backup.path)?;
+        println!("Restored {}", backup.path.display());
+
         Ok(())
     }
 
-    fn multiply_bpm(
-        tracks: &Vec<Track>,
-        multiplier: f64,
-        db_path: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let connection = get_connection(&db_path);
+    /// Shows the plan and, depending on `options`, saves it, stops after showing it,
+    /// or applies it once the user confirms. Before applying, it makes sure Mixxx
+    /// is not running and backs up the database.
+    /// Returns whether the plan was applied, or [`LibHelperError::UserAbort`]
+    /// if the user declined.
+    fn execute_plan(
+        db: &mut MixxxDb,
+        plan: &ChangePlan,
+        options: &WriteOptions,
+    ) -> Result<bool, LibHelperError> {
+        print!("{plan}");
+
+        if let Some(plan_out) = &options.plan_out {
+            plan.save(plan_out)?;
+            println!("Saved plan to {plan_out}");
+            return Ok(false);
+        }
+        if options.dry_run || plan.is_empty() {
+            return Ok(false);
+        }
+
+        if !options.assume_yes {
+            println!("Continue? y/n");
+            if !read_confirmation()? {
+                return Err(LibHelperError::UserAbort);
+            }
+        }
+
+        apply_with_safeguards(db, plan, options)?;
+        Ok(true)
+    }
+
+    /// Like [`execute_plan`] for several rules at once: shows the changes of each
+    /// rule and asks for each whether to include them, then saves or applies the
+    /// included changes as one plan. Returns the plan if it was applied.
+    fn execute_rule_plans(
+        db: &mut MixxxDb,
+        rule_plans: &[(&BpmRule, ChangePlan)],
+        options: &WriteOptions,
+    ) -> Result<Option<ChangePlan>, LibHelperError> {
+        let ask = options.plan_out.is_none() && !options.dry_run && !options.assume_yes;
+
+        let mut plan = ChangePlan::default();
+        let mut declined = false;
+        for (rule, rule_plan) in rule_plans {
+            if rule_plan.is_empty() {
+                println!("No tracks to change for {rule}");
+                continue;
+            }
+
+            println!("Will change {} tracks for {rule}", rule_plan.track_count());
+            print!("{rule_plan}");
+            if ask {
+                println!("Include these changes? y/n");
+                if !read_confirmation()? {
+                    declined = true;
+                    continue;
+                }
+            }
+            plan.changes.extend(rule_plan.changes.iter().cloned());
+        }
+
+        if let Some(plan_out) = &options.plan_out {
+            plan.save(plan_out)?;
+            println!("Saved plan to {plan_out}");
+            return Ok(None);
+        }
+        if plan.is_empty() && declined {
+            return Err(LibHelperError::UserAbort);
+        }
+        if options.dry_run || plan.is_empty() {
+            return Ok(None);
+        }
+
+        apply_with_safeguards(db, &plan, options)?;
+        Ok(Some(plan))
+    }
+
+    /// Makes sure Mixxx is not running and backs up the database before applying the plan.
+    fn apply_with_safeguards(
+        db: &mut MixxxDb,
+        plan: &ChangePlan,
+        options: &WriteOptions,
+    ) -> Result<(), LibHelperError> {
+        ensure_mixxx_not_running(db.path(), options.wait)?;
+        backup_database(db, options)?;
+        apply_plan(db, plan)
+    }
 
-        let mut stmt = connection.prepare(
-            "UPDATE library
-            SET bpm = ?1
-            WHERE id = ?1",
+    fn backup_database(db: &MixxxDb, options: &WriteOptions) -> Result<(), LibHelperError> {
+        let backup_path = create_backup(
+            db.connection(),
+            &backup_dir(db.path(), options),
+            options.keep_backups,
         )?;
+        println!("Backed up database to {}", backup_path.display());
+
+        Ok(())
+    }
+
+    fn backup_dir(mixxx_db_path: &str, options: &WriteOptions) -> PathBuf {
+        match &options.backup_dir {
+            Some(backup_dir) => PathBuf::from(backup_dir),
+            None => default_backup_dir(Path::new(mixxx_db_path)),
+        }
+    }
+
+    fn read_confirmation() -> Result<bool, LibHelperError> {
+        let mut confirmation: String = String::new();
+        let _ = stdout().flush();
+        stdin().read_line(&mut confirmation)?;
+        Ok(confirmation.eq("y\n"))
+    }
 
+    fn plan_bpm_multiplication(
+        db: &MixxxDb,
+        tracks: &[Track],
+        multiplier: Multiplier,
+    ) -> Result<ChangePlan, LibHelperError> {
+        let mut plan = ChangePlan::default();
         for track in tracks {
-            let new_bpm = track.bpm as f64 * multiplier;
-            let new_bpm = (new_bpm * 100.0).round() / 100.0;
-            stmt.execute((&new_bpm, &track.id))?;
+            let new_bpm = multiplier.apply(track.bpm);
+            let beats_changes = match plan_beats_change(db, track.id, new_bpm) {
+                Ok(beats_changes) => beats_changes,
+                Err(err @ LibHelperError::InvalidBeats(_)) => {
+                    println!("Skipping track {} ({}): {err}", track.id, track.location);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            let change = plan_track_change(
+                db,
+                ChangeTable::Library,
+                track.id,
+                "bpm",
+                ColumnValue::Real(new_bpm),
+            )?;
+            plan.changes.extend(change);
+            plan.changes.extend(beats_changes);
         }
 
-        Ok(())
+        Ok(plan)
     }
 
-    fn find_edm_tracks_with_low_bpm(
-        mixxx_db_path: &str,
-    ) -> Result<Vec<Track>, Box<dyn std::error::Error>> {
-        let tracks_with_bpm_below_100 = find_tracks_with_bpm_below(100, &mixxx_db_path)?;
-        let tracks_with_id3_formats = filter_to_id3_supported_formats(&tracks_with_bpm_below_100);
-        let id3tracks = get_id3_infos_for_tracks(&tracks_with_id3_formats)?;
-        Ok(filter_to_edm_tracks(&tracks_with_bpm_below_100, &id3tracks))
+    /// Plans the changes of each rule in turn. A track is only changed by the
+    /// first rule that matches it.
+    fn plan_bpm_rules<'a>(
+        db: &MixxxDb,
+        rule_set: &'a BpmRuleSet,
+        bpm_options: &FixBpmOptions,
+        tag_report: &mut TagReadReport,
+    ) -> Result<Vec<(&'a BpmRule, ChangePlan)>, LibHelperError> {
+        let min_bpm = rule_set
+            .rules
+            .iter()
+            .map(|rule| rule.min_bpm)
+            .fold(f64::INFINITY, f64::min);
+        let max_bpm = rule_set
+            .rules
+            .iter()
+            .map(|rule| rule.max_bpm)
+            .fold(0.0, f64::max);
+        let mut remaining_tracks = find_tracks_with_bpm_between(min_bpm, max_bpm, db)?;
+        if !bpm_options.include_locked {
+            let locked_tracks = find_bpm_locked_track_ids(db)?;
+            let track_count = remaining_tracks.len();
+            remaining_tracks.retain(|track| !locked_tracks.contains(&track.id));
+            if remaining_tracks.len() < track_count {
+                println!(
+                    "Leaving {} tracks with a locked BPM alone, include them with --include-locked",
+                    track_count - remaining_tracks.len()
+                );
+            }
+        }
+        let tracks_with_tag_formats = filter_to_tag_supported_formats(&remaining_tracks);
+        let tagged_tracks = get_tag_infos_for_tracks(&tracks_with_tag_formats, tag_report);
+
+        let mut rule_plans = vec![];
+        for rule in &rule_set.rules {
+            let is_candidate = |track: &&Track| {
+                rule.matches_bpm(track.bpm)
+                    && remaining_tracks
+                        .iter()
+                        .any(|remaining| remaining.id == track.id)
+            };
+            let tracks: Vec<Track> = remaining_tracks
+                .iter()
+                .filter(is_candidate)
+                .cloned()
+                .collect();
+            let tracks_with_tags: Vec<Track> =
+                tagged_tracks.iter().filter(is_candidate).cloned().collect();
+            let matched = filter_to_genre(&tracks, &tracks_with_tags, |genre| {
+                rule.matches_genre(genre, &rule_set.taxonomy)
+            });
+
+            remaining_tracks.retain(|track| !matched.iter().any(|other| other.id == track.id));
+            let matched = if bpm_options.verify_audio {
+                filter_to_audio_confirmed(matched, rule.multiplier, bpm_options.min_confidence)
+            } else {
+                matched
+            };
+            rule_plans.push((
+                rule,
+                plan_bpm_multiplication(db, &matched, rule.multiplier)?,
+            ));
+        }
+
+        Ok(rule_plans)
     }
 
-    fn get_id3_infos_for_tracks(tracks: &Vec<Track>) -> Result<Vec<Track>, id3::Error> {
+    /// The tracks whose tags could be read, with their tags. Files that are
+    /// missing are skipped, all other problems are noted in `tag_report`.
+    fn get_tag_infos_for_tracks(tracks: &Vec<Track>, tag_report: &mut TagReadReport) -> Vec<Track> {
         let mut result = vec![];
         for track in tracks {
             if !Path::new(&track.location).exists() {
                 continue;
             }
-            let tag = Tag::read_from_path(&track.location)?;
             let mut enriched_track = track.clone();
-            enriched_track.id3 = Some(tag);
-            result.push(enriched_track);
+            enriched_track.metadata = tag_report.read(Path::new(&track.location));
+            if enriched_track.metadata.is_some() {
+                result.push(enriched_track);
+            }
         }
 
-        return Ok(result);
+        result
     }
 
-    fn is_format(track: &Track, formats: &Vec<&str>) -> bool {
-        let location_lowercase = track.location.to_lowercase();
-        let formats_lowercase: Vec<String> = formats
+    /// Keeps the tracks whose content is a format with tags that can be read,
+    /// whatever their file names say.
+    fn filter_to_tag_supported_formats(tracks: &[Track]) -> Vec<Track> {
+        tracks
             .iter()
+            .filter(|track| {
+                detect_format(Path::new(&track.location))
+                    .ok()
+                    .flatten()
+                    .and_then(|format| format.tag_format())
+                    .is_some()
+            })
             .cloned()
-            .map(|format| format.to_lowercase())
-            .collect();
+            .collect()
+    }
+
+    /// Keeps the tracks whose audio favours the corrected tempo over the current one
+    /// by at least `min_confidence`, reporting the analysis of each track.
+    fn filter_to_audio_confirmed(
+        tracks: Vec<Track>,
+        multiplier: Multiplier,
+        min_confidence: f64,
+    ) -> Vec<Track> {
+        let mut confirmed = vec![];
+        for track in tracks {
+            let new_bpm = multiplier.apply(track.bpm);
+            let samples = match decode_mono(Path::new(&track.location), AUDIO_ANALYSIS_SECONDS) {
+                Ok(samples) => samples,
+                Err(err) => {
+                    println!("Skipping track {}: {err}", track.id);
+                    continue;
+                }
+            };
 
-        for format in formats_lowercase {
-            if location_lowercase.ends_with(&format) {
-                return true;
+            let envelope = OnsetEnvelope::from_samples(&samples, ANALYSIS_SAMPLE_RATE);
+            let confidence = envelope.preference(track.bpm, new_bpm);
+            let estimate = envelope
+                .estimate()
+                .map(|estimate| format!("{:.1}", estimate.bpm))
+                .unwrap_or(String::from("?"));
+            let agrees = confidence >= min_confidence;
+            println!(
+                "track {}\t{}\taudio estimate {estimate} BPM, confidence {confidence:.2} for {new_bpm} BPM{}",
+                track.id,
+                track.location,
+                if agrees { "" } else { ", skipping" }
+            );
+            if agrees {
+                confirmed.push(track);
             }
         }
 
-        return false;
+        confirmed
     }
 
-    fn filter_to_id3_supported_formats(tracks: &Vec<Track>) -> Vec<Track> {
-        return tracks
-            .iter()
-            .cloned()
-            .filter(|track| is_format(track, &vec!["mp3", "mp4", "wav", "aiff"]))
-            .collect();
-    }
-
-    fn filter_to_edm_tracks(tracks: &Vec<Track>, tracks_with_id3: &Vec<Track>) -> Vec<Track> {
+    fn filter_to_genre(
+        tracks: &[Track],
+        tracks_with_tags: &[Track],
+        matches_genre: impl Fn(&str) -> bool,
+    ) -> Vec<Track> {
         let tracks_identified_from_db: Vec<Track> = tracks
             .iter()
+            .filter(|track| matches_genre(&track.genre))
             .cloned()
-            .filter(|track| is_edm(&track.genre))
             .collect();
 
         let mut result = tracks_identified_from_db;
 
-        let tracks_identified_from_id3: Vec<Track> = tracks_with_id3
+        let tracks_identified_from_tags: Vec<Track> = tracks_with_tags
             .iter()
-            .cloned()
             .filter(|track| {
-                track.id3.is_some()
-                    && track.id3.as_ref().unwrap().genre().is_some()
-                    && is_edm(track.id3.as_ref().unwrap().genre().as_ref().unwrap())
+                track
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.genre.as_deref())
+                    .is_some_and(&matches_genre)
             })
+            .cloned()
             .collect();
 
-        for track in tracks_identified_from_id3 {
+        for track in tracks_identified_from_tags {
             let tracks_found_by_location: Vec<Track> = result
                 .iter()
-                .cloned()
                 .filter(|existing| existing.location.eq(&track.location))
+                .cloned()
                 .collect();
-            if tracks_found_by_location.len() == 0 {
+            if tracks_found_by_location.is_empty() {
                 result.push(track);
             }
         }
@@ -197,68 +1206,84 @@ pub mod mixxx_db {
         result
     }
 
-    fn find_tracks_with_bpm_below(
-        bpm: u8,
-        mixxx_db_path: &str,
-    ) -> Result<Vec<Track>, rusqlite::Error> {
-        let connection = get_connection(&mixxx_db_path);
-
-        let mut stmt = connection.prepare(
-            "SELECT l.id, l.bpm, l.genre, tl.location FROM library l
-             INNER JOIN track_locations tl
-             ON tl.id = l.location
-             WHERE l.bpm IS NOT NULL
-             AND l.bpm > ?1;",
-        )?;
-
-        let rows = stmt.query_map([bpm], |row| {
-            Ok(Track {
-                id: row.get(0)?,
-                bpm: row.get(1)?,
-                genre: row.get(2)?,
-                location: row.get(3)?,
-                id3: None,
-            })
-        })?;
+    /// Tracks with a BPM of at least `min_bpm` and below `max_bpm`, leaving out unanalyzed ones.
+    fn find_tracks_with_bpm_between(
+        min_bpm: f64,
+        max_bpm: f64,
+        db: &MixxxDb,
+    ) -> Result<Vec<Track>, LibHelperError> {
+        db.require_columns("library", &["genre"])?;
+        TrackQuery::new()
+            .bpm_between(min_bpm, max_bpm)
+            .include_deleted(true)
+            .load(db)
+    }
 
-        let mut tracks = Vec::new();
-        for row in rows {
-            tracks.push(row.unwrap());
+    /// Ids of the tracks whose BPM was locked in Mixxx. Schemas without the flag have none.
+    fn find_bpm_locked_track_ids(db: &MixxxDb) -> Result<HashSet<usize>, LibHelperError> {
+        if !db.has_column("library", "bpm_lock") {
+            return Ok(HashSet::new());
         }
 
-        Ok(tracks)
+        let mut stmt = db
+            .connection()
+            .prepare("SELECT id FROM library WHERE bpm_lock = 1")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<HashSet<usize>, _>>()?)
     }
 
-    fn get_connection(db_path: &str) -> rusqlite::Connection {
-        return rusqlite::Connection::open(db_path).expect("Could not open db path");
+    /// Opens the database and warns if its schema is newer than any this tool knows.
+    fn open_db(mixxx_db_path: &str) -> Result<MixxxDb, LibHelperError> {
+        let db = MixxxDb::open(mixxx_db_path)?;
+        if !db.is_known_schema_version() {
+            println!(
+                "Warning: schema version {} is newer than this tool knows, only the columns it uses are checked",
+                db.schema_version()
+            );
+        }
+
+        Ok(db)
     }
 
     #[cfg(test)]
     mod tests {
-        use std::{fs, path};
+        use std::path::Path;
 
         use rusqlite::Connection;
 
-        use crate::mixxx_db::filter_to_edm_tracks;
+        use crate::mixxx_db::filter_to_genre;
+        use crate::test_db::test_db::{insert_track, open_test_db, open_test_mixxx_db};
 
-        use super::{filter_to_id3_supported_formats, fix_edm_bpm, Track};
+        use super::{
+            filter_to_tag_supported_formats, fix_edm_bpm, is_edm, open_db, plan_bpm_rules,
+            BpmRuleSet, FixBpmOptions, LibHelperError, TagReadReport, Track, WriteOptions,
+        };
 
-        //#[test]
+        #[test]
         fn full_integration_test() -> Result<(), Box<dyn std::error::Error>> {
             // setup
-            if path::Path::is_file(path::Path::new("db.db")) {
-                fs::remove_file("db.db")?;
-            }
-            let db_name = "db.db";
-            let connection = rusqlite::Connection::open(&db_name)?;
-            setup_test_db(&connection)?;
+            let db_dir = tempfile::tempdir()?;
+            let db_path = db_dir.path().join("mixxxdb.sqlite");
+            let db_name = db_path.to_str().unwrap();
+            let fixture = open_test_db();
+            insert_track(&fixture, 1, "/sample", "Trance", 92.0);
+            insert_track(&fixture, 2, "/sample2", "", 93.3);
+            fixture.execute("VACUUM INTO ?1", [db_name])?;
+            let options = WriteOptions {
+                assume_yes: true,
+                backup_dir: Some(db_dir.path().join("backups").to_string_lossy().to_string()),
+                ..WriteOptions::default()
+            };
 
             // run
-            fix_edm_bpm(&db_name)?;
+            fix_edm_bpm(db_name, &options)?;
 
             // verify
+            let connection = Connection::open(db_name)?;
             let mut stmt = connection.prepare(
-                "SELECT l.id, l.bpm, l.genre from library l
+                "SELECT l.id, l.bpm, l.genre, tl.location from library l
+                 INNER JOIN track_locations tl
+                 ON tl.id = l.location
                  WHERE l.bpm IS NOT NULL;
                  ",
             )?;
@@ -269,178 +1294,195 @@ pub mod mixxx_db {
                     bpm: row.get(1)?,
                     genre: row.get(2)?,
                     location: row.get(3)?,
-                    id3: None,
+                    ..Track::default()
                 })
             })?;
 
-            let mut tracks = Vec::new();
-            for row in rows {
-                tracks.push(row.unwrap());
-            }
+            let tracks = rows.collect::<Result<Vec<Track>, _>>()?;
 
             assert_eq!(tracks.len(), 2);
 
-            let edm_track = tracks.iter().cloned().find(|track| track.id == 1).unwrap();
+            let edm_track = tracks.iter().find(|track| track.id == 1).unwrap();
             assert_eq!(edm_track.bpm, 138.0);
 
-            let unknown_genre_track = tracks.iter().cloned().find(|track| track.id == 2).unwrap();
+            let unknown_genre_track = tracks.iter().find(|track| track.id == 2).unwrap();
             assert_eq!(unknown_genre_track.bpm, 93.3);
 
-            // teardown
-            std::fs::remove_file("db.db")?;
             Ok(())
         }
 
         #[test]
-        fn filter_to_edm_tracks_leaves_out_whitespace_genre() {
+        fn plan_bpm_rules_lets_first_matching_rule_win() {
             // setup
-            let tracks = vec![Track {
-                id: 123,
-                bpm: 123.0,
-                genre: String::from(" "),
-                location: String::from(""),
-                id3: None,
-            }];
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/dnb.mp3", "Drum & Bass", 87.0);
+            insert_track(db.connection(), 2, "/hiphop.mp3", "Hip-Hop", 180.0);
+            insert_track(db.connection(), 3, "/trance.mp3", "Trance", 92.0);
+            let rule_set = BpmRuleSet::from_toml(
+                r#"
+                [[rule]]
+                genre = "drum ?(&|and|n) ?bass"
+                min_bpm = 80
+                max_bpm = 95
+                multiplier = 2
+
+                [[rule]]
+                genre = "hip.?hop"
+                min_bpm = 160
+                multiplier = "1/2"
+
+                [[rule]]
+                genre = "."
+                max_bpm = 100
+                multiplier = "3/2"
+                "#,
+            )
+            .unwrap();
 
             // run
-            let result = filter_to_edm_tracks(&tracks, &vec![]);
+            let rule_plans = plan_bpm_rules(
+                &db,
+                &rule_set,
+                &FixBpmOptions::default(),
+                &mut TagReadReport::default(),
+            )
+            .unwrap();
 
             // verify
-            assert_eq!(result.len(), 0);
+            let changed: Vec<Vec<(Option<usize>, String)>> = rule_plans
+                .iter()
+                .map(|(_, plan)| {
+                    plan.changes
+                        .iter()
+                        .map(|change| (change.track_id, change.new_value.to_string()))
+                        .collect()
+                })
+                .collect();
+            assert_eq!(
+                changed,
+                vec![
+                    vec![(Some(1), String::from("174"))],
+                    vec![(Some(2), String::from("90"))],
+                    vec![(Some(3), String::from("138"))],
+                ]
+            );
+        }
+
+        #[test]
+        fn plan_bpm_rules_skips_locked_tracks_unless_included() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/locked.mp3", "Trance", 92.0);
+            insert_track(db.connection(), 2, "/unlocked.mp3", "Trance", 92.0);
+            db.connection()
+                .execute("UPDATE library SET bpm_lock = 1 WHERE id = 1", [])
+                .unwrap();
+            let rule_set = BpmRuleSet::edm_default();
+
+            // run
+            let skipping = plan_bpm_rules(
+                &db,
+                &rule_set,
+                &FixBpmOptions::default(),
+                &mut TagReadReport::default(),
+            )
+            .unwrap();
+            let including = plan_bpm_rules(
+                &db,
+                &rule_set,
+                &FixBpmOptions {
+                    include_locked: true,
+                    ..FixBpmOptions::default()
+                },
+                &mut TagReadReport::default(),
+            )
+            .unwrap();
+
+            // verify
+            assert_eq!(skipping[0].1.track_count(), 1);
+            assert_eq!(skipping[0].1.changes[0].track_id, Some(2));
+            assert_eq!(including[0].1.track_count(), 2);
+        }
+
+        #[test]
+        fn open_db_reports_unopenable_path() {
+            // run
+            let result = open_db("/does/not/exist/mixxxdb.sqlite");
+
+            // verify
+            assert!(matches!(
+                result,
+                Err(LibHelperError::DbOpen { path, .. }) if path == "/does/not/exist/mixxxdb.sqlite"
+            ));
         }
 
         #[test]
-        fn filter_to_id3_supported_formats_leaves_out_mov() {
+        fn filter_to_genre_leaves_out_whitespace_genre() {
             // setup
             let tracks = vec![Track {
                 id: 123,
                 bpm: 123.0,
                 genre: String::from(" "),
-                location: String::from(".mov"),
-                id3: None,
+                ..Track::default()
             }];
 
             // run
-            let result = filter_to_id3_supported_formats(&tracks);
+            let result = filter_to_genre(&tracks, &[], is_edm);
 
             // verify
             assert_eq!(result.len(), 0);
         }
 
-        #[test]
-        fn filter_to_id3_supported_formats_includes_all_valid_formats() {
-            // setup
-            let base_track = Track {
+        fn track_with_content(dir: &Path, file_name: &str, content: &[u8]) -> Track {
+            let path = dir.join(file_name);
+            std::fs::write(&path, content).unwrap();
+            Track {
                 id: 123,
                 bpm: 123.0,
                 genre: String::from(""),
-                location: String::from(""),
-                id3: None,
-            };
+                location: path.to_string_lossy().to_string(),
+                ..Track::default()
+            }
+        }
 
+        #[test]
+        fn filter_to_tag_supported_formats_leaves_out_mov() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
             let tracks = vec![
-                Track {
-                    location: String::from("mp3"),
-                    ..base_track.clone()
-                },
-                Track {
-                    location: String::from("mp4"),
-                    ..base_track.clone()
-                },
-                Track {
-                    location: String::from("wav"),
-                    ..base_track.clone()
-                },
-                Track {
-                    location: String::from("aiff"),
-                    ..base_track
-                },
+                track_with_content(dir.path(), "clip.mov", b"\0\0\0\x14ftypqt  \0\0\0\0"),
+                track_with_content(dir.path(), "notes.mp3", b"not audio"),
             ];
 
             // run
-            let result = filter_to_id3_supported_formats(&tracks);
+            let result = filter_to_tag_supported_formats(&tracks);
 
             // verify
-            assert_eq!(result.len(), 4);
+            assert_eq!(result.len(), 0);
         }
 
-        fn setup_test_db(connection: &Connection) -> Result<(), Box<dyn std::error::Error>> {
-            let stmt = r#"
-            CREATE TABLE track_locations (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                location VARCHAR(512) UNIQUE
-            )
-            "#;
-            connection.execute(&stmt, ())?;
-
-            let stmt = r#"
-            CREATE TABLE library (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                genre VARCHAR(64),
-                bpm FLOAT,
-                location INTEGER REFERENCES track_locations(location)
-            )
-            "#;
-            connection.execute(&stmt, ())?;
-
-            let stmt = r#"
-            INSERT INTO track_locations (
-                id,
-                location
-            )
-            VALUES (
-                1,
-                "/sample"
-            )
-            "#;
-            connection.execute(&stmt, ())?;
-
-            let stmt = r#"
-            INSERT INTO track_locations (
-                id,
-                location
-            )
-            VALUES (
-                2,
-                "/sample2"
-            )
-            "#;
-            connection.execute(&stmt, ())?;
-
-            let stmt = r#"
-            INSERT INTO library (
-                id,
-                genre,
-                bpm,
-                location
-            )
-            VALUES (
-                1,
-                "edm",
-                93.3,
-                1
-            )
-            "#;
-            connection.execute(&stmt, ())?;
-
-            let stmt = r#"
-            INSERT INTO library (
-                id,
-                genre,
-                bpm,
-                location
-            )
-            VALUES (
-                2,
-                "",
-                93.3,
-                2
-            )
-            "#;
+        #[test]
+        fn filter_to_tag_supported_formats_includes_all_valid_formats() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let mut ogg_vorbis = b"OggS".to_vec();
+            ogg_vorbis.extend_from_slice(&[0; 22]);
+            ogg_vorbis.extend_from_slice(&[1, 30]);
+            ogg_vorbis.extend_from_slice(b"\x01vorbis");
+            let tracks = vec![
+                track_with_content(dir.path(), "track.MPEG", &[0xFF, 0xFB, 0x90, 0x00]),
+                track_with_content(dir.path(), "track.m4a", b"\0\0\0\x20ftypM4A \0\0\0\0"),
+                track_with_content(dir.path(), "track.wav", b"RIFF\x24\0\0\0WAVEfmt "),
+                track_with_content(dir.path(), "track.aif", b"FORM\0\0\0\x24AIFFCOMM"),
+                track_with_content(dir.path(), "track", b"fLaC\0\0\0\x22"),
+                track_with_content(dir.path(), "track.ogg", &ogg_vorbis),
+            ];
 
-            connection.execute(&stmt, ())?;
+            // run
+            let result = filter_to_tag_supported_formats(&tracks);
 
-            Ok(())
+            // verify
+            assert_eq!(result.len(), 6);
         }
     }
 }
@@ -5,7 +5,9 @@ pub mod logfile_anonymize {
     use rand::{distributions::Alphanumeric, Rng};
     use regex::Regex;
 
-    pub fn anonymize(file_contents: &str) -> Result<String, Box<dyn std::error::Error>> {
+    use crate::error::error::LibHelperError;
+
+    pub fn anonymize(file_contents: &str) -> Result<String, LibHelperError> {
         // serial number macOS
         let result = replace(file_contents, "S/N: [A-Z0-9]+", "S/N: [HIDDEN]")?;
         // replace device IDs
@@ -18,13 +20,13 @@ pub mod logfile_anonymize {
         file_contents: &str,
         regex_str: &str,
         device_serial_replacement: &str,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        let device_serial_regex = Regex::new(&regex_str).unwrap();
+    ) -> Result<String, LibHelperError> {
+        let device_serial_regex = Regex::new(regex_str)?;
 
         let mut result = String::new();
         for line in file_contents.lines() {
             let replaced = device_serial_regex
-                .replace_all(&line, device_serial_replacement)
+                .replace_all(line, device_serial_replacement)
                 .to_string();
 
             result.push_str(&replaced);
@@ -33,8 +35,8 @@ pub mod logfile_anonymize {
         Ok(result)
     }
 
-    fn replace_device_ids(input_str: &str) -> Result<String, Box<dyn std::error::Error>> {
-        let device_serial_regex = Regex::new(r"\{ (\w{4}:\w{4}) r(\d+) ").unwrap();
+    fn replace_device_ids(input_str: &str) -> Result<String, LibHelperError> {
+        let device_serial_regex = Regex::new(r"\{ (\w{4}:\w{4}) r(\d+) ")?;
 
         // Define a map to store original device IDs and their new random IDs
         let mut id_map: HashMap<String, String> = HashMap::new();
@@ -44,7 +46,7 @@ pub mod logfile_anonymize {
         for line in input_str.lines() {
             // Replace the device IDs with new random IDs
             let device_serial_replacement = &device_serial_regex
-                .replace_all(&line, |caps: &regex::Captures| {
+                .replace_all(line, |caps: &regex::Captures| {
                     let device_id = &caps[1];
                     let replacement_id = id_map
                         .entry(device_id.to_string())
@@ -53,7 +55,7 @@ pub mod logfile_anonymize {
                 })
                 .to_string();
 
-            result.push_str(&device_serial_replacement);
+            result.push_str(device_serial_replacement);
             result.push('\n');
         }
 
@@ -0,0 +1,178 @@
+pub mod test_db {
+
+    use rusqlite::Connection;
+
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::{
+        MixxxDb, NEWEST_KNOWN_SCHEMA_VERSION,
+    };
+    use crate::track_analysis::audio_decode::audio_decode::ANALYSIS_SAMPLE_RATE;
+
+    /// Creates an in-memory database with the subset of the Mixxx schema
+    /// the library functions work on.
+    pub fn open_test_db() -> Connection {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                r#"
+            CREATE TABLE track_locations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                location VARCHAR(512) UNIQUE,
+                filename VARCHAR(512),
+                directory VARCHAR(512),
+                filesize INTEGER,
+                fs_deleted INTEGER,
+                needs_verification INTEGER
+            );
+
+            CREATE TABLE library (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                artist VARCHAR(64),
+                title VARCHAR(64),
+                album VARCHAR(64),
+                album_artist VARCHAR(64),
+                year VARCHAR(16),
+                genre VARCHAR(64),
+                location INTEGER REFERENCES track_locations(location),
+                comment VARCHAR(256),
+                duration INTEGER,
+                bitrate INTEGER,
+                samplerate INTEGER,
+                bpm FLOAT,
+                mixxx_deleted INTEGER,
+                filetype VARCHAR(8) DEFAULT "?",
+                timesplayed INTEGER DEFAULT 0,
+                last_played_at DATETIME DEFAULT NULL,
+                rating INTEGER DEFAULT 0,
+                key VARCHAR(8) DEFAULT "",
+                datetime_added DEFAULT CURRENT_TIMESTAMP,
+                color INTEGER,
+                beats BLOB,
+                beats_version TEXT,
+                bpm_lock INTEGER DEFAULT 0,
+                source_synchronized_ms INTEGER
+            );
+
+            CREATE TABLE directories (
+                directory TEXT UNIQUE
+            );
+
+            CREATE TABLE crates (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name VARCHAR(48) UNIQUE NOT NULL,
+                count INTEGER DEFAULT 0,
+                show INTEGER DEFAULT 1,
+                locked INTEGER DEFAULT 0,
+                autodj_source INTEGER DEFAULT 0
+            );
+
+            CREATE TABLE crate_tracks (
+                crate_id INTEGER NOT NULL REFERENCES crates(id),
+                track_id INTEGER NOT NULL REFERENCES library(id),
+                UNIQUE (crate_id, track_id)
+            );
+
+            CREATE TABLE Playlists (
+                id INTEGER PRIMARY KEY,
+                name VARCHAR(48),
+                position INTEGER,
+                hidden INTEGER DEFAULT 0 NOT NULL,
+                date_created DATETIME,
+                date_modified DATETIME,
+                locked INTEGER DEFAULT 0
+            );
+
+            CREATE TABLE PlaylistTracks (
+                id INTEGER PRIMARY KEY,
+                playlist_id INTEGER REFERENCES Playlists(id),
+                track_id INTEGER REFERENCES library(id),
+                position INTEGER,
+                pl_datetime_added TEXT
+            );
+
+            CREATE TABLE cues (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                track_id INTEGER NOT NULL REFERENCES library(id),
+                type INTEGER DEFAULT 0 NOT NULL,
+                position INTEGER DEFAULT -1 NOT NULL,
+                length INTEGER DEFAULT 0 NOT NULL,
+                hotcue INTEGER DEFAULT -1 NOT NULL,
+                label TEXT DEFAULT '' NOT NULL,
+                color INTEGER DEFAULT 4294901760 NOT NULL
+            );
+
+            CREATE TABLE settings (
+                name TEXT UNIQUE NOT NULL,
+                value TEXT,
+                locked INTEGER DEFAULT 0,
+                hidden INTEGER DEFAULT 0
+            );
+            "#,
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO settings (name, value) VALUES ('mixxx.schema.version', ?1)",
+                [NEWEST_KNOWN_SCHEMA_VERSION.to_string()],
+            )
+            .unwrap();
+
+        connection
+    }
+
+    /// Like [`open_test_db`], wrapped in the handle the library functions take.
+    pub fn open_test_mixxx_db() -> MixxxDb {
+        MixxxDb::from_connection(open_test_db(), ":memory:").unwrap()
+    }
+
+    /// Inserts a track and its location row, using `id` for both.
+    pub fn insert_track(connection: &Connection, id: usize, location: &str, genre: &str, bpm: f64) {
+        let path = std::path::Path::new(location);
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string());
+        let directory = path.parent().map(|dir| dir.to_string_lossy().to_string());
+        let filesize = std::fs::metadata(path).map(|metadata| metadata.len()).ok();
+
+        connection
+            .execute(
+                "INSERT INTO track_locations (id, location, filename, directory, filesize, fs_deleted, needs_verification)
+                 VALUES (?1, ?2, ?3, ?4, ?5, 0, 0)",
+                (id, location, filename, directory, filesize),
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO library (id, genre, bpm, location, mixxx_deleted)
+                 VALUES (?1, ?2, ?3, ?1, 0)",
+                (id, genre, bpm),
+            )
+            .unwrap();
+    }
+
+    /// Inserts a cue of the given type, in hotcue slot `hotcue` or -1 for none.
+    pub fn insert_cue(connection: &Connection, track_id: usize, cue_type: i64, hotcue: i64) {
+        connection
+            .execute(
+                "INSERT INTO cues (track_id, type, hotcue) VALUES (?1, ?2, ?3)",
+                (track_id, cue_type, hotcue),
+            )
+            .unwrap();
+    }
+
+    /// Deterministic noise with a melody on top, some seconds long.
+    pub fn music(seed: u64, seconds: usize) -> Vec<f32> {
+        let mut state = seed;
+        let rate = ANALYSIS_SAMPLE_RATE as usize;
+        (0..seconds * rate)
+            .map(|index| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                let noise = (state >> 40) as f32 / (1u64 << 24) as f32 - 0.5;
+                let note = 400.0 + 100.0 * ((index / (rate / 4) + seed as usize) % 7) as f32;
+                let tone = (2.0 * std::f32::consts::PI * note * index as f32 / rate as f32).sin();
+                0.3 * noise + 0.5 * tone
+            })
+            .collect()
+    }
+}
@@ -1 +1,6 @@
+pub mod file_type;
+pub mod metadata;
+pub mod tag_report;
+pub mod tags;
 pub mod track;
+pub mod track_query;
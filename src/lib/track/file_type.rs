@@ -0,0 +1,334 @@
+pub mod file_type {
+    use std::{
+        fmt,
+        fs::File,
+        io::{self, Read, Seek, SeekFrom},
+        path::Path,
+    };
+
+    use crate::{
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb, error::error::LibHelperError,
+        track::metadata::metadata::TagFormat,
+    };
+
+    /// Enough for every container header this module recognizes.
+    const SNIFF_BYTES: usize = 64;
+
+    /// The audio format of a file, as its content says.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AudioFormat {
+        Mp3,
+        /// AAC in ADTS frames, without a container
+        Aac,
+        Flac,
+        OggVorbis,
+        Opus,
+        /// Ogg with another codec, such as FLAC or Speex
+        Ogg,
+        Wav,
+        Aiff,
+        /// MP4 audio, usually AAC or ALAC in `.m4a` files
+        Mp4,
+    }
+
+    /// A track whose `library.filetype` does not match its content.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FiletypeMismatch {
+        pub id: usize,
+        pub location: String,
+        pub filetype: String,
+        /// `None` if the content is no audio format this module knows
+        pub detected: Option<AudioFormat>,
+        /// Why the file could not be read, in which case nothing was detected
+        pub read_error: Option<String>,
+    }
+
+    impl AudioFormat {
+        /// The kind of tags files of this format carry, if they can be read.
+        pub fn tag_format(&self) -> Option<TagFormat> {
+            match self {
+                AudioFormat::Mp3 | AudioFormat::Wav | AudioFormat::Aiff => Some(TagFormat::Id3),
+                AudioFormat::Flac | AudioFormat::OggVorbis | AudioFormat::Opus => {
+                    Some(TagFormat::VorbisComment)
+                }
+                AudioFormat::Mp4 => Some(TagFormat::Mp4),
+                AudioFormat::Aac | AudioFormat::Ogg => None,
+            }
+        }
+
+        /// The values of `library.filetype` Mixxx uses for this format.
+        pub fn mixxx_filetypes(&self) -> &'static [&'static str] {
+            match self {
+                AudioFormat::Mp3 => &["mp3"],
+                AudioFormat::Aac => &["aac"],
+                AudioFormat::Flac => &["flac"],
+                AudioFormat::OggVorbis | AudioFormat::Ogg => &["ogg", "oga"],
+                AudioFormat::Opus => &["opus"],
+                AudioFormat::Wav => &["wav"],
+                AudioFormat::Aiff => &["aiff", "aif"],
+                AudioFormat::Mp4 => &["m4a", "mp4"],
+            }
+        }
+    }
+
+    impl fmt::Display for AudioFormat {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let name = match self {
+                AudioFormat::Mp3 => "MP3",
+                AudioFormat::Aac => "AAC",
+                AudioFormat::Flac => "FLAC",
+                AudioFormat::OggVorbis => "Ogg Vorbis",
+                AudioFormat::Opus => "Opus",
+                AudioFormat::Ogg => "Ogg",
+                AudioFormat::Wav => "WAV",
+                AudioFormat::Aiff => "AIFF",
+                AudioFormat::Mp4 => "MP4",
+            };
+            write!(f, "{name}")
+        }
+    }
+
+    /// Reads the start of a file to tell its format, skipping a leading ID3v2 tag.
+    /// Returns `None` for content that is no known audio format.
+    pub fn detect_format(path: &Path) -> Result<Option<AudioFormat>, io::Error> {
+        let mut file = File::open(path)?;
+        let mut header = read_up_to(&mut file, SNIFF_BYTES)?;
+
+        // an ID3v2 tag can precede MPEG frames, but also FLAC or AAC data
+        if let Some(tag_length) = id3v2_length(&header) {
+            file.seek(SeekFrom::Start(tag_length))?;
+            header = read_up_to(&mut file, SNIFF_BYTES)?;
+            return Ok(sniff_format(&header).or(Some(AudioFormat::Mp3)));
+        }
+
+        Ok(sniff_format(&header))
+    }
+
+    /// Tells the format from the first bytes of a file.
+    pub fn sniff_format(header: &[u8]) -> Option<AudioFormat> {
+        if header.starts_with(b"fLaC") {
+            return Some(AudioFormat::Flac);
+        }
+        if header.starts_with(b"OggS") {
+            // the first packet of the first page names the codec
+            let segment_count = *header.get(26)? as usize;
+            let packet = header.get(27 + segment_count..)?;
+            return Some(if packet.starts_with(b"\x01vorbis") {
+                AudioFormat::OggVorbis
+            } else if packet.starts_with(b"OpusHead") {
+                AudioFormat::Opus
+            } else {
+                AudioFormat::Ogg
+            });
+        }
+        if header.starts_with(b"RIFF") && header.get(8..12) == Some(b"WAVE") {
+            return Some(AudioFormat::Wav);
+        }
+        if header.starts_with(b"FORM") && matches!(header.get(8..12), Some(b"AIFF") | Some(b"AIFC"))
+        {
+            return Some(AudioFormat::Aiff);
+        }
+        if header.get(4..8) == Some(b"ftyp") {
+            // QuickTime movies share the container, but are no audio files
+            return (header.get(8..12) != Some(b"qt  ")).then_some(AudioFormat::Mp4);
+        }
+        if let [0xFF, second, ..] = header {
+            if second & 0xE0 == 0xE0 {
+                return match (second >> 1) & 0b11 {
+                    // layer bits: 0 is AAC in ADTS, 1 is MPEG layer III
+                    0 => Some(AudioFormat::Aac),
+                    1 => Some(AudioFormat::Mp3),
+                    _ => None,
+                };
+            }
+        }
+
+        None
+    }
+
+    /// Finds the tracks whose `library.filetype` disagrees with their content.
+    /// Missing files and deleted tracks are left out, files that cannot be read
+    /// are reported with their [`FiletypeMismatch::read_error`].
+    pub fn find_filetype_mismatches(db: &MixxxDb) -> Result<Vec<FiletypeMismatch>, LibHelperError> {
+        db.require_columns("library", &["id", "location", "filetype", "mixxx_deleted"])?;
+        db.require_columns("track_locations", &["id", "location"])?;
+        let mut stmt = db.connection().prepare(
+            "SELECT l.id, tl.location, l.filetype FROM library l
+             INNER JOIN track_locations tl
+             ON tl.id = l.location
+             WHERE l.mixxx_deleted IS NOT 1
+             ORDER BY l.id",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, usize>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut mismatches = vec![];
+        for (id, location, filetype) in rows {
+            let path = Path::new(&location);
+            if !path.is_file() {
+                continue;
+            }
+            let detected = match detect_format(path) {
+                Ok(detected) => detected,
+                Err(err) => {
+                    mismatches.push(FiletypeMismatch {
+                        id,
+                        location,
+                        filetype,
+                        detected: None,
+                        read_error: Some(err.to_string()),
+                    });
+                    continue;
+                }
+            };
+            let matches = detected.is_some_and(|format| {
+                format
+                    .mixxx_filetypes()
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(filetype.trim()))
+            });
+            if !matches {
+                mismatches.push(FiletypeMismatch {
+                    id,
+                    location,
+                    filetype,
+                    detected,
+                    read_error: None,
+                });
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// The length of an ID3v2 tag at the start of the data, including its header and footer.
+    pub fn id3v2_length(header: &[u8]) -> Option<u64> {
+        if !header.starts_with(b"ID3") || header.len() < 10 {
+            return None;
+        }
+        // the size is stored in four 7-bit bytes
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |size, &byte| (size << 7) | u64::from(byte & 0x7F));
+        let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+        Some(10 + size + footer)
+    }
+
+    fn read_up_to(file: &mut File, length: usize) -> Result<Vec<u8>, io::Error> {
+        let mut buffer = vec![];
+        file.take(length as u64).read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_mixxx_db};
+
+        #[test]
+        fn sniff_format_recognizes_containers() {
+            assert_eq!(sniff_format(b"fLaC\0\0\0\x22"), Some(AudioFormat::Flac));
+            assert_eq!(
+                sniff_format(b"RIFF\x24\0\0\0WAVEfmt "),
+                Some(AudioFormat::Wav)
+            );
+            assert_eq!(
+                sniff_format(b"FORM\0\0\0\x24AIFFCOMM"),
+                Some(AudioFormat::Aiff)
+            );
+            assert_eq!(
+                sniff_format(b"\0\0\0\x20ftypM4A \0\0\0\0"),
+                Some(AudioFormat::Mp4)
+            );
+            assert_eq!(sniff_format(b"\0\0\0\x14ftypqt  \0\0\0\0"), None);
+            assert_eq!(
+                sniff_format(&[0xFF, 0xFB, 0x90, 0x00]),
+                Some(AudioFormat::Mp3)
+            );
+            assert_eq!(
+                sniff_format(&[0xFF, 0xF1, 0x50, 0x80]),
+                Some(AudioFormat::Aac)
+            );
+            assert_eq!(sniff_format(b"not audio"), None);
+
+            let mut opus = b"OggS\0\x02".to_vec();
+            opus.extend_from_slice(&[0; 20]);
+            opus.extend_from_slice(&[1, 19]);
+            opus.extend_from_slice(b"OpusHead");
+            assert_eq!(sniff_format(&opus), Some(AudioFormat::Opus));
+        }
+
+        #[test]
+        fn detect_format_looks_past_id3_tag() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let flac_with_id3 = dir.path().join("bootlegmp3");
+            let mut content = b"ID3\x04\0\0\0\0\0\x05".to_vec();
+            content.extend_from_slice(&[0; 5]);
+            content.extend_from_slice(b"fLaC");
+            std::fs::write(&flac_with_id3, content).unwrap();
+
+            // run
+            let format = detect_format(&flac_with_id3).unwrap();
+
+            // verify
+            assert_eq!(format, Some(AudioFormat::Flac));
+        }
+
+        #[test]
+        fn find_filetype_mismatches_flags_wrong_filetype() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let mp3 = dir.path().join("track.mp3");
+            let flac = dir.path().join("mislabeled.mp3");
+            std::fs::write(&mp3, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+            std::fs::write(&flac, b"fLaC\0\0\0\x22").unwrap();
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, mp3.to_str().unwrap(), "", 120.0);
+            insert_track(db.connection(), 2, flac.to_str().unwrap(), "", 120.0);
+            insert_track(db.connection(), 3, "/does/not/exist.mp3", "", 120.0);
+            db.connection()
+                .execute("UPDATE library SET filetype = 'mp3'", [])
+                .unwrap();
+
+            // run
+            let mismatches = find_filetype_mismatches(&db).unwrap();
+
+            // verify
+            assert_eq!(mismatches.len(), 1);
+            assert_eq!(mismatches[0].id, 2);
+            assert_eq!(mismatches[0].detected, Some(AudioFormat::Flac));
+        }
+
+        #[cfg(target_os = "linux")]
+        #[test]
+        fn find_filetype_mismatches_reports_unreadable_files() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let mp3 = dir.path().join("track.mp3");
+            std::fs::write(&mp3, b"fLaC\0\0\0\x22").unwrap();
+            let db = open_test_mixxx_db();
+            // a regular file that fails to read from its start, even for root
+            insert_track(db.connection(), 1, "/proc/self/mem", "", 120.0);
+            insert_track(db.connection(), 2, mp3.to_str().unwrap(), "", 120.0);
+            db.connection()
+                .execute("UPDATE library SET filetype = 'mp3'", [])
+                .unwrap();
+
+            // run
+            let mismatches = find_filetype_mismatches(&db).unwrap();
+
+            // verify
+            assert_eq!(mismatches.len(), 2);
+            assert!(mismatches[0].read_error.is_some());
+            assert_eq!(mismatches[1].detected, Some(AudioFormat::Flac));
+        }
+    }
+}
@@ -0,0 +1,751 @@
+pub mod metadata {
+    use std::{
+        fmt,
+        fs::File,
+        io::{BufReader, Read, Seek, SeekFrom},
+        path::Path,
+        str::FromStr,
+    };
+
+    use id3::{Tag, TagLike};
+    use serde::Serialize;
+
+    use crate::{
+        error::error::LibHelperError,
+        track::file_type::file_type::{detect_format, AudioFormat},
+    };
+
+    /// Comment headers with embedded cover art can be large, but not this large.
+    pub const MAX_HEADER_BYTES: u64 = 64 * 1024 * 1024;
+
+    /// The kind of tag the metadata of a file was read from.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum TagFormat {
+        /// ID3v2, in MP3 files or as a chunk of WAV and AIFF files
+        Id3,
+        /// Vorbis comments, in FLAC, Ogg Vorbis and Opus files
+        VorbisComment,
+        /// iTunes-style `ilst` atoms, in M4A and MP4 files
+        Mp4,
+    }
+
+    /// The tag fields this tool works with, whatever format they were read from.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct TrackMetadata {
+        pub format: TagFormat,
+        pub title: Option<String>,
+        pub artist: Option<String>,
+        pub album: Option<String>,
+        pub genre: Option<String>,
+        pub bpm: Option<f64>,
+        /// The musical key, in whatever notation the tag uses
+        pub key: Option<String>,
+        pub comment: Option<String>,
+        /// Usually just the year, but some tags hold a full date
+        pub year: Option<String>,
+    }
+
+    /// The tag fields that have a column of the same name in `library`.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub enum TagField {
+        Artist,
+        Title,
+        Album,
+        Genre,
+        Bpm,
+        Key,
+        Comment,
+        Year,
+    }
+
+    /// What kept the tags of a file from being read.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TagProblemKind {
+        /// The file is fine, it just has no tag
+        NoTag,
+        /// There is a tag, but it is damaged or truncated
+        CorruptTag,
+        /// The content is no audio format whose tags can be read
+        UnsupportedFormat,
+        /// The file could not be opened or read
+        Unreadable,
+    }
+
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    pub struct TagProblem {
+        pub path: String,
+        pub kind: TagProblemKind,
+        pub message: String,
+    }
+
+    impl TagProblemKind {
+        pub fn description(&self) -> &'static str {
+            match self {
+                TagProblemKind::NoTag => "no tag",
+                TagProblemKind::CorruptTag => "corrupt tag",
+                TagProblemKind::UnsupportedFormat => "unsupported format",
+                TagProblemKind::Unreadable => "unreadable",
+            }
+        }
+    }
+
+    impl From<TagProblem> for LibHelperError {
+        fn from(problem: TagProblem) -> Self {
+            LibHelperError::TagRead {
+                path: problem.path,
+                message: problem.message,
+            }
+        }
+    }
+
+    impl TrackMetadata {
+        /// Metadata without any values, as of a file without a tag.
+        pub fn empty(format: TagFormat) -> TrackMetadata {
+            TrackMetadata {
+                format,
+                title: None,
+                artist: None,
+                album: None,
+                genre: None,
+                bpm: None,
+                key: None,
+                comment: None,
+                year: None,
+            }
+        }
+
+        /// The value of a field as text, `None` if it is missing or blank.
+        pub fn get(&self, field: TagField) -> Option<String> {
+            let value = match field {
+                TagField::Artist => self.artist.clone(),
+                TagField::Title => self.title.clone(),
+                TagField::Album => self.album.clone(),
+                TagField::Genre => self.genre.clone(),
+                TagField::Bpm => self.bpm.map(format_bpm),
+                TagField::Key => self.key.clone(),
+                TagField::Comment => self.comment.clone(),
+                TagField::Year => self.year.clone(),
+            };
+            value.filter(|value| !value.trim().is_empty())
+        }
+    }
+
+    impl TagField {
+        pub const ALL: [TagField; 8] = [
+            TagField::Artist,
+            TagField::Title,
+            TagField::Album,
+            TagField::Genre,
+            TagField::Bpm,
+            TagField::Key,
+            TagField::Comment,
+            TagField::Year,
+        ];
+
+        /// The name of the field, which is also its column in `library`.
+        pub fn name(&self) -> &'static str {
+            match self {
+                TagField::Artist => "artist",
+                TagField::Title => "title",
+                TagField::Album => "album",
+                TagField::Genre => "genre",
+                TagField::Bpm => "bpm",
+                TagField::Key => "key",
+                TagField::Comment => "comment",
+                TagField::Year => "year",
+            }
+        }
+    }
+
+    impl fmt::Display for TagField {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.name())
+        }
+    }
+
+    impl FromStr for TagField {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Self, Self::Err> {
+            TagField::ALL
+                .into_iter()
+                .find(|field| field.name().eq_ignore_ascii_case(name.trim()))
+                .ok_or_else(|| {
+                    let names: Vec<&str> = TagField::ALL.iter().map(|field| field.name()).collect();
+                    format!("unknown field {name}, expected one of {}", names.join(", "))
+                })
+        }
+    }
+
+    /// A BPM with at most two decimals, without trailing zeros.
+    pub fn format_bpm(bpm: f64) -> String {
+        let formatted = format!("{bpm:.2}");
+        formatted
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string()
+    }
+
+    /// Reads the tags of a file, choosing the tag format by the file's content.
+    pub fn read_metadata(path: &Path) -> Result<TrackMetadata, TagProblem> {
+        let problem = |kind, message: String| TagProblem {
+            path: path.to_string_lossy().to_string(),
+            kind,
+            message,
+        };
+        let format = match detect_format(path) {
+            Ok(Some(format)) => format,
+            Ok(None) => {
+                return Err(problem(
+                    TagProblemKind::UnsupportedFormat,
+                    String::from("not a known audio format"),
+                ))
+            }
+            Err(err) => return Err(problem(TagProblemKind::Unreadable, err.to_string())),
+        };
+
+        let result = match format {
+            AudioFormat::Mp3 | AudioFormat::Wav | AudioFormat::Aiff => read_id3(path),
+            AudioFormat::Flac => read_flac(path),
+            AudioFormat::OggVorbis | AudioFormat::Opus => read_ogg(path),
+            AudioFormat::Mp4 => read_mp4(path),
+            AudioFormat::Aac | AudioFormat::Ogg => {
+                return Err(problem(
+                    TagProblemKind::UnsupportedFormat,
+                    format!("tags of {format} files are not read"),
+                ))
+            }
+        };
+        match result {
+            Ok(Some(metadata)) => Ok(metadata),
+            Ok(None) => Err(problem(TagProblemKind::NoTag, String::from("no tag"))),
+            Err(message) => Err(problem(TagProblemKind::CorruptTag, message)),
+        }
+    }
+
+    fn read_id3(path: &Path) -> Result<Option<TrackMetadata>, String> {
+        let tag = match Tag::read_from_path(path) {
+            Ok(tag) => tag,
+            Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => return Ok(None),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        let text = |id: &str| {
+            tag.get(id)
+                .and_then(|frame| frame.content().text())
+                .map(String::from)
+        };
+        let comment = tag
+            .comments()
+            .find(|comment| comment.description.is_empty())
+            .map(|comment| comment.text.clone());
+        Ok(Some(TrackMetadata {
+            format: TagFormat::Id3,
+            title: tag.title().map(String::from),
+            artist: tag.artist().map(String::from),
+            album: tag.album().map(String::from),
+            genre: tag.genre_parsed().map(|genre| genre.to_string()),
+            bpm: text("TBPM").as_deref().and_then(parse_bpm),
+            key: text("TKEY"),
+            comment,
+            year: text("TDRC").or_else(|| text("TYER")),
+        }))
+    }
+
+    /// Finds the `VORBIS_COMMENT` block among the metadata blocks at the start of a FLAC file.
+    fn read_flac(path: &Path) -> Result<Option<TrackMetadata>, String> {
+        let mut file = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+        let mut magic = [0; 4];
+        read_exact(&mut file, &mut magic)?;
+        if &magic != b"fLaC" {
+            return Err(String::from("not a FLAC file"));
+        }
+
+        loop {
+            let mut header = [0; 4];
+            read_exact(&mut file, &mut header)?;
+            let is_last = header[0] & 0x80 != 0;
+            let block_type = header[0] & 0x7F;
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+
+            if block_type == 4 {
+                let mut block = vec![0; length as usize];
+                read_exact(&mut file, &mut block)?;
+                return parse_vorbis_comments(&block).map(Some);
+            }
+            if is_last {
+                return Ok(None);
+            }
+            file.seek(SeekFrom::Current(i64::from(length)))
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    /// Reads the comment header, the second packet of the first logical stream
+    /// of an Ogg Vorbis or Opus file.
+    fn read_ogg(path: &Path) -> Result<Option<TrackMetadata>, String> {
+        let file = File::open(path).map_err(|err| err.to_string())?;
+        let mut file = BufReader::new(file).take(MAX_HEADER_BYTES);
+
+        let mut packets: Vec<Vec<u8>> = vec![];
+        let mut packet = vec![];
+        let mut stream_serial = None;
+        while packets.len() < 2 {
+            let mut header = [0; 27];
+            read_exact(&mut file, &mut header)?;
+            if &header[0..4] != b"OggS" {
+                return Err(String::from("not an Ogg file"));
+            }
+            let serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+            let mut lacing = vec![0; header[26] as usize];
+            read_exact(&mut file, &mut lacing)?;
+            let mut body = vec![0; lacing.iter().map(|&value| value as usize).sum()];
+            read_exact(&mut file, &mut body)?;
+
+            if *stream_serial.get_or_insert(serial) != serial {
+                continue;
+            }
+            let mut offset = 0;
+            for &value in &lacing {
+                packet.extend_from_slice(&body[offset..offset + value as usize]);
+                offset += value as usize;
+                if value < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+        }
+
+        let comment_header = &packets[1];
+        let comments = if let Some(comments) = comment_header.strip_prefix(b"\x03vorbis") {
+            comments
+        } else if let Some(comments) = comment_header.strip_prefix(b"OpusTags") {
+            comments
+        } else {
+            // e.g. FLAC or Speex in Ogg, whose comments are not read
+            return Ok(None);
+        };
+        parse_vorbis_comments(comments).map(Some)
+    }
+
+    /// Splits a Vorbis comment block into its vendor string and `FIELD=value` comments.
+    pub fn split_vorbis_comments(data: &[u8]) -> Result<(String, Vec<String>), String> {
+        let mut reader = ByteReader { data, position: 0 };
+        let vendor_length = reader.u32_le()?;
+        let vendor = String::from_utf8_lossy(reader.take(vendor_length as usize)?).to_string();
+
+        let mut comments = vec![];
+        for _ in 0..reader.u32_le()? {
+            let length = reader.u32_le()?;
+            comments.push(String::from_utf8_lossy(reader.take(length as usize)?).to_string());
+        }
+
+        Ok((vendor, comments))
+    }
+
+    fn parse_vorbis_comments(data: &[u8]) -> Result<TrackMetadata, String> {
+        let (_, comments) = split_vorbis_comments(data)?;
+        let mut metadata = TrackMetadata::empty(TagFormat::VorbisComment);
+        let mut genres: Vec<String> = vec![];
+        for comment in comments {
+            let Some((field, value)) = comment.split_once('=') else {
+                continue;
+            };
+            let value = value.to_string();
+            match field.to_uppercase().as_str() {
+                "TITLE" => metadata.title = metadata.title.or(Some(value)),
+                "ARTIST" => metadata.artist = metadata.artist.or(Some(value)),
+                "ALBUM" => metadata.album = metadata.album.or(Some(value)),
+                "GENRE" => genres.push(value),
+                "BPM" | "TEMPO" => metadata.bpm = metadata.bpm.or(parse_bpm(&value)),
+                "INITIALKEY" | "KEY" => metadata.key = metadata.key.or(Some(value)),
+                "COMMENT" | "DESCRIPTION" => metadata.comment = metadata.comment.or(Some(value)),
+                "DATE" | "YEAR" => metadata.year = metadata.year.or(Some(value)),
+                _ => {}
+            }
+        }
+        if !genres.is_empty() {
+            metadata.genre = Some(genres.join("; "));
+        }
+
+        Ok(metadata)
+    }
+
+    /// Reads the `moov` atom, skipping over the audio data, and parses the
+    /// item list at `moov.udta.meta.ilst`.
+    fn read_mp4(path: &Path) -> Result<Option<TrackMetadata>, String> {
+        let mut file = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+        loop {
+            let mut header = [0; 8];
+            read_exact(&mut file, &mut header)?;
+            let mut size = u64::from(u32::from_be_bytes([
+                header[0], header[1], header[2], header[3],
+            ]));
+            let mut header_length = 8;
+            if size == 1 {
+                let mut large_size = [0; 8];
+                read_exact(&mut file, &mut large_size)?;
+                size = u64::from_be_bytes(large_size);
+                header_length = 16;
+            }
+            // 0 means the atom extends to the end of the file
+            if size != 0 && size < header_length {
+                return Err(String::from("invalid atom size"));
+            }
+            if &header[4..8] == b"moov" {
+                if size == 0 || size - header_length > MAX_HEADER_BYTES {
+                    return Err(String::from("moov atom has an invalid size"));
+                }
+                let mut moov = vec![0; (size - header_length) as usize];
+                read_exact(&mut file, &mut moov)?;
+                return parse_mp4_items(&moov);
+            }
+            if size == 0 {
+                return Err(String::from("no moov atom"));
+            }
+            // a corrupt large size would turn into a seek backwards
+            let body_length = i64::try_from(size - header_length)
+                .map_err(|_| String::from("invalid atom size"))?;
+            file.seek(SeekFrom::Current(body_length))
+                .map_err(|err| err.to_string())?;
+        }
+    }
+
+    fn parse_mp4_items(moov: &[u8]) -> Result<Option<TrackMetadata>, String> {
+        let Some(udta) = find_atom(moov, b"udta")? else {
+            return Ok(None);
+        };
+        let Some(meta) = find_atom(udta, b"meta")? else {
+            return Ok(None);
+        };
+        // `meta` is a full box with version and flags, except in some QuickTime files
+        let meta = if meta.get(4..8) == Some(b"hdlr") {
+            meta
+        } else {
+            meta.get(4..).unwrap_or_default()
+        };
+        let Some(ilst) = find_atom(meta, b"ilst")? else {
+            return Ok(None);
+        };
+
+        let mut metadata = TrackMetadata::empty(TagFormat::Mp4);
+        for (name, item) in atoms(ilst)? {
+            // freeform items are named by a `name` atom, after version and flags
+            let freeform_name = match &name {
+                b"----" => find_atom(item, b"name")?
+                    .and_then(|name| name.get(4..))
+                    .map(|name| String::from_utf8_lossy(name).to_lowercase()),
+                _ => None,
+            };
+            let Some(data) = find_atom(item, b"data")? else {
+                continue;
+            };
+            // type indicator and locale, then the value
+            let Some(value) = data.get(8..) else {
+                continue;
+            };
+            let text = || Some(String::from_utf8_lossy(value).to_string());
+            match &name {
+                b"\xa9nam" => metadata.title = text(),
+                b"\xa9ART" => metadata.artist = text(),
+                b"\xa9alb" => metadata.album = text(),
+                b"\xa9gen" => metadata.genre = text(),
+                b"\xa9cmt" => metadata.comment = text(),
+                b"\xa9day" => metadata.year = text(),
+                b"----" if freeform_name.as_deref() == Some("initialkey") => metadata.key = text(),
+                b"gnre" if metadata.genre.is_none() => {
+                    metadata.genre = read_be_integer(value).and_then(id3v1_genre)
+                }
+                b"tmpo" => {
+                    metadata.bpm = read_be_integer(value)
+                        .filter(|&bpm| bpm > 0)
+                        .map(|bpm| bpm as f64)
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Some(metadata))
+    }
+
+    /// The body of the first child atom of this type.
+    pub fn find_atom<'a>(data: &'a [u8], name: &[u8; 4]) -> Result<Option<&'a [u8]>, String> {
+        Ok(atoms(data)?
+            .into_iter()
+            .find(|(atom_name, _)| atom_name == name)
+            .map(|(_, body)| body))
+    }
+
+    /// The name and body of an MP4 atom.
+    pub type Atom<'a> = ([u8; 4], &'a [u8]);
+
+    /// Splits a sequence of atoms into their names and bodies.
+    pub fn atoms(data: &[u8]) -> Result<Vec<Atom<'_>>, String> {
+        let mut reader = ByteReader { data, position: 0 };
+        let mut atoms = vec![];
+        while reader.remaining() >= 8 {
+            let mut size = u64::from(reader.u32_be()?);
+            let name: [u8; 4] = reader.take(4)?.try_into().unwrap();
+            let mut header_length = 8;
+            if size == 1 {
+                let large_size = reader.take(8)?;
+                size = u64::from_be_bytes(large_size.try_into().unwrap());
+                header_length = 16;
+            } else if size == 0 {
+                size = reader.remaining() as u64 + header_length;
+            }
+            let body_length = size
+                .checked_sub(header_length)
+                .ok_or_else(|| String::from("invalid atom size"))?;
+            atoms.push((name, reader.take(body_length as usize)?));
+        }
+
+        Ok(atoms)
+    }
+
+    fn read_be_integer(value: &[u8]) -> Option<u64> {
+        if value.is_empty() || value.len() > 8 {
+            return None;
+        }
+        Some(
+            value
+                .iter()
+                .fold(0, |number, &byte| (number << 8) | u64::from(byte)),
+        )
+    }
+
+    /// MP4 `gnre` atoms hold the ID3v1 genre number plus one.
+    fn id3v1_genre(number: u64) -> Option<String> {
+        let index = number.checked_sub(1)?;
+        let mut tag = Tag::new();
+        tag.set_genre(format!("({index})"));
+        let genre = tag.genre_parsed()?.to_string();
+        // unknown numbers are left as they were
+        (genre != format!("({index})")).then_some(genre)
+    }
+
+    fn parse_bpm(value: &str) -> Option<f64> {
+        value
+            .trim()
+            .replace(',', ".")
+            .parse::<f64>()
+            .ok()
+            .filter(|bpm| *bpm > 0.0)
+    }
+
+    fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<(), String> {
+        reader
+            .read_exact(buffer)
+            .map_err(|_| String::from("file ends unexpectedly"))
+    }
+
+    struct ByteReader<'a> {
+        data: &'a [u8],
+        position: usize,
+    }
+
+    impl<'a> ByteReader<'a> {
+        fn remaining(&self) -> usize {
+            self.data.len() - self.position
+        }
+
+        fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+            if length > self.remaining() {
+                return Err(String::from("tag ends unexpectedly"));
+            }
+            let bytes = &self.data[self.position..self.position + length];
+            self.position += length;
+            Ok(bytes)
+        }
+
+        fn u32_le(&mut self) -> Result<u32, String> {
+            Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+        }
+
+        fn u32_be(&mut self) -> Result<u32, String> {
+            Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn vorbis_comments(comments: &[&str]) -> Vec<u8> {
+            let mut data = vec![];
+            data.extend_from_slice(&4u32.to_le_bytes());
+            data.extend_from_slice(b"test");
+            data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+            for comment in comments {
+                data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+                data.extend_from_slice(comment.as_bytes());
+            }
+            data
+        }
+
+        fn ogg_page(serial: u32, packet: &[u8]) -> Vec<u8> {
+            let mut lacing = vec![255; packet.len() / 255];
+            lacing.push((packet.len() % 255) as u8);
+            let mut page = b"OggS".to_vec();
+            page.extend_from_slice(&[0, 0]);
+            page.extend_from_slice(&0u64.to_le_bytes());
+            page.extend_from_slice(&serial.to_le_bytes());
+            page.extend_from_slice(&[0; 8]);
+            page.push(lacing.len() as u8);
+            page.extend_from_slice(&lacing);
+            page.extend_from_slice(packet);
+            page
+        }
+
+        fn atom(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+            let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            atom.extend_from_slice(name);
+            atom.extend_from_slice(body);
+            atom
+        }
+
+        fn data_atom(type_indicator: u32, value: &[u8]) -> Vec<u8> {
+            let mut body = type_indicator.to_be_bytes().to_vec();
+            body.extend_from_slice(&[0; 4]);
+            body.extend_from_slice(value);
+            atom(b"data", &body)
+        }
+
+        #[test]
+        fn read_metadata_reads_flac_vorbis_comments() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.flac");
+            let comments =
+                vorbis_comments(&["TITLE=Intro", "genre=Trance", "GENRE=Uplifting", "BPM=138"]);
+            let mut flac = b"fLaC".to_vec();
+            flac.extend_from_slice(&[0, 0, 0, 34]);
+            flac.extend_from_slice(&[0; 34]);
+            flac.push(0x80 | 4);
+            flac.extend_from_slice(&(comments.len() as u32).to_be_bytes()[1..]);
+            flac.extend_from_slice(&comments);
+            std::fs::write(&path, flac).unwrap();
+
+            // run
+            let metadata = read_metadata(&path).unwrap();
+
+            // verify
+            assert_eq!(metadata.format, TagFormat::VorbisComment);
+            assert_eq!(metadata.title.as_deref(), Some("Intro"));
+            assert_eq!(metadata.genre.as_deref(), Some("Trance; Uplifting"));
+            assert_eq!(metadata.bpm, Some(138.0));
+        }
+
+        #[test]
+        fn read_metadata_reads_opus_tags_across_pages() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.opus");
+            let long_title = format!("TITLE={}", "x".repeat(300));
+            let mut tags = b"OpusTags".to_vec();
+            tags.extend_from_slice(&vorbis_comments(&[&long_title, "GENRE=Techno"]));
+            let mut ogg = ogg_page(7, b"OpusHead");
+            ogg.extend_from_slice(&ogg_page(9, b"other stream"));
+            ogg.extend_from_slice(&ogg_page(7, &tags));
+            std::fs::write(&path, ogg).unwrap();
+
+            // run
+            let metadata = read_metadata(&path).unwrap();
+
+            // verify
+            assert_eq!(metadata.genre.as_deref(), Some("Techno"));
+            assert_eq!(metadata.title.map(|title| title.len()), Some(300));
+        }
+
+        #[test]
+        fn read_metadata_reads_mp4_items() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.m4a");
+            let mut ilst = atom(b"\xa9ART", &data_atom(1, b"Artist"));
+            ilst.extend_from_slice(&atom(b"gnre", &data_atom(0, &[0, 32])));
+            ilst.extend_from_slice(&atom(b"tmpo", &data_atom(21, &[0, 174])));
+            let mut key = atom(b"mean", b"\0\0\0\0com.apple.iTunes");
+            key.extend_from_slice(&atom(b"name", b"\0\0\0\0initialkey"));
+            key.extend_from_slice(&data_atom(1, b"Am"));
+            ilst.extend_from_slice(&atom(b"----", &key));
+            let mut meta = vec![0; 4];
+            meta.extend_from_slice(&atom(b"ilst", &ilst));
+            let moov = atom(b"moov", &atom(b"udta", &atom(b"meta", &meta)));
+            let mut mp4 = atom(b"ftyp", b"M4A ");
+            mp4.extend_from_slice(&atom(b"mdat", &[0; 100]));
+            mp4.extend_from_slice(&moov);
+            std::fs::write(&path, mp4).unwrap();
+
+            // run
+            let metadata = read_metadata(&path).unwrap();
+
+            // verify
+            assert_eq!(metadata.format, TagFormat::Mp4);
+            assert_eq!(metadata.artist.as_deref(), Some("Artist"));
+            assert_eq!(metadata.genre.as_deref(), Some("Trance"));
+            assert_eq!(metadata.bpm, Some(174.0));
+            assert_eq!(metadata.key.as_deref(), Some("Am"));
+        }
+
+        #[test]
+        fn read_metadata_rejects_moov_smaller_than_its_header() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("short.m4a");
+            let mut mp4 = atom(b"ftyp", b"M4A ");
+            mp4.extend_from_slice(b"\0\0\0\x04moov");
+            std::fs::write(&path, mp4).unwrap();
+            let large_path = dir.path().join("large.m4a");
+            let mut mp4 = atom(b"ftyp", b"M4A ");
+            mp4.extend_from_slice(b"\0\0\0\x01moov\0\0\0\0\0\0\0\x0c");
+            std::fs::write(&large_path, mp4).unwrap();
+
+            // run
+            let short = read_metadata(&path);
+            let large = read_metadata(&large_path);
+
+            // verify
+            assert_eq!(short.unwrap_err().kind, TagProblemKind::CorruptTag);
+            assert_eq!(large.unwrap_err().kind, TagProblemKind::CorruptTag);
+        }
+
+        #[test]
+        fn read_metadata_rejects_atom_size_beyond_i64() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.m4a");
+            let mut mp4 = atom(b"ftyp", b"M4A ");
+            // read as a seek back to the start of the file, the reading would never end
+            let size = u64::MAX - mp4.len() as u64 + 1;
+            mp4.extend_from_slice(b"\0\0\0\x01free");
+            mp4.extend_from_slice(&size.to_be_bytes());
+            std::fs::write(&path, mp4).unwrap();
+
+            // run
+            let problem = read_metadata(&path).unwrap_err();
+
+            // verify
+            assert_eq!(problem.kind, TagProblemKind::CorruptTag);
+            assert_eq!(problem.message, "invalid atom size");
+        }
+
+        #[test]
+        fn read_metadata_tells_missing_from_corrupt_tag() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.mp3");
+            std::fs::write(&path, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+            let corrupt_path = dir.path().join("corrupt.flac");
+            std::fs::write(&corrupt_path, b"fLaC\x84\0\0\x40\0\0").unwrap();
+
+            // run
+            let untagged = read_metadata(&path);
+            let corrupt = read_metadata(&corrupt_path);
+
+            // verify
+            assert_eq!(untagged.unwrap_err().kind, TagProblemKind::NoTag);
+            assert_eq!(corrupt.unwrap_err().kind, TagProblemKind::CorruptTag);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+pub mod tag_report {
+    use std::{fmt, path::Path};
+
+    use serde::Serialize;
+
+    use crate::{
+        error::error::LibHelperError,
+        track::metadata::metadata::{read_metadata, TagProblem, TagProblemKind, TrackMetadata},
+    };
+
+    const KINDS: [TagProblemKind; 4] = [
+        TagProblemKind::NoTag,
+        TagProblemKind::CorruptTag,
+        TagProblemKind::UnsupportedFormat,
+        TagProblemKind::Unreadable,
+    ];
+
+    /// The outcome of reading the tags of many files, collected so one bad file
+    /// does not stop a whole run.
+    #[derive(Clone, Debug, Default, Serialize)]
+    pub struct TagReadReport {
+        /// Files whose tags were read
+        pub read: usize,
+        pub problems: Vec<TagProblem>,
+    }
+
+    impl TagReadReport {
+        /// Reads the tags of a file, noting what went wrong instead of failing.
+        pub fn read(&mut self, path: &Path) -> Option<TrackMetadata> {
+            match read_metadata(path) {
+                Ok(metadata) => {
+                    self.read += 1;
+                    Some(metadata)
+                }
+                Err(problem) => {
+                    self.problems.push(problem);
+                    None
+                }
+            }
+        }
+
+        pub fn count(&self, kind: TagProblemKind) -> usize {
+            self.problems
+                .iter()
+                .filter(|problem| problem.kind == kind)
+                .count()
+        }
+
+        /// Writes the problems as JSON if `path` ends in `.json`, otherwise as CSV.
+        pub fn save(&self, path: &str) -> Result<(), LibHelperError> {
+            let is_json = Path::new(path)
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+            let contents = if is_json {
+                serde_json::to_string_pretty(self).map_err(std::io::Error::other)?
+            } else {
+                self.to_csv()
+            };
+            std::fs::write(path, contents)?;
+            Ok(())
+        }
+
+        fn to_csv(&self) -> String {
+            let mut csv = String::from("path,kind,message\n");
+            for problem in &self.problems {
+                csv.push_str(&format!(
+                    "{},{},{}\n",
+                    csv_field(&problem.path),
+                    csv_field(problem.kind.description()),
+                    csv_field(&problem.message)
+                ));
+            }
+            csv
+        }
+    }
+
+    /// A one-line summary, listing the counts of each kind of problem.
+    impl fmt::Display for TagReadReport {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "Read the tags of {} of {} files",
+                self.read,
+                self.read + self.problems.len()
+            )?;
+            let counts: Vec<String> = KINDS
+                .iter()
+                .map(|&kind| (kind, self.count(kind)))
+                .filter(|(_, count)| *count > 0)
+                .map(|(kind, count)| format!("{count} {}", kind.description()))
+                .collect();
+            if !counts.is_empty() {
+                write!(f, " ({})", counts.join(", "))?;
+            }
+            Ok(())
+        }
+    }
+
+    /// Quotes a field if it contains a separator, quote or line break.
+    fn csv_field(value: &str) -> String {
+        if value.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn read_continues_past_problems() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let untagged = dir.path().join("untagged.mp3");
+            let corrupt = dir.path().join("corrupt, really.flac");
+            std::fs::write(&untagged, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+            std::fs::write(&corrupt, b"fLaC\x84\0\0\x40\0\0").unwrap();
+            let mut report = TagReadReport::default();
+
+            // run
+            for path in [&untagged, &corrupt, &dir.path().join("missing.mp3")] {
+                assert_eq!(report.read(path), None);
+            }
+
+            // verify
+            assert_eq!(
+                report.to_string(),
+                "Read the tags of 0 of 3 files (1 no tag, 1 corrupt tag, 1 unreadable)"
+            );
+            let csv = report.to_csv();
+            assert!(csv.starts_with("path,kind,message\n"));
+            assert!(csv.contains(",corrupt tag,"));
+            assert!(csv.contains("corrupt, really.flac\""));
+        }
+
+        #[test]
+        fn save_writes_json_by_extension() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let report_path = dir.path().join("report.json");
+            let mut report = TagReadReport::default();
+            report.read(&dir.path().join("missing.mp3"));
+
+            // run
+            report.save(report_path.to_str().unwrap()).unwrap();
+
+            // verify
+            let json: serde_json::Value =
+                serde_json::from_str(&std::fs::read_to_string(&report_path).unwrap()).unwrap();
+            assert_eq!(json["problems"][0]["kind"], "unreadable");
+        }
+    }
+}
@@ -0,0 +1,1171 @@
+pub mod tags {
+    use std::{
+        fs::{self, File, FileTimes},
+        io::{self, Read, Seek, SeekFrom, Write},
+        ops::Range,
+        path::{Path, PathBuf},
+    };
+
+    use id3::{frame::Comment, Tag, TagLike, Version};
+
+    use crate::{
+        error::error::LibHelperError,
+        track::{
+            file_type::file_type::{detect_format, AudioFormat},
+            metadata::metadata::{
+                atoms, find_atom, split_vorbis_comments, TagField, MAX_HEADER_BYTES,
+            },
+        },
+    };
+
+    const FLAC_PADDING_BLOCK: u8 = 1;
+    const FLAC_VORBIS_COMMENT_BLOCK: u8 = 4;
+    /// FLAC metadata block lengths have 24 bits.
+    const MAX_FLAC_BLOCK_LENGTH: usize = 0xFF_FFFF;
+    /// Room left after the metadata of a FLAC file that has to be rewritten,
+    /// so later changes fit in place.
+    const FLAC_PADDING: usize = 4096;
+    /// Written as the vendor of Vorbis comments this tool creates.
+    const VORBIS_VENDOR: &str = "mixxx_libhelper";
+    /// Length of an Ogg page header before its lacing values.
+    const OGG_HEADER_LENGTH: usize = 27;
+    /// Set in the header type of a page that continues a packet of the previous one.
+    const OGG_CONTINUED_PACKET: u8 = 0x01;
+    /// CRC-32 of Ogg pages: polynomial 0x04C11DB7, not reflected, starting from 0.
+    const OGG_CRC_TABLE: [u32; 256] = ogg_crc_table();
+
+    /// An MP4 atom with its name and body.
+    type OwnedAtom = ([u8; 4], Vec<u8>);
+    /// The name of an atom in a file, the range of its body and of the whole atom.
+    type AtomPosition = ([u8; 4], Range<u64>, Range<u64>);
+
+    /// Whether the file's content is a format whose tags can be written: ID3
+    /// in MP3, WAV and AIFF files, Vorbis comments in FLAC, Ogg Vorbis and Opus
+    /// files and MP4 items.
+    pub fn supports_tag_writing(path: &Path) -> bool {
+        matches!(
+            detect_format(path),
+            Ok(Some(
+                AudioFormat::Mp3
+                    | AudioFormat::Wav
+                    | AudioFormat::Aiff
+                    | AudioFormat::Flac
+                    | AudioFormat::OggVorbis
+                    | AudioFormat::Opus
+                    | AudioFormat::Mp4
+            ))
+        )
+    }
+
+    /// Sets several fields in the tag of a file at once, adding a tag if it has
+    /// none. Everything else in the tag is kept, and an ID3 tag keeps its version.
+    /// MP4 files can only hold whole BPMs, so the BPM is rounded for them.
+    pub fn write_fields(path: &Path, fields: &[(TagField, String)]) -> Result<(), LibHelperError> {
+        let result = match detect_format(path) {
+            Ok(Some(AudioFormat::Mp3 | AudioFormat::Wav | AudioFormat::Aiff)) => {
+                write_id3(path, fields)
+            }
+            Ok(Some(AudioFormat::Flac)) => write_flac(path, fields),
+            Ok(Some(AudioFormat::OggVorbis | AudioFormat::Opus)) => write_ogg(path, fields),
+            Ok(Some(AudioFormat::Mp4)) => write_mp4(path, fields),
+            Ok(Some(format)) => Err(format!("writing tags of {format} files is not supported")),
+            Ok(None) => Err(String::from("not a known audio format")),
+            Err(err) => Err(err.to_string()),
+        };
+        result.map_err(|message| LibHelperError::TagWrite {
+            path: path.to_string_lossy().to_string(),
+            message,
+        })
+    }
+
+    /// Runs `write`, then sets the access and modification times of the file
+    /// back to what they were, so other programs do not take it as changed.
+    pub fn keep_file_times(
+        path: &Path,
+        write: impl FnOnce() -> Result<(), LibHelperError>,
+    ) -> Result<(), LibHelperError> {
+        let metadata = fs::metadata(path)?;
+        let times = FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?);
+        write()?;
+        File::options().write(true).open(path)?.set_times(times)?;
+
+        Ok(())
+    }
+
+    fn write_id3(path: &Path, fields: &[(TagField, String)]) -> Result<(), String> {
+        let mut tag = match Tag::read_from_path(path) {
+            Ok(tag) => tag,
+            Err(err) if matches!(err.kind, id3::ErrorKind::NoTag) => Tag::new(),
+            Err(err) => return Err(err.to_string()),
+        };
+        let version = match tag.version() {
+            Version::Id3v22 => Version::Id3v23,
+            version => version,
+        };
+        for (field, value) in fields {
+            match field {
+                TagField::Artist => tag.set_artist(value),
+                TagField::Title => tag.set_title(value),
+                TagField::Album => tag.set_album(value),
+                TagField::Genre => tag.set_genre(value),
+                TagField::Bpm => tag.set_text("TBPM", value),
+                TagField::Key => tag.set_text("TKEY", value),
+                TagField::Comment => {
+                    tag.remove_comment(Some(""), None);
+                    tag.add_frame(Comment {
+                        lang: String::from("eng"),
+                        description: String::new(),
+                        text: value.clone(),
+                    });
+                }
+                // ID3v2.3 has a year frame, later versions a recording time
+                TagField::Year if version == Version::Id3v24 => tag.set_text("TDRC", value),
+                TagField::Year => tag.set_text("TYER", value),
+            }
+        }
+        tag.write_to_path(path, version)
+            .map_err(|err| err.to_string())
+    }
+
+    /// Replaces the `VORBIS_COMMENT` block of a FLAC file. The metadata blocks
+    /// are written in place if the padding leaves room, otherwise the file is
+    /// rewritten with new padding.
+    fn write_flac(path: &Path, fields: &[(TagField, String)]) -> Result<(), String> {
+        let mut file = io::BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+        let mut magic = [0; 4];
+        read_exact(&mut file, &mut magic)?;
+        if &magic != b"fLaC" {
+            return Err(String::from("not a FLAC file"));
+        }
+        let mut blocks: Vec<(u8, Vec<u8>)> = vec![];
+        loop {
+            let mut header = [0; 4];
+            read_exact(&mut file, &mut header)?;
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+            let mut body = vec![0; length as usize];
+            read_exact(&mut file, &mut body)?;
+            blocks.push((header[0] & 0x7F, body));
+            if header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+        drop(file);
+        let old_length: usize = blocks.iter().map(|(_, body)| 4 + body.len()).sum();
+
+        let (vendor, comments) = match blocks
+            .iter()
+            .find(|(block_type, _)| *block_type == FLAC_VORBIS_COMMENT_BLOCK)
+        {
+            Some((_, body)) => split_vorbis_comments(body)?,
+            None => (String::from(VORBIS_VENDOR), vec![]),
+        };
+        let comment_block = join_vorbis_comments(&vendor, &set_vorbis_comments(comments, fields));
+        if comment_block.len() > MAX_FLAC_BLOCK_LENGTH {
+            return Err(String::from("the comments do not fit into a FLAC block"));
+        }
+
+        // the new comments take the place of the old ones, or follow STREAMINFO
+        let mut new_blocks: Vec<(u8, Vec<u8>)> = vec![];
+        let mut comment_block = Some(comment_block);
+        for (block_type, body) in blocks {
+            match block_type {
+                FLAC_PADDING_BLOCK => {}
+                FLAC_VORBIS_COMMENT_BLOCK => new_blocks.extend(
+                    comment_block
+                        .take()
+                        .map(|body| (FLAC_VORBIS_COMMENT_BLOCK, body)),
+                ),
+                _ => new_blocks.push((block_type, body)),
+            }
+        }
+        if let Some(body) = comment_block {
+            new_blocks.insert(new_blocks.len().min(1), (FLAC_VORBIS_COMMENT_BLOCK, body));
+        }
+
+        let new_length: usize = new_blocks.iter().map(|(_, body)| 4 + body.len()).sum();
+        let room = old_length.checked_sub(new_length + 4);
+        let fits_in_place =
+            new_length == old_length || room.is_some_and(|room| room <= MAX_FLAC_BLOCK_LENGTH);
+        if new_length != old_length {
+            let padding = room.filter(|_| fits_in_place).unwrap_or(FLAC_PADDING);
+            new_blocks.push((FLAC_PADDING_BLOCK, vec![0; padding]));
+        }
+
+        let mut metadata = b"fLaC".to_vec();
+        let block_count = new_blocks.len();
+        for (index, (block_type, body)) in new_blocks.iter().enumerate() {
+            let last_flag = if index + 1 == block_count { 0x80 } else { 0 };
+            metadata.push(last_flag | block_type);
+            metadata.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            metadata.extend_from_slice(body);
+        }
+
+        let old_range = 0..4 + old_length as u64;
+        if fits_in_place {
+            write_at(path, 0, &metadata)
+        } else {
+            replace_range(path, old_range, &metadata)
+        }
+    }
+
+    /// The Vorbis comment names a field is read from, the one it is written to first.
+    fn vorbis_names(field: TagField) -> &'static [&'static str] {
+        match field {
+            TagField::Artist => &["ARTIST"],
+            TagField::Title => &["TITLE"],
+            TagField::Album => &["ALBUM"],
+            TagField::Genre => &["GENRE"],
+            TagField::Bpm => &["BPM", "TEMPO"],
+            TagField::Key => &["INITIALKEY", "KEY"],
+            TagField::Comment => &["COMMENT", "DESCRIPTION"],
+            TagField::Year => &["DATE", "YEAR"],
+        }
+    }
+
+    /// Replaces all comments of each field with a single one holding the new
+    /// value, where the first of them was.
+    fn set_vorbis_comments(
+        mut comments: Vec<String>,
+        fields: &[(TagField, String)],
+    ) -> Vec<String> {
+        for (field, value) in fields {
+            let names = vorbis_names(*field);
+            let is_field = |comment: &String| {
+                comment.split_once('=').is_some_and(|(name, _)| {
+                    names.iter().any(|other| other.eq_ignore_ascii_case(name))
+                })
+            };
+            let position = comments.iter().position(is_field);
+            comments.retain(|comment| !is_field(comment));
+            let position = position.unwrap_or(comments.len());
+            comments.insert(position, format!("{}={value}", names[0]));
+        }
+
+        comments
+    }
+
+    fn join_vorbis_comments(vendor: &str, comments: &[String]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        data.extend_from_slice(vendor.as_bytes());
+        data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            data.extend_from_slice(comment.as_bytes());
+        }
+        data
+    }
+
+    /// A page of an Ogg file as it is stored.
+    struct OggPage {
+        data: Vec<u8>,
+    }
+
+    impl OggPage {
+        fn serial(&self) -> u32 {
+            u32::from_le_bytes(self.data[14..18].try_into().unwrap())
+        }
+
+        fn sequence(&self) -> u32 {
+            u32::from_le_bytes(self.data[18..22].try_into().unwrap())
+        }
+
+        fn lacing(&self) -> &[u8] {
+            &self.data[OGG_HEADER_LENGTH..OGG_HEADER_LENGTH + self.data[26] as usize]
+        }
+
+        fn body(&self) -> &[u8] {
+            &self.data[OGG_HEADER_LENGTH + self.data[26] as usize..]
+        }
+    }
+
+    /// Replaces the comment header, the second packet of the stream of an Ogg
+    /// Vorbis or Opus file. The header packets after the first page are laid
+    /// out in new pages, written in place if they take as much room as before.
+    /// Otherwise the file is rewritten, and if the number of pages changed, the
+    /// sequence numbers of the audio pages behind them are moved along.
+    fn write_ogg(path: &Path, fields: &[(TagField, String)]) -> Result<(), String> {
+        let mut file = io::BufReader::new(File::open(path).map_err(|err| err.to_string())?);
+        let mut pages: Vec<OggPage> = vec![];
+        let mut packets: Vec<Vec<u8>> = vec![];
+        let mut packet = vec![];
+        let mut header_count = None;
+        let mut headers_end = 0;
+        loop {
+            if headers_end > MAX_HEADER_BYTES {
+                return Err(String::from("the Ogg headers are too large"));
+            }
+            let page = read_ogg_page(&mut file)
+                .map_err(|err| err.to_string())?
+                .ok_or_else(|| String::from("file ends unexpectedly"))?;
+            if pages
+                .first()
+                .is_some_and(|first| first.serial() != page.serial())
+            {
+                return Err(String::from(
+                    "Ogg files with several streams are not supported",
+                ));
+            }
+            let mut offset = 0;
+            for &value in page.lacing() {
+                packet.extend_from_slice(&page.body()[offset..offset + value as usize]);
+                offset += value as usize;
+                if value < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+            headers_end += page.data.len() as u64;
+            pages.push(page);
+
+            let header_count = *header_count.get_or_insert(match packets.first() {
+                Some(first) if first.starts_with(b"\x01vorbis") => 3,
+                Some(first) if first.starts_with(b"OpusHead") => 2,
+                _ => return Err(String::from("not an Ogg Vorbis or Opus stream")),
+            });
+            if pages.len() == 1 && (packets.len() != 1 || !packet.is_empty()) {
+                return Err(String::from(
+                    "the first Ogg page holds more than one packet",
+                ));
+            }
+            if packets.len() >= header_count {
+                if packets.len() > header_count || !packet.is_empty() {
+                    return Err(String::from("the Ogg headers share a page with audio data"));
+                }
+                break;
+            }
+        }
+        drop(file);
+
+        let prefix: &[u8] = if packets[0].starts_with(b"OpusHead") {
+            b"OpusTags"
+        } else {
+            b"\x03vorbis"
+        };
+        let comments = packets[1]
+            .strip_prefix(prefix)
+            .ok_or_else(|| String::from("the second Ogg packet holds no comments"))?;
+        // data following the comments, such as the framing bit of Vorbis, is kept
+        let comments_end = vorbis_comments_end(comments)?;
+        let (vendor, old_comments) = split_vorbis_comments(&comments[..comments_end])?;
+        let mut comment_packet = prefix.to_vec();
+        comment_packet.extend_from_slice(&join_vorbis_comments(
+            &vendor,
+            &set_vorbis_comments(old_comments, fields),
+        ));
+        comment_packet.extend_from_slice(&comments[comments_end..]);
+        packets[1] = comment_packet;
+
+        let serial = pages[0].serial();
+        let new_pages = ogg_pages(serial, pages[0].sequence().wrapping_add(1), &packets[1..]);
+        let headers: Vec<u8> = new_pages.concat();
+        let range = pages[0].data.len() as u64..headers_end;
+        let delta = (new_pages.len() as u32).wrapping_sub(pages.len() as u32 - 1);
+        if delta != 0 {
+            replace_range_with(path, range, &headers, |source, target| {
+                copy_renumbered(source, target, serial, delta)
+            })
+        } else if headers.len() as u64 == range.end - range.start {
+            write_at(path, range.start, &headers)
+        } else {
+            replace_range(path, range, &headers)
+        }
+    }
+
+    /// Where the vendor string and the comments of a Vorbis comment block end.
+    fn vorbis_comments_end(data: &[u8]) -> Result<usize, String> {
+        let length_at = |position: usize| -> Result<usize, String> {
+            data.get(position..position + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+                .ok_or_else(|| String::from("invalid Vorbis comments"))
+        };
+        let mut position = 4 + length_at(0)?;
+        let count = length_at(position)?;
+        position += 4;
+        for _ in 0..count {
+            position += 4 + length_at(position)?;
+        }
+        if position > data.len() {
+            return Err(String::from("invalid Vorbis comments"));
+        }
+        Ok(position)
+    }
+
+    /// Reads the next page, `None` at the end of the file.
+    fn read_ogg_page(reader: &mut impl Read) -> io::Result<Option<OggPage>> {
+        let mut data = vec![0; OGG_HEADER_LENGTH];
+        if reader.read(&mut data[..1])? == 0 {
+            return Ok(None);
+        }
+        reader.read_exact(&mut data[1..])?;
+        if &data[0..4] != b"OggS" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an Ogg page",
+            ));
+        }
+        let lacing_start = data.len();
+        data.resize(lacing_start + data[26] as usize, 0);
+        reader.read_exact(&mut data[lacing_start..])?;
+        let body_length: usize = data[lacing_start..]
+            .iter()
+            .map(|&value| value as usize)
+            .sum();
+        let body_start = data.len();
+        data.resize(body_start + body_length, 0);
+        reader.read_exact(&mut data[body_start..])?;
+
+        Ok(Some(OggPage { data }))
+    }
+
+    /// Lays out header packets in pages of the stream `serial`, numbered from
+    /// `sequence`. Packets continue on the next page where one is full.
+    fn ogg_pages(serial: u32, sequence: u32, packets: &[Vec<u8>]) -> Vec<Vec<u8>> {
+        // a packet ends with the first segment shorter than 255 bytes, which may be empty
+        let mut segments: Vec<(&[u8], bool)> = vec![];
+        for packet in packets {
+            let mut chunks: Vec<&[u8]> = packet.chunks(255).collect();
+            if packet.len() % 255 == 0 {
+                chunks.push(&[]);
+            }
+            let last = chunks.len() - 1;
+            segments.extend(
+                chunks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, chunk)| (chunk, index == last)),
+            );
+        }
+
+        let mut pages = vec![];
+        let mut continued = false;
+        for (index, page_segments) in segments.chunks(255).enumerate() {
+            let ends_packet = page_segments.last().is_some_and(|(_, last)| *last);
+            // header pages have no position in the audio, pages ending no packet none at all
+            let granule_position: u64 = if ends_packet { 0 } else { u64::MAX };
+            let mut page = b"OggS\0".to_vec();
+            page.push(if continued { OGG_CONTINUED_PACKET } else { 0 });
+            page.extend_from_slice(&granule_position.to_le_bytes());
+            page.extend_from_slice(&serial.to_le_bytes());
+            page.extend_from_slice(&sequence.wrapping_add(index as u32).to_le_bytes());
+            page.extend_from_slice(&[0; 4]);
+            page.push(page_segments.len() as u8);
+            page.extend(page_segments.iter().map(|(chunk, _)| chunk.len() as u8));
+            for (chunk, _) in page_segments {
+                page.extend_from_slice(chunk);
+            }
+            set_ogg_crc(&mut page);
+            continued = !ends_packet;
+            pages.push(page);
+        }
+
+        pages
+    }
+
+    /// Copies the pages of `source` to `target`, moving the sequence numbers of
+    /// the pages of the stream `serial` by `delta`.
+    fn copy_renumbered(
+        source: &mut File,
+        target: &mut File,
+        serial: u32,
+        delta: u32,
+    ) -> io::Result<()> {
+        let mut reader = io::BufReader::new(source);
+        let mut writer = io::BufWriter::new(target);
+        while let Some(mut page) = read_ogg_page(&mut reader)? {
+            if page.serial() == serial {
+                let sequence = page.sequence().wrapping_add(delta);
+                page.data[18..22].copy_from_slice(&sequence.to_le_bytes());
+                set_ogg_crc(&mut page.data);
+            }
+            writer.write_all(&page.data)?;
+        }
+        writer.flush()
+    }
+
+    fn set_ogg_crc(page: &mut [u8]) {
+        page[22..26].fill(0);
+        let crc = ogg_crc(page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    fn ogg_crc(data: &[u8]) -> u32 {
+        data.iter().fold(0, |crc, &byte| {
+            (crc << 8) ^ OGG_CRC_TABLE[((crc >> 24) as u8 ^ byte) as usize]
+        })
+    }
+
+    const fn ogg_crc_table() -> [u32; 256] {
+        let mut table = [0; 256];
+        let mut index = 0;
+        while index < 256 {
+            let mut value = (index as u32) << 24;
+            let mut bit = 0;
+            while bit < 8 {
+                value = if value & 0x8000_0000 != 0 {
+                    (value << 1) ^ 0x04C1_1DB7
+                } else {
+                    value << 1
+                };
+                bit += 1;
+            }
+            table[index] = value;
+            index += 1;
+        }
+        table
+    }
+
+    /// Sets the items in `moov.udta.meta.ilst` of an MP4 file, adding the atoms
+    /// that are missing. Free space right after `moov` is used if it grows; if
+    /// there is not enough, the file is rewritten and the chunk offsets of the
+    /// audio data behind `moov` are moved along. Fragmented files are only
+    /// written in place, as their fragments hold absolute offsets as well.
+    fn write_mp4(path: &Path, fields: &[(TagField, String)]) -> Result<(), String> {
+        let mut file = File::open(path).map_err(|err| err.to_string())?;
+        let top_level = top_level_atoms(&mut file)?;
+        let moov_index = top_level
+            .iter()
+            .position(|(name, _, _)| name == b"moov")
+            .ok_or_else(|| String::from("no moov atom"))?;
+        let (_, moov_body, moov_range) = top_level[moov_index].clone();
+        // free space following moov can be taken up or left behind
+        let region_end = top_level[moov_index + 1..]
+            .iter()
+            .take_while(|(name, _, _)| name == b"free" || name == b"skip")
+            .last()
+            .map_or(moov_range.end, |(_, _, range)| range.end);
+        let region = moov_range.start..region_end;
+
+        if moov_body.end - moov_body.start > MAX_HEADER_BYTES {
+            return Err(String::from("moov atom has an invalid size"));
+        }
+        let mut moov = vec![0; (moov_body.end - moov_body.start) as usize];
+        file.seek(SeekFrom::Start(moov_body.start))
+            .map_err(|err| err.to_string())?;
+        read_exact(&mut file, &mut moov)?;
+        drop(file);
+
+        let mut new_moov = atom(b"moov", &set_mp4_items(&moov, fields)?);
+        let region_length = region.end - region.start;
+        let new_length = new_moov.len() as u64;
+        if new_length == region_length || new_length + 8 <= region_length {
+            if new_length < region_length {
+                new_moov.extend_from_slice(&atom(
+                    b"free",
+                    &vec![0; (region_length - new_length - 8) as usize],
+                ));
+            }
+            write_at(path, region.start, &new_moov)
+        } else if top_level.iter().any(|(name, _, _)| name == b"moof")
+            || find_atom(&moov, b"mvex")?.is_some()
+        {
+            Err(String::from(
+                "the tags of this fragmented MP4 file do not fit into the space they have",
+            ))
+        } else {
+            let delta = new_length as i64 - region_length as i64;
+            shift_chunk_offsets(&mut new_moov[8..], region.end, delta)?;
+            replace_range(path, region, &new_moov)
+        }
+    }
+
+    /// The position of each top-level atom of an MP4 file.
+    fn top_level_atoms(file: &mut File) -> Result<Vec<AtomPosition>, String> {
+        let file_length = file.metadata().map_err(|err| err.to_string())?.len();
+        let mut atoms = vec![];
+        let mut position = 0;
+        while position + 8 <= file_length {
+            file.seek(SeekFrom::Start(position))
+                .map_err(|err| err.to_string())?;
+            let mut header = [0; 8];
+            read_exact(file, &mut header)?;
+            let mut size = u64::from(u32::from_be_bytes([
+                header[0], header[1], header[2], header[3],
+            ]));
+            let mut header_length = 8;
+            if size == 1 {
+                let mut large_size = [0; 8];
+                read_exact(file, &mut large_size)?;
+                size = u64::from_be_bytes(large_size);
+                header_length = 16;
+            } else if size == 0 {
+                size = file_length - position;
+            }
+            // a corrupt large size can reach past the end of any file, and of u64
+            let end = position
+                .checked_add(size)
+                .filter(|end| size >= header_length && *end <= file_length)
+                .ok_or_else(|| String::from("invalid atom size"))?;
+            let name: [u8; 4] = header[4..8].try_into().unwrap();
+            atoms.push((name, position + header_length..end, position..end));
+            position = end;
+        }
+
+        Ok(atoms)
+    }
+
+    /// Sets the items in the `udta.meta.ilst` of a `moov` body, returning the new body.
+    fn set_mp4_items(moov: &[u8], fields: &[(TagField, String)]) -> Result<Vec<u8>, String> {
+        let mut moov = owned_atoms(moov)?;
+        let udta_index = child_index(&mut moov, b"udta", Vec::new);
+        let mut udta = owned_atoms(&moov[udta_index].1)?;
+        let meta_index = child_index(&mut udta, b"meta", new_meta);
+        // `meta` is a full box with version and flags, except in some QuickTime files
+        let meta_body = &udta[meta_index].1;
+        let prefix_length = if meta_body.get(4..8) == Some(b"hdlr") {
+            0
+        } else {
+            meta_body.len().min(4)
+        };
+        let prefix = meta_body[..prefix_length].to_vec();
+        let mut meta = owned_atoms(&meta_body[prefix_length..])?;
+        let ilst_index = child_index(&mut meta, b"ilst", Vec::new);
+        let mut items = owned_atoms(&meta[ilst_index].1)?;
+
+        for (field, value) in fields {
+            set_mp4_item(&mut items, *field, value)?;
+        }
+
+        meta[ilst_index].1 = join_atoms(&items);
+        udta[meta_index].1 = [prefix, join_atoms(&meta)].concat();
+        moov[udta_index].1 = join_atoms(&udta);
+        Ok(join_atoms(&moov))
+    }
+
+    /// Replaces the items of a field with one holding the new value, where the first of them was.
+    fn set_mp4_item(
+        items: &mut Vec<OwnedAtom>,
+        field: TagField,
+        value: &str,
+    ) -> Result<(), String> {
+        let text_item = |name: &[u8; 4]| (*name, data_atom(1, value.as_bytes()));
+        let item = match field {
+            TagField::Artist => text_item(b"\xa9ART"),
+            TagField::Title => text_item(b"\xa9nam"),
+            TagField::Album => text_item(b"\xa9alb"),
+            TagField::Genre => text_item(b"\xa9gen"),
+            TagField::Comment => text_item(b"\xa9cmt"),
+            TagField::Year => text_item(b"\xa9day"),
+            TagField::Bpm => {
+                let bpm = value
+                    .parse::<f64>()
+                    .ok()
+                    .map(f64::round)
+                    .filter(|bpm| (1.0..=f64::from(u16::MAX)).contains(bpm))
+                    .ok_or_else(|| format!("invalid BPM {value}"))?;
+                // type 21 is a big-endian signed integer
+                (*b"tmpo", data_atom(21, &(bpm as u16).to_be_bytes()))
+            }
+            TagField::Key => {
+                let mut body = atom(b"mean", b"\0\0\0\0com.apple.iTunes");
+                body.extend_from_slice(&atom(b"name", b"\0\0\0\0initialKey"));
+                body.extend_from_slice(&data_atom(1, value.as_bytes()));
+                (*b"----", body)
+            }
+        };
+
+        let is_field = |(name, body): &OwnedAtom| match field {
+            // the numeric genre would be read if the text genre was missing
+            TagField::Genre => name == b"\xa9gen" || name == b"gnre",
+            TagField::Key => {
+                name == b"----"
+                    && find_atom(body, b"name")
+                        .ok()
+                        .flatten()
+                        .and_then(|name| name.get(4..))
+                        .is_some_and(|name| name.eq_ignore_ascii_case(b"initialkey"))
+            }
+            _ => *name == item.0,
+        };
+        let position = items.iter().position(is_field);
+        items.retain(|item| !is_field(item));
+        let position = position.unwrap_or(items.len());
+        items.insert(position, item);
+
+        Ok(())
+    }
+
+    /// A `meta` body with the handler iTunes uses for its item list.
+    fn new_meta() -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend_from_slice(b"mdirappl");
+        hdlr.extend_from_slice(&[0; 9]);
+        let mut meta = vec![0; 4];
+        meta.extend_from_slice(&atom(b"hdlr", &hdlr));
+        meta
+    }
+
+    /// Adds `delta` to the chunk offsets in `stco` and `co64` atoms that point
+    /// at or behind `from`, which is where the audio data moved.
+    fn shift_chunk_offsets(data: &mut [u8], from: u64, delta: i64) -> Result<(), String> {
+        let children: Vec<([u8; 4], Range<usize>)> = atoms(data)?
+            .into_iter()
+            .map(|(name, body)| {
+                let start = body.as_ptr() as usize - data.as_ptr() as usize;
+                (name, start..start + body.len())
+            })
+            .collect();
+        for (name, range) in children {
+            let body = &mut data[range];
+            let width = match &name {
+                b"trak" | b"mdia" | b"minf" | b"stbl" => {
+                    shift_chunk_offsets(body, from, delta)?;
+                    continue;
+                }
+                b"stco" => 4,
+                b"co64" => 8,
+                _ => continue,
+            };
+            // version and flags, then the number of entries
+            let count = body
+                .get(4..8)
+                .map(|count| u32::from_be_bytes(count.try_into().unwrap()) as usize)
+                .ok_or_else(|| String::from("chunk offset table ends unexpectedly"))?;
+            let entries = body
+                .get_mut(8..8 + count * width)
+                .ok_or_else(|| String::from("chunk offset table ends unexpectedly"))?;
+            for entry in entries.chunks_exact_mut(width) {
+                let offset = entry
+                    .iter()
+                    .fold(0u64, |offset, &byte| (offset << 8) | u64::from(byte));
+                if offset < from {
+                    continue;
+                }
+                let shifted = offset
+                    .checked_add_signed(delta)
+                    .ok_or_else(|| String::from("invalid chunk offset"))?;
+                if width == 4 {
+                    let shifted = u32::try_from(shifted)
+                        .map_err(|_| String::from("chunk offsets would exceed 32 bits"))?;
+                    entry.copy_from_slice(&shifted.to_be_bytes());
+                } else {
+                    entry.copy_from_slice(&shifted.to_be_bytes());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn owned_atoms(data: &[u8]) -> Result<Vec<OwnedAtom>, String> {
+        Ok(atoms(data)?
+            .into_iter()
+            .map(|(name, body)| (name, body.to_vec()))
+            .collect())
+    }
+
+    /// The index of the first child atom with this name, added with the body
+    /// `create` gives if there is none.
+    fn child_index(
+        children: &mut Vec<OwnedAtom>,
+        name: &[u8; 4],
+        create: impl FnOnce() -> Vec<u8>,
+    ) -> usize {
+        match children.iter().position(|(other, _)| other == name) {
+            Some(index) => index,
+            None => {
+                children.push((*name, create()));
+                children.len() - 1
+            }
+        }
+    }
+
+    fn join_atoms(atoms: &[OwnedAtom]) -> Vec<u8> {
+        atoms
+            .iter()
+            .flat_map(|(name, body)| atom(name, body))
+            .collect()
+    }
+
+    fn atom(name: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut atom = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(name);
+        atom.extend_from_slice(body);
+        atom
+    }
+
+    /// A `data` atom with a type indicator and the default locale.
+    fn data_atom(type_indicator: u32, value: &[u8]) -> Vec<u8> {
+        let mut body = type_indicator.to_be_bytes().to_vec();
+        body.extend_from_slice(&[0; 4]);
+        body.extend_from_slice(value);
+        atom(b"data", &body)
+    }
+
+    fn write_at(path: &Path, position: u64, data: &[u8]) -> Result<(), String> {
+        let write = || -> io::Result<()> {
+            let mut file = File::options().write(true).open(path)?;
+            file.seek(SeekFrom::Start(position))?;
+            file.write_all(data)?;
+            file.sync_all()
+        };
+        write().map_err(|err| err.to_string())
+    }
+
+    /// Replaces a range of bytes of a file with `data`. The result is written to
+    /// a new file next to it, which replaces the old one once it is complete.
+    fn replace_range(path: &Path, range: Range<u64>, data: &[u8]) -> Result<(), String> {
+        replace_range_with(path, range, data, |source, target| {
+            io::copy(source, target).map(|_| ())
+        })
+    }
+
+    /// Like [`replace_range`], with `copy_rest` copying what follows the range.
+    fn replace_range_with(
+        path: &Path,
+        range: Range<u64>,
+        data: &[u8],
+        copy_rest: impl FnOnce(&mut File, &mut File) -> io::Result<()>,
+    ) -> Result<(), String> {
+        let temp_path = temp_path(path);
+        let write = || -> io::Result<()> {
+            let mut source = File::open(path)?;
+            let mut target = File::create(&temp_path)?;
+            io::copy(&mut (&mut source).take(range.start), &mut target)?;
+            target.write_all(data)?;
+            source.seek(SeekFrom::Start(range.end))?;
+            copy_rest(&mut source, &mut target)?;
+            target.sync_all()?;
+            fs::set_permissions(&temp_path, source.metadata()?.permissions())?;
+            fs::rename(&temp_path, path)
+        };
+        write().map_err(|err| {
+            let _ = fs::remove_file(&temp_path);
+            err.to_string()
+        })
+    }
+
+    fn temp_path(path: &Path) -> PathBuf {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!(".{file_name}.libhelper-tmp"))
+    }
+
+    fn read_exact(reader: &mut impl Read, buffer: &mut [u8]) -> Result<(), String> {
+        reader
+            .read_exact(buffer)
+            .map_err(|_| String::from("file ends unexpectedly"))
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        use super::*;
+        use crate::track::metadata::metadata::read_metadata;
+
+        fn flac_with_comments(comments: &[&str], padding: usize) -> Vec<u8> {
+            let comments: Vec<String> =
+                comments.iter().map(|comment| comment.to_string()).collect();
+            let comment_block = join_vorbis_comments("test", &comments);
+            let mut flac = b"fLaC".to_vec();
+            flac.extend_from_slice(&[0, 0, 0, 34]);
+            flac.extend_from_slice(&[0; 34]);
+            flac.push(FLAC_VORBIS_COMMENT_BLOCK);
+            flac.extend_from_slice(&(comment_block.len() as u32).to_be_bytes()[1..]);
+            flac.extend_from_slice(&comment_block);
+            flac.push(0x80 | FLAC_PADDING_BLOCK);
+            flac.extend_from_slice(&(padding as u32).to_be_bytes()[1..]);
+            flac.extend_from_slice(&vec![0; padding]);
+            flac.extend_from_slice(b"AUDIO");
+            flac
+        }
+
+        /// An Ogg file with the header packets, the first on a page of its own,
+        /// followed by a page of audio.
+        fn ogg_with_headers(headers: &[Vec<u8>]) -> Vec<u8> {
+            let mut ogg = ogg_pages(7, 0, &headers[..1]).concat();
+            ogg.extend(ogg_pages(7, 1, &headers[1..]).concat());
+            ogg.extend(ogg_pages(7, 2, &[b"AUDIO".to_vec()]).concat());
+            ogg
+        }
+
+        /// The sequence numbers of the pages of an Ogg file, checking their CRCs.
+        fn ogg_sequence_numbers(mut ogg: &[u8]) -> Vec<u32> {
+            let mut sequence_numbers = vec![];
+            while let Some(page) = read_ogg_page(&mut ogg).unwrap() {
+                let mut checked = page.data.clone();
+                set_ogg_crc(&mut checked);
+                assert_eq!(checked, page.data);
+                sequence_numbers.push(page.sequence());
+            }
+            sequence_numbers
+        }
+
+        /// The chunk offsets in `moov.trak.mdia.minf.stbl.stco`.
+        fn chunk_offsets(mp4: &[u8]) -> Vec<u32> {
+            let mut atom = find_atom(mp4, b"moov").unwrap().unwrap();
+            for name in [b"trak", b"mdia", b"minf", b"stbl", b"stco"] {
+                atom = find_atom(atom, name).unwrap().unwrap();
+            }
+            atom[8..]
+                .chunks_exact(4)
+                .map(|entry| u32::from_be_bytes(entry.try_into().unwrap()))
+                .collect()
+        }
+
+        #[test]
+        fn write_fields_adds_tag_to_untagged_file() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.mp3");
+            std::fs::write(&path, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+
+            // run
+            write_fields(&path, &[(TagField::Genre, String::from("Trance"))]).unwrap();
+
+            // verify
+            let tag = Tag::read_from_path(&path).unwrap();
+            assert_eq!(tag.genre(), Some("Trance"));
+            assert!(supports_tag_writing(&path));
+            assert!(!supports_tag_writing(Path::new("/does/not/exist.mp3")));
+        }
+
+        #[test]
+        fn write_fields_replaces_comment() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.mp3");
+            std::fs::write(&path, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+            write_fields(&path, &[(TagField::Comment, String::from("old"))]).unwrap();
+
+            // run
+            write_fields(
+                &path,
+                &[
+                    (TagField::Comment, String::from("Great intro")),
+                    (TagField::Key, String::from("8A")),
+                    (TagField::Year, String::from("1999")),
+                ],
+            )
+            .unwrap();
+
+            // verify
+            let tag = Tag::read_from_path(&path).unwrap();
+            let comments: Vec<&str> = tag
+                .comments()
+                .map(|comment| comment.text.as_str())
+                .collect();
+            assert_eq!(comments, vec!["Great intro"]);
+            let text = |id| tag.get(id).and_then(|frame| frame.content().text());
+            assert_eq!(text("TKEY"), Some("8A"));
+            assert_eq!(text("TDRC"), Some("1999"));
+        }
+
+        #[test]
+        fn write_fields_replaces_flac_comments_in_place() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.flac");
+            let flac = flac_with_comments(&["tempo=92", "GENRE=Trance", "BPM=92"], 100);
+            std::fs::write(&path, &flac).unwrap();
+
+            // run
+            write_fields(&path, &[(TagField::Bpm, String::from("138"))]).unwrap();
+
+            // verify
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(written.len(), flac.len());
+            assert!(written.ends_with(b"AUDIO"));
+            let metadata = read_metadata(&path).unwrap();
+            assert_eq!(metadata.bpm, Some(138.0));
+            assert_eq!(metadata.genre.as_deref(), Some("Trance"));
+        }
+
+        #[test]
+        fn write_fields_rewrites_flac_without_room() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.flac");
+            std::fs::write(&path, flac_with_comments(&["GENRE=Trance"], 0)).unwrap();
+            let comment = "x".repeat(500);
+
+            // run
+            write_fields(&path, &[(TagField::Comment, comment.clone())]).unwrap();
+
+            // verify
+            assert!(std::fs::read(&path).unwrap().ends_with(b"AUDIO"));
+            let metadata = read_metadata(&path).unwrap();
+            assert_eq!(metadata.comment, Some(comment));
+            assert_eq!(metadata.genre.as_deref(), Some("Trance"));
+        }
+
+        #[test]
+        fn ogg_crc_matches_check_value() {
+            // run
+            let crc = ogg_crc(b"123456789");
+
+            // verify
+            // the check value of CRC-32/CKSUM, 0x765E7680, before its final inversion
+            assert_eq!(crc, 0x89A1_897F);
+        }
+
+        #[test]
+        fn write_fields_replaces_ogg_vorbis_comments() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.ogg");
+            let comments = vec![String::from("GENRE=Trance"), String::from("BPM=92")];
+            let mut comment_packet = b"\x03vorbis".to_vec();
+            comment_packet.extend_from_slice(&join_vorbis_comments("test", &comments));
+            // the framing bit
+            comment_packet.push(1);
+            let setup_packet = b"\x05vorbis setup".to_vec();
+            let ogg = ogg_with_headers(&[
+                b"\x01vorbis identification".to_vec(),
+                comment_packet,
+                setup_packet.clone(),
+            ]);
+            std::fs::write(&path, &ogg).unwrap();
+
+            // run
+            write_fields(&path, &[(TagField::Bpm, String::from("138"))]).unwrap();
+
+            // verify
+            let written = std::fs::read(&path).unwrap();
+            assert_eq!(written.len(), ogg.len() + 1);
+            assert!(written.ends_with(b"AUDIO"));
+            assert_eq!(ogg_sequence_numbers(&written), vec![0, 1, 2]);
+            let metadata = read_metadata(&path).unwrap();
+            assert_eq!(metadata.bpm, Some(138.0));
+            assert_eq!(metadata.genre.as_deref(), Some("Trance"));
+            let mut pages = written.as_slice();
+            read_ogg_page(&mut pages).unwrap();
+            let header_page = read_ogg_page(&mut pages).unwrap().unwrap();
+            assert!(header_page
+                .body()
+                .ends_with(&[&[1], setup_packet.as_slice()].concat()));
+        }
+
+        #[test]
+        fn write_fields_renumbers_opus_pages_when_comments_grow() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.opus");
+            let mut tags = b"OpusTags".to_vec();
+            tags.extend_from_slice(&join_vorbis_comments(
+                "test",
+                &[String::from("GENRE=House")],
+            ));
+            std::fs::write(&path, ogg_with_headers(&[b"OpusHead".to_vec(), tags])).unwrap();
+            // more than the 255 segments of 255 bytes a single page holds
+            let comment = "x".repeat(70_000);
+
+            // run
+            write_fields(&path, &[(TagField::Comment, comment.clone())]).unwrap();
+
+            // verify
+            let written = std::fs::read(&path).unwrap();
+            assert!(written.ends_with(b"AUDIO"));
+            assert_eq!(ogg_sequence_numbers(&written), vec![0, 1, 2, 3]);
+            let metadata = read_metadata(&path).unwrap();
+            assert_eq!(metadata.comment, Some(comment));
+            assert_eq!(metadata.genre.as_deref(), Some("House"));
+            assert!(supports_tag_writing(&path));
+        }
+
+        #[test]
+        fn write_fields_moves_mp4_chunk_offsets_along() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.m4a");
+            let ftyp = atom(b"ftyp", b"M4A \0\0\0\0");
+            let stco_offset = |offset: u32| {
+                let mut stco = vec![0, 0, 0, 0, 0, 0, 0, 1];
+                stco.extend_from_slice(&offset.to_be_bytes());
+                let mut atom_body = atom(b"stco", &stco);
+                for name in [b"stbl", b"minf", b"mdia", b"trak"] {
+                    atom_body = atom(name, &atom_body);
+                }
+                atom(b"moov", &atom_body)
+            };
+            let moov_length = stco_offset(0).len();
+            // the audio data follows the 8 byte header of mdat
+            let audio_offset = (ftyp.len() + moov_length + 8) as u32;
+            let mut mp4 = ftyp;
+            mp4.extend_from_slice(&stco_offset(audio_offset));
+            mp4.extend_from_slice(&atom(b"mdat", b"AUDIO"));
+            std::fs::write(&path, &mp4).unwrap();
+
+            // run
+            write_fields(&path, &[(TagField::Bpm, String::from("137.6"))]).unwrap();
+
+            // verify
+            let written = std::fs::read(&path).unwrap();
+            let offset = chunk_offsets(&written)[0] as usize;
+            assert_eq!(&written[offset..offset + 5], b"AUDIO");
+            assert_eq!(read_metadata(&path).unwrap().bpm, Some(138.0));
+        }
+
+        #[test]
+        fn write_fields_rejects_mp4_atom_larger_than_possible() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.m4a");
+            let mut mp4 = atom(b"ftyp", b"M4A \0\0\0\0");
+            mp4.extend_from_slice(&1u32.to_be_bytes());
+            mp4.extend_from_slice(b"moov");
+            mp4.extend_from_slice(&(u64::MAX - 4).to_be_bytes());
+            std::fs::write(&path, &mp4).unwrap();
+
+            // run
+            let result = write_fields(&path, &[(TagField::Bpm, String::from("128"))]);
+
+            // verify
+            assert!(matches!(
+                result,
+                Err(LibHelperError::TagWrite { message, .. }) if message == "invalid atom size"
+            ));
+        }
+
+        #[test]
+        fn write_fields_refuses_to_move_fragments_of_mp4() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.m4a");
+            let mut mp4 = atom(b"ftyp", b"M4A \0\0\0\0");
+            mp4.extend_from_slice(&atom(b"moov", &atom(b"mvex", &atom(b"trex", &[0; 24]))));
+            mp4.extend_from_slice(&atom(b"moof", &atom(b"mfhd", &[0; 8])));
+            mp4.extend_from_slice(&atom(b"mdat", b"AUDIO"));
+            std::fs::write(&path, &mp4).unwrap();
+
+            // run
+            let result = write_fields(&path, &[(TagField::Bpm, String::from("128"))]);
+
+            // verify
+            assert!(matches!(
+                result,
+                Err(LibHelperError::TagWrite { message, .. }) if message.contains("fragmented")
+            ));
+            assert_eq!(std::fs::read(&path).unwrap(), mp4);
+        }
+
+        #[test]
+        fn keep_file_times_restores_modification_time() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.mp3");
+            std::fs::write(&path, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+            let modified = UNIX_EPOCH + Duration::from_secs(1_500_000_000);
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+
+            // run
+            keep_file_times(&path, || {
+                write_fields(&path, &[(TagField::Bpm, String::from("128"))])
+            })
+            .unwrap();
+
+            // verify
+            assert_eq!(
+                std::fs::metadata(&path).unwrap().modified().unwrap(),
+                modified
+            );
+            assert_eq!(read_metadata(&path).unwrap().bpm, Some(128.0));
+        }
+    }
+}
@@ -1,13 +1,138 @@
 pub mod track {
 
-    use id3::Tag;
+    use rusqlite::Row;
 
-    #[derive(Clone, Debug)]
+    use crate::db_schema::from_row::from_row::{integer, real, select_columns, text, FromRow};
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+    use crate::track::metadata::metadata::TrackMetadata;
+
+    /// The `library` columns a [`Track`] is read from. The location comes from `track_locations`.
+    pub const LIBRARY_COLUMNS: &[&str] = &[
+        "id",
+        "artist",
+        "title",
+        "album",
+        "album_artist",
+        "year",
+        "genre",
+        "comment",
+        "key",
+        "bpm",
+        "bpm_lock",
+        "duration",
+        "bitrate",
+        "samplerate",
+        "filetype",
+        "rating",
+        "color",
+        "timesplayed",
+        "last_played_at",
+        "datetime_added",
+        "mixxx_deleted",
+        "replaygain",
+        "replaygain_peak",
+        "coverart_source",
+        "coverart_type",
+        "coverart_location",
+        "coverart_hash",
+    ];
+
+    /// A row of the Mixxx `library` table joined with its `track_locations` row.
+    ///
+    /// Columns that are `NULL` or missing from the schema are `None`, except for
+    /// `genre` and `bpm`, which are empty and `0.0` for tracks without them.
+    #[derive(Clone, Debug, Default)]
     pub struct Track {
         pub id: usize,
         pub bpm: f64,
         pub genre: String,
         pub location: String,
-        pub id3: Option<Tag>,
+        pub artist: Option<String>,
+        pub title: Option<String>,
+        pub album: Option<String>,
+        pub album_artist: Option<String>,
+        pub year: Option<String>,
+        pub comment: Option<String>,
+        pub key: Option<String>,
+        /// Whether the BPM was locked in Mixxx
+        pub bpm_lock: bool,
+        /// In seconds
+        pub duration: Option<f64>,
+        /// In kbit/s
+        pub bitrate: Option<u32>,
+        /// In Hz
+        pub samplerate: Option<u32>,
+        /// The file extension Mixxx recorded, e.g. `mp3`
+        pub filetype: Option<String>,
+        /// 0 to 5 stars
+        pub rating: Option<u8>,
+        /// RGB value of the track color
+        pub color: Option<u32>,
+        pub timesplayed: Option<u32>,
+        pub last_played_at: Option<String>,
+        pub datetime_added: Option<String>,
+        /// Removed from the library in Mixxx, but kept for its history
+        pub mixxx_deleted: bool,
+        pub replaygain: Option<f64>,
+        pub replaygain_peak: Option<f64>,
+        pub cover_art: CoverArt,
+        /// Read from the file's tags, `None` until read or if the file has no tag
+        pub metadata: Option<TrackMetadata>,
+    }
+
+    /// Where Mixxx found the cover of a track, as stored in the `coverart_*` columns.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct CoverArt {
+        /// Whether Mixxx guessed the cover, or the user picked it
+        pub source: Option<i64>,
+        /// Whether the cover is embedded in the file's tags or a separate file
+        pub kind: Option<i64>,
+        pub location: Option<String>,
+        pub hash: Option<i64>,
+    }
+
+    impl FromRow for Track {
+        fn select_list(db: &MixxxDb) -> String {
+            let mut columns = select_columns(db, "library", "l", LIBRARY_COLUMNS);
+            columns.push(String::from("tl.location AS track_location"));
+            columns.join(", ")
+        }
+
+        fn from_row(row: &Row) -> rusqlite::Result<Track> {
+            Ok(Track {
+                id: row.get("id")?,
+                bpm: real(row, "bpm")?.unwrap_or_default(),
+                genre: text(row, "genre")?.unwrap_or_default(),
+                location: row.get("track_location")?,
+                artist: text(row, "artist")?,
+                title: text(row, "title")?,
+                album: text(row, "album")?,
+                album_artist: text(row, "album_artist")?,
+                year: text(row, "year")?,
+                comment: text(row, "comment")?,
+                key: text(row, "key")?,
+                bpm_lock: integer::<i64>(row, "bpm_lock")?.is_some_and(|lock| lock != 0),
+                duration: real(row, "duration")?,
+                bitrate: integer(row, "bitrate")?,
+                samplerate: integer(row, "samplerate")?,
+                filetype: text(row, "filetype")?,
+                rating: integer(row, "rating")?,
+                color: integer(row, "color")?,
+                timesplayed: integer(row, "timesplayed")?,
+                last_played_at: text(row, "last_played_at")?,
+                datetime_added: text(row, "datetime_added")?,
+                mixxx_deleted: integer::<i64>(row, "mixxx_deleted")?
+                    .is_some_and(|deleted| deleted != 0),
+                replaygain: real(row, "replaygain")?,
+                replaygain_peak: real(row, "replaygain_peak")?,
+                cover_art: CoverArt {
+                    source: integer(row, "coverart_source")?,
+                    kind: integer(row, "coverart_type")?,
+                    location: text(row, "coverart_location")?,
+                    hash: integer(row, "coverart_hash")?,
+                },
+                metadata: None,
+            })
+        }
     }
 }
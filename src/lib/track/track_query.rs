@@ -0,0 +1,262 @@
+pub mod track_query {
+    use rusqlite::types::Value;
+
+    use crate::db_schema::from_row::from_row::FromRow;
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+    use crate::error::error::LibHelperError;
+    use crate::track::track::track::Track;
+
+    /// Loads [`Track`]s from the library, narrowed down by filters that all have to match.
+    /// Filters are set by chaining, e.g.
+    /// `TrackQuery::new().genre("Trance").bpm_between(130.0, 145.0).load(&db)`.
+    #[derive(Clone, Debug, Default)]
+    pub struct TrackQuery {
+        ids: Option<Vec<usize>>,
+        genre: Option<String>,
+        bpm_range: Option<(f64, f64)>,
+        directory: Option<String>,
+        crate_name: Option<String>,
+        playlist: Option<String>,
+        include_deleted: bool,
+        limit: Option<usize>,
+    }
+
+    impl TrackQuery {
+        /// All tracks that were not deleted in Mixxx.
+        pub fn new() -> TrackQuery {
+            TrackQuery::default()
+        }
+
+        pub fn ids(mut self, ids: &[usize]) -> TrackQuery {
+            self.ids = Some(ids.to_vec());
+            self
+        }
+
+        /// Tracks whose genre is `genre`, ignoring case.
+        pub fn genre(mut self, genre: &str) -> TrackQuery {
+            self.genre = Some(genre.to_string());
+            self
+        }
+
+        /// Tracks with a BPM of at least `min` and below `max`, leaving out unanalyzed ones.
+        pub fn bpm_between(mut self, min: f64, max: f64) -> TrackQuery {
+            self.bpm_range = Some((min, max));
+            self
+        }
+
+        /// Tracks whose file is in `directory`, including subdirectories.
+        pub fn directory(mut self, directory: &str) -> TrackQuery {
+            self.directory = Some(directory.trim_end_matches('/').to_string());
+            self
+        }
+
+        pub fn in_crate(mut self, crate_name: &str) -> TrackQuery {
+            self.crate_name = Some(crate_name.to_string());
+            self
+        }
+
+        pub fn in_playlist(mut self, playlist: &str) -> TrackQuery {
+            self.playlist = Some(playlist.to_string());
+            self
+        }
+
+        /// Also load the tracks that were deleted in Mixxx.
+        pub fn include_deleted(mut self, include_deleted: bool) -> TrackQuery {
+            self.include_deleted = include_deleted;
+            self
+        }
+
+        /// At most `limit` tracks, the ones with the lowest ids.
+        pub fn limit(mut self, limit: usize) -> TrackQuery {
+            self.limit = Some(limit);
+            self
+        }
+
+        /// The matching tracks, ordered by id.
+        pub fn load(&self, db: &MixxxDb) -> Result<Vec<Track>, LibHelperError> {
+            db.require_columns("library", &["id", "location"])?;
+            db.require_columns("track_locations", &["id", "location"])?;
+
+            let mut query = format!(
+                "SELECT {} FROM library l
+                 INNER JOIN track_locations tl
+                 ON tl.id = l.location
+                 WHERE 1",
+                Track::select_list(db)
+            );
+            let mut params: Vec<Value> = vec![];
+
+            if !self.include_deleted && db.has_column("library", "mixxx_deleted") {
+                query.push_str(" AND l.mixxx_deleted IS NOT 1");
+            }
+            if let Some(ids) = &self.ids {
+                let placeholders: Vec<String> = ids
+                    .iter()
+                    .map(|id| {
+                        params.push(Value::Integer(*id as i64));
+                        format!("?{}", params.len())
+                    })
+                    .collect();
+                query.push_str(&format!(" AND l.id IN ({})", placeholders.join(", ")));
+            }
+            if let Some(genre) = &self.genre {
+                db.require_columns("library", &["genre"])?;
+                params.push(Value::Text(genre.clone()));
+                query.push_str(&format!(" AND l.genre = ?{} COLLATE NOCASE", params.len()));
+            }
+            if let Some((min, max)) = self.bpm_range {
+                db.require_columns("library", &["bpm"])?;
+                params.push(Value::Real(min));
+                params.push(Value::Real(max));
+                query.push_str(&format!(
+                    " AND l.bpm > 0 AND l.bpm >= ?{} AND l.bpm < ?{}",
+                    params.len() - 1,
+                    params.len()
+                ));
+            }
+            if let Some(directory) = &self.directory {
+                db.require_columns("track_locations", &["directory"])?;
+                params.push(Value::Text(directory.clone()));
+                params.push(Value::Text(format!("{directory}/")));
+                query.push_str(&format!(
+                    " AND (tl.directory = ?{} OR substr(tl.directory, 1, length(?{})) = ?{})",
+                    params.len() - 1,
+                    params.len(),
+                    params.len()
+                ));
+            }
+            if let Some(crate_name) = &self.crate_name {
+                db.require_columns("crates", &["id", "name"])?;
+                db.require_columns("crate_tracks", &["crate_id", "track_id"])?;
+                params.push(Value::Text(crate_name.clone()));
+                query.push_str(&format!(
+                    " AND l.id IN (SELECT ct.track_id FROM crate_tracks ct
+                       INNER JOIN crates c ON c.id = ct.crate_id
+                       WHERE c.name = ?{})",
+                    params.len()
+                ));
+            }
+            if let Some(playlist) = &self.playlist {
+                db.require_columns("Playlists", &["id", "name"])?;
+                db.require_columns("PlaylistTracks", &["playlist_id", "track_id"])?;
+                params.push(Value::Text(playlist.clone()));
+                query.push_str(&format!(
+                    " AND l.id IN (SELECT pt.track_id FROM PlaylistTracks pt
+                       INNER JOIN Playlists p ON p.id = pt.playlist_id
+                       WHERE p.name = ?{})",
+                    params.len()
+                ));
+            }
+            query.push_str(" ORDER BY l.id");
+            if let Some(limit) = self.limit {
+                query.push_str(&format!(" LIMIT {limit}"));
+            }
+
+            let mut stmt = db.connection().prepare(&query)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(params), Track::from_row)?;
+            Ok(rows.collect::<Result<Vec<Track>, _>>()?)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_mixxx_db};
+
+        fn loaded_ids(db: &MixxxDb, query: TrackQuery) -> Vec<usize> {
+            query
+                .load(db)
+                .unwrap()
+                .iter()
+                .map(|track| track.id)
+                .collect()
+        }
+
+        #[test]
+        fn load_maps_the_whole_row() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/a.mp3", "Trance", 138.0);
+            db.connection()
+                .execute_batch(
+                    "UPDATE library SET artist = 'Artist', title = 'Title', album_artist = '',
+                       duration = 301.5, bitrate = 320, samplerate = '44100', rating = 4,
+                       color = 16711680, timesplayed = 3, key = 'Am', bpm_lock = 1,
+                       datetime_added = '2024-05-01 20:00:00', filetype = 'mp3'
+                     WHERE id = 1;",
+                )
+                .unwrap();
+
+            // run
+            let tracks = TrackQuery::new().load(&db).unwrap();
+
+            // verify
+            let track = &tracks[0];
+            assert_eq!(track.location, "/music/a.mp3");
+            assert_eq!(track.genre, "Trance");
+            assert_eq!(track.bpm, 138.0);
+            assert_eq!(track.artist.as_deref(), Some("Artist"));
+            assert_eq!(track.title.as_deref(), Some("Title"));
+            assert_eq!(track.album_artist, None);
+            assert_eq!(track.duration, Some(301.5));
+            assert_eq!(track.bitrate, Some(320));
+            assert_eq!(track.samplerate, Some(44100));
+            assert_eq!(track.rating, Some(4));
+            assert_eq!(track.color, Some(0xff0000));
+            assert_eq!(track.timesplayed, Some(3));
+            assert_eq!(track.key.as_deref(), Some("Am"));
+            assert!(track.bpm_lock);
+            assert!(!track.mixxx_deleted);
+            assert_eq!(track.datetime_added.as_deref(), Some("2024-05-01 20:00:00"));
+            assert_eq!(track.filetype.as_deref(), Some("mp3"));
+            // not in the test schema
+            assert_eq!(track.replaygain, None);
+            assert_eq!(track.cover_art, Default::default());
+        }
+
+        #[test]
+        fn load_combines_filters() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/trance/a.mp3", "Trance", 138.0);
+            insert_track(db.connection(), 2, "/music/trance/b.mp3", "trance", 150.0);
+            insert_track(db.connection(), 3, "/music/trance/c.mp3", "Trance", 0.0);
+            insert_track(db.connection(), 4, "/music/house/d.mp3", "House", 124.0);
+            insert_track(db.connection(), 5, "/music/trance/e.mp3", "Trance", 140.0);
+            db.connection()
+                .execute_batch(
+                    "UPDATE library SET mixxx_deleted = 1 WHERE id = 5;
+                     INSERT INTO crates (id, name) VALUES (1, 'Peak time');
+                     INSERT INTO crate_tracks (crate_id, track_id) VALUES (1, 2), (1, 4);",
+                )
+                .unwrap();
+
+            // run & verify
+            assert_eq!(loaded_ids(&db, TrackQuery::new()), vec![1, 2, 3, 4]);
+            assert_eq!(
+                loaded_ids(&db, TrackQuery::new().include_deleted(true).limit(4)),
+                vec![1, 2, 3, 4]
+            );
+            assert_eq!(
+                loaded_ids(
+                    &db,
+                    TrackQuery::new().genre("TRANCE").bpm_between(100.0, 150.0)
+                ),
+                vec![1]
+            );
+            assert_eq!(
+                loaded_ids(
+                    &db,
+                    TrackQuery::new()
+                        .directory("/music/trance/")
+                        .include_deleted(true)
+                ),
+                vec![1, 2, 3, 5]
+            );
+            assert_eq!(
+                loaded_ids(&db, TrackQuery::new().in_crate("Peak time").ids(&[2, 3])),
+                vec![2]
+            );
+        }
+    }
+}
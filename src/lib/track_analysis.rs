@@ -0,0 +1,2 @@
+pub mod audio_decode;
+pub mod tempo;
@@ -0,0 +1,132 @@
+pub mod audio_decode {
+    use std::path::Path;
+
+    use gstreamer as gst;
+    use gstreamer::prelude::*;
+
+    use crate::error::error::LibHelperError;
+    use crate::track_fingerprinting::read_control::read_control::CancelToken;
+
+    /// Low enough to decode quickly, high enough to hear hi-hats.
+    pub const ANALYSIS_SAMPLE_RATE: u32 = 11025;
+
+    /// How long a pull waits for a sample before the bus is checked again.
+    /// Errors end no pull, so a pull without a timeout would wait forever on them.
+    const PULL_TIMEOUT: gst::ClockTime = gst::ClockTime::from_mseconds(100);
+
+    /// Decodes up to `max_seconds` of a file to mono samples at
+    /// [`ANALYSIS_SAMPLE_RATE`] with whatever GStreamer plugins are installed.
+    pub fn decode_mono(path: &Path, max_seconds: u32) -> Result<Vec<f32>, LibHelperError> {
+        decode_mono_with(path, max_seconds, &CancelToken::default())
+    }
+
+    /// Like [`decode_mono`], stopping with an error once `cancel` is triggered.
+    pub fn decode_mono_with(
+        path: &Path,
+        max_seconds: u32,
+        cancel: &CancelToken,
+    ) -> Result<Vec<f32>, LibHelperError> {
+        let decode_error = |message: String| LibHelperError::AudioDecode {
+            path: path.to_string_lossy().to_string(),
+            message,
+        };
+
+        gst::init().map_err(|err| decode_error(err.to_string()))?;
+        let uri =
+            gst::glib::filename_to_uri(path, None).map_err(|err| decode_error(err.to_string()))?;
+        let pipeline = gst::parse::launch(&format!(
+            "uridecodebin name=source ! audioconvert ! audioresample
+             ! audio/x-raw,format=F32LE,channels=1,rate={ANALYSIS_SAMPLE_RATE}
+             ! appsink name=sink sync=false"
+        ))
+        .map_err(|err| decode_error(err.to_string()))?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| decode_error(String::from("not a pipeline")))?;
+        let (Some(source), Some(sink)) = (pipeline.by_name("source"), pipeline.by_name("sink"))
+        else {
+            return Err(decode_error(String::from("incomplete pipeline")));
+        };
+        source.set_property("uri", uri.as_str());
+
+        let result = pull_samples(&pipeline, &sink, max_seconds, cancel);
+        let _ = pipeline.set_state(gst::State::Null);
+        result.map_err(decode_error)
+    }
+
+    fn pull_samples(
+        pipeline: &gst::Pipeline,
+        sink: &gst::Element,
+        max_seconds: u32,
+        cancel: &CancelToken,
+    ) -> Result<Vec<f32>, String> {
+        pipeline
+            .set_state(gst::State::Playing)
+            .map_err(|err| err.to_string())?;
+
+        let bus = pipeline
+            .bus()
+            .ok_or_else(|| String::from("pipeline without bus"))?;
+        let max_samples = (max_seconds * ANALYSIS_SAMPLE_RATE) as usize;
+        let mut samples: Vec<f32> = vec![];
+        let mut at_end = false;
+        while samples.len() < max_samples {
+            if cancel.is_cancelled() {
+                return Err(String::from("cancelled"));
+            }
+            while let Some(message) =
+                bus.pop_filtered(&[gst::MessageType::Error, gst::MessageType::Eos])
+            {
+                match message.view() {
+                    gst::MessageView::Error(err) => return Err(err.error().to_string()),
+                    _ => at_end = true,
+                }
+            }
+            // `None` once the timeout passed, or at the end with no samples left
+            let sample = sink.emit_by_name::<Option<gst::Sample>>(
+                "try-pull-sample",
+                &[&PULL_TIMEOUT.nseconds()],
+            );
+            let Some(sample) = sample else {
+                if at_end {
+                    break;
+                }
+                continue;
+            };
+            let Some(buffer) = sample.buffer() else {
+                continue;
+            };
+            let map = buffer.map_readable().map_err(|err| err.to_string())?;
+            samples.extend(
+                map.as_slice()
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+            );
+        }
+
+        if samples.is_empty() {
+            return Err(String::from("no audio decoded"));
+        }
+
+        samples.truncate(max_samples);
+        Ok(samples)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn decode_mono_fails_on_non_audio_file() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("notes.mp3");
+            std::fs::write(&path, "these are no audio frames".repeat(100)).unwrap();
+
+            // run
+            let result = decode_mono(&path, 10);
+
+            // verify
+            assert!(matches!(result, Err(LibHelperError::AudioDecode { .. })));
+        }
+    }
+}
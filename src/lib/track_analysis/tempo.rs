@@ -0,0 +1,182 @@
+pub mod tempo {
+    /// Samples per onset frame, about 11.6 ms at the analysis rate.
+    const HOP_SIZE: usize = 128;
+    const MIN_BPM: f64 = 50.0;
+    const MAX_BPM: f64 = 220.0;
+    /// How many multiples of a beat period count towards its support,
+    /// so a tempo is backed by whole bars instead of a single lag.
+    const COMB_SIZE: usize = 4;
+    /// Tempo that listeners perceive most readily. Estimates are weighted towards
+    /// it by octaves, which settles whether a steady pulse is 69 or 138 BPM.
+    const PREFERRED_BPM: f64 = 120.0;
+
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub struct TempoEstimate {
+        pub bpm: f64,
+        /// How much more periodic the onsets are at `bpm` than on average, from 0 to 1
+        pub confidence: f64,
+    }
+
+    /// How strongly a piece of audio pulses over time, the basis of tempo estimation.
+    pub struct OnsetEnvelope {
+        frames_per_second: f64,
+        autocorrelation: Vec<f64>,
+    }
+
+    impl OnsetEnvelope {
+        /// Computes the onset envelope of mono samples: the rise in log energy from
+        /// one short frame to the next, which peaks at drum hits and note starts.
+        pub fn from_samples(samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+            let energies: Vec<f64> = samples
+                .chunks_exact(HOP_SIZE)
+                .map(|frame| {
+                    let energy: f64 = frame.iter().map(|s| f64::from(*s).powi(2)).sum();
+                    (1.0 + 1000.0 * energy / HOP_SIZE as f64).ln()
+                })
+                .collect();
+            let mut onsets: Vec<f64> = energies
+                .windows(2)
+                .map(|pair| (pair[1] - pair[0]).max(0.0))
+                .collect();
+            let mean = onsets.iter().sum::<f64>() / onsets.len().max(1) as f64;
+            for onset in &mut onsets {
+                *onset -= mean;
+            }
+
+            let frames_per_second = f64::from(sample_rate) / HOP_SIZE as f64;
+            let max_lag = ((60.0 / MIN_BPM) * frames_per_second).ceil() as usize * COMB_SIZE;
+            let autocorrelation = (0..=max_lag.min(onsets.len().saturating_sub(1)))
+                .map(|lag| {
+                    let sum: f64 = onsets[lag..].iter().zip(&onsets).map(|(a, b)| a * b).sum();
+                    sum / (onsets.len() - lag) as f64
+                })
+                .collect();
+
+            OnsetEnvelope {
+                frames_per_second,
+                autocorrelation,
+            }
+        }
+
+        /// How well onsets repeat at the beat period of `bpm` and its multiples,
+        /// relative to the energy of the envelope. Not meaningful on its own,
+        /// only to compare tempos of the same audio.
+        pub fn support(&self, bpm: f64) -> f64 {
+            let Some(zero_lag) = self.autocorrelation.first().filter(|value| **value > 0.0) else {
+                return 0.0;
+            };
+
+            let period = 60.0 / bpm * self.frames_per_second;
+            let values: Vec<f64> = (1..=COMB_SIZE)
+                .filter_map(|multiple| self.autocorrelation_at(period * multiple as f64))
+                .collect();
+            if values.is_empty() {
+                return 0.0;
+            }
+            values.iter().sum::<f64>() / values.len() as f64 / zero_lag
+        }
+
+        /// The tempo between 50 and 220 BPM with the most support, preferring
+        /// tempos closer to 120 BPM over their halves and doubles.
+        pub fn estimate(&self) -> Option<TempoEstimate> {
+            let candidates: Vec<(f64, f64)> = (MIN_BPM as usize * 10..=MAX_BPM as usize * 10)
+                .map(|tenths| {
+                    let bpm = tenths as f64 / 10.0;
+                    let octaves = (bpm / PREFERRED_BPM).log2();
+                    (bpm, self.support(bpm) * (-0.5 * octaves * octaves).exp())
+                })
+                .collect();
+            let (bpm, best) = candidates
+                .iter()
+                .copied()
+                .max_by(|a, b| a.1.total_cmp(&b.1))?;
+            if best <= 0.0 {
+                return None;
+            }
+
+            let mean = candidates
+                .iter()
+                .map(|(_, support)| support.max(0.0))
+                .sum::<f64>()
+                / candidates.len() as f64;
+            Some(TempoEstimate {
+                bpm,
+                confidence: (1.0 - mean / best).clamp(0.0, 1.0),
+            })
+        }
+
+        /// How much the audio favours `proposed_bpm` over `current_bpm`, from 0
+        /// (only the current tempo fits) to 1 (only the proposed tempo fits).
+        pub fn preference(&self, current_bpm: f64, proposed_bpm: f64) -> f64 {
+            let current = self.support(current_bpm).max(0.0);
+            let proposed = self.support(proposed_bpm).max(0.0);
+            if current + proposed == 0.0 {
+                return 0.5;
+            }
+            proposed / (current + proposed)
+        }
+
+        fn autocorrelation_at(&self, lag: f64) -> Option<f64> {
+            let index = lag.floor() as usize;
+            let fraction = lag - lag.floor();
+            let lower = self.autocorrelation.get(index)?;
+            let upper = self.autocorrelation.get(index + 1)?;
+            Some(lower + (upper - lower) * fraction)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const SAMPLE_RATE: u32 = 11025;
+
+        /// Short noise bursts on every beat, like a bare kick drum.
+        fn click_track(bpm: f64, seconds: f64) -> Vec<f32> {
+            let mut samples = vec![0.0f32; (seconds * f64::from(SAMPLE_RATE)) as usize];
+            let period = 60.0 / bpm * f64::from(SAMPLE_RATE);
+            let mut position = 0.0;
+            while (position as usize) < samples.len() {
+                let start = position as usize;
+                for (offset, sample) in samples[start..].iter_mut().take(400).enumerate() {
+                    *sample = if offset % 2 == 0 { 0.8 } else { -0.8 };
+                }
+                position += period;
+            }
+            samples
+        }
+
+        #[test]
+        fn estimate_finds_tempo_of_click_track() {
+            // setup
+            let envelope = OnsetEnvelope::from_samples(&click_track(138.0, 30.0), SAMPLE_RATE);
+
+            // run
+            let estimate = envelope.estimate().unwrap();
+
+            // verify
+            assert!((estimate.bpm - 138.0).abs() < 1.5, "{estimate:?}");
+            assert!(estimate.confidence > 0.5, "{estimate:?}");
+        }
+
+        #[test]
+        fn preference_favours_the_real_tempo() {
+            // setup
+            let envelope = OnsetEnvelope::from_samples(&click_track(138.0, 30.0), SAMPLE_RATE);
+
+            // run & verify
+            assert!(envelope.preference(92.0, 138.0) > 0.6);
+            assert!(envelope.preference(138.0, 92.0) < 0.4);
+        }
+
+        #[test]
+        fn silence_has_no_tempo() {
+            // run
+            let envelope = OnsetEnvelope::from_samples(&[0.0; 44100], SAMPLE_RATE);
+
+            // verify
+            assert_eq!(envelope.estimate(), None);
+            assert_eq!(envelope.preference(92.0, 138.0), 0.5);
+        }
+    }
+}
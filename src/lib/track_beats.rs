@@ -0,0 +1 @@
+pub mod beat_grid;
@@ -0,0 +1,236 @@
+pub mod beat_grid {
+    use prost::Message;
+    use rusqlite::OptionalExtension;
+
+    use crate::{
+        db_changes::change_plan::change_plan::{
+            plan_track_change, ChangeTable, ColumnChange, ColumnValue,
+        },
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb,
+        error::error::LibHelperError,
+    };
+
+    pub const BEAT_GRID_VERSION: &str = "BeatGrid-2.0";
+    pub const BEAT_MAP_VERSION: &str = "BeatMap-1.0";
+
+    // The messages below mirror `src/proto/beats.proto` in the Mixxx sources.
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum Source {
+        Analyzer = 0,
+        FileMetadata = 1,
+        User = 2,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Beat {
+        #[prost(int32, optional, tag = "1")]
+        pub frame_position: Option<i32>,
+        #[prost(bool, optional, tag = "2", default = "true")]
+        pub enabled: Option<bool>,
+        #[prost(enumeration = "Source", optional, tag = "3", default = "Analyzer")]
+        pub source: Option<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Bpm {
+        #[prost(double, optional, tag = "1")]
+        pub bpm: Option<f64>,
+        #[prost(enumeration = "Source", optional, tag = "2", default = "Analyzer")]
+        pub source: Option<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BeatMap {
+        #[prost(message, repeated, tag = "1")]
+        pub beat: Vec<Beat>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct BeatGrid {
+        #[prost(message, optional, tag = "1")]
+        pub bpm: Option<Bpm>,
+        #[prost(message, optional, tag = "2")]
+        pub first_beat: Option<Beat>,
+    }
+
+    /// Rewrites a serialized `beats` value so it ticks at `new_bpm`, returning
+    /// the new `beats_version` and blob.
+    ///
+    /// A beat grid keeps its first beat and only gets the new tempo. A beat map
+    /// can't be scaled without moving every beat, so it is replaced by a
+    /// constant beat grid anchored at its first enabled beat.
+    pub fn rescale_beats(
+        beats_version: &str,
+        beats: &[u8],
+        new_bpm: f64,
+    ) -> Result<(String, Vec<u8>), LibHelperError> {
+        let first_beat = match beats_version {
+            BEAT_GRID_VERSION => {
+                let mut grid = BeatGrid::decode(beats).map_err(|err| {
+                    LibHelperError::InvalidBeats(format!("{BEAT_GRID_VERSION}: {err}"))
+                })?;
+                let bpm = grid.bpm.get_or_insert_with(Bpm::default);
+                bpm.bpm = Some(new_bpm);
+                return Ok((beats_version.to_string(), grid.encode_to_vec()));
+            }
+            BEAT_MAP_VERSION => {
+                let map = BeatMap::decode(beats).map_err(|err| {
+                    LibHelperError::InvalidBeats(format!("{BEAT_MAP_VERSION}: {err}"))
+                })?;
+                map.beat
+                    .into_iter()
+                    .find(|beat| beat.enabled())
+                    .ok_or_else(|| {
+                        LibHelperError::InvalidBeats(String::from("the beat map has no beats"))
+                    })?
+            }
+            _ => {
+                return Err(LibHelperError::InvalidBeats(format!(
+                    "unsupported beats version {beats_version}"
+                )))
+            }
+        };
+
+        let grid = BeatGrid {
+            bpm: Some(Bpm {
+                bpm: Some(new_bpm),
+                source: None,
+            }),
+            first_beat: Some(Beat {
+                frame_position: first_beat.frame_position,
+                enabled: None,
+                source: first_beat.source,
+            }),
+        };
+        Ok((BEAT_GRID_VERSION.to_string(), grid.encode_to_vec()))
+    }
+
+    /// Plans rewriting the stored beats of a track along with a BPM change, so
+    /// the grid on the waveform matches the new tempo. Plans nothing for tracks
+    /// without beats or schemas without the `beats` columns.
+    pub fn plan_beats_change(
+        db: &MixxxDb,
+        track_id: usize,
+        new_bpm: f64,
+    ) -> Result<Vec<ColumnChange>, LibHelperError> {
+        if !db.has_column("library", "beats") || !db.has_column("library", "beats_version") {
+            return Ok(vec![]);
+        }
+
+        let stored: Option<(Option<Vec<u8>>, Option<String>)> = db
+            .connection()
+            .query_row(
+                "SELECT beats, beats_version FROM library WHERE id = ?1",
+                [track_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((Some(beats), Some(beats_version))) = stored else {
+            return Ok(vec![]);
+        };
+
+        let (new_version, new_beats) = rescale_beats(&beats_version, &beats, new_bpm)?;
+        let mut changes = vec![];
+        for (column, new_value) in [
+            ("beats", ColumnValue::Blob(new_beats)),
+            ("beats_version", ColumnValue::Text(new_version)),
+        ] {
+            changes.extend(plan_track_change(
+                db,
+                ChangeTable::Library,
+                track_id,
+                column,
+                new_value,
+            )?);
+        }
+
+        Ok(changes)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_mixxx_db};
+
+        fn beat_at(frame_position: i32, enabled: bool) -> Beat {
+            Beat {
+                frame_position: Some(frame_position),
+                enabled: Some(enabled),
+                source: None,
+            }
+        }
+
+        #[test]
+        fn rescale_beats_keeps_first_beat_of_grid() {
+            // setup
+            let grid = BeatGrid {
+                bpm: Some(Bpm {
+                    bpm: Some(92.0),
+                    source: Some(Source::User as i32),
+                }),
+                first_beat: Some(beat_at(1234, true)),
+            };
+
+            // run
+            let (version, blob) =
+                rescale_beats(BEAT_GRID_VERSION, &grid.encode_to_vec(), 138.0).unwrap();
+
+            // verify
+            let rescaled = BeatGrid::decode(blob.as_slice()).unwrap();
+            assert_eq!(version, BEAT_GRID_VERSION);
+            assert_eq!(rescaled.bpm.as_ref().unwrap().bpm, Some(138.0));
+            assert_eq!(rescaled.bpm.unwrap().source(), Source::User);
+            assert_eq!(rescaled.first_beat.unwrap().frame_position, Some(1234));
+        }
+
+        #[test]
+        fn rescale_beats_turns_map_into_grid_at_first_enabled_beat() {
+            // setup
+            let map = BeatMap {
+                beat: vec![beat_at(100, false), beat_at(500, true), beat_at(900, true)],
+            };
+
+            // run
+            let (version, blob) =
+                rescale_beats(BEAT_MAP_VERSION, &map.encode_to_vec(), 174.0).unwrap();
+
+            // verify
+            let grid = BeatGrid::decode(blob.as_slice()).unwrap();
+            assert_eq!(version, BEAT_GRID_VERSION);
+            assert_eq!(grid.bpm.unwrap().bpm, Some(174.0));
+            assert_eq!(grid.first_beat.unwrap().frame_position, Some(500));
+        }
+
+        #[test]
+        fn plan_beats_change_plans_blob_and_version() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/track.mp3", "Trance", 92.0);
+            let map = BeatMap {
+                beat: vec![beat_at(0, true)],
+            };
+            db.connection()
+                .execute(
+                    "UPDATE library SET beats = ?1, beats_version = ?2 WHERE id = 1",
+                    (map.encode_to_vec(), BEAT_MAP_VERSION),
+                )
+                .unwrap();
+
+            // run
+            let changes = plan_beats_change(&db, 1, 138.0).unwrap();
+
+            // verify
+            let columns: Vec<&str> = changes
+                .iter()
+                .map(|change| change.column.as_str())
+                .collect();
+            assert_eq!(columns, vec!["beats", "beats_version"]);
+            assert_eq!(
+                changes[1].new_value,
+                ColumnValue::Text(String::from(BEAT_GRID_VERSION))
+            );
+        }
+    }
+}
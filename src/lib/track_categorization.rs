@@ -1 +1,3 @@
 pub mod genre;
+pub mod normalization;
+pub mod taxonomy;
@@ -1,15 +1,12 @@
 pub mod genre {
+    use crate::track_categorization::taxonomy::taxonomy::GenreTaxonomy;
 
-    fn is_trance(genre_string: &str) -> bool {
-        return genre_string.to_lowercase().contains("trance");
-    }
-
-    fn is_dance(genre_string: &str) -> bool {
-        return genre_string.to_lowercase().contains("dance");
-    }
+    /// The taxonomy family the EDM BPM correction applies to.
+    pub const EDM_FAMILY: &str = "Electronic";
 
+    /// Whether the built-in taxonomy files the genre under electronic music.
     pub fn is_edm(genre_string: &str) -> bool {
-        return is_dance(&genre_string) || is_trance(&genre_string);
+        GenreTaxonomy::builtin().is_in_family(genre_string, EDM_FAMILY)
     }
 
     #[cfg(test)]
@@ -18,19 +15,32 @@ pub mod genre {
 
         #[test]
         fn trance_is_edm() {
-            assert_eq!(is_edm("Trance (Main Floor)"), true);
+            assert!(is_edm("Trance (Main Floor)"));
         }
 
         #[test]
         fn detects_main_floor_trance() {
-            assert_eq!(is_trance("Trance (Main Floor"), true);
-            assert_eq!(is_trance("Trance"), true);
+            assert!(is_edm("Trance (Main Floor"));
+            assert!(is_edm("Trance"));
         }
 
         #[test]
         fn detects_main_floor_dance() {
-            assert_eq!(is_dance("Dance"), true);
-            assert_eq!(is_dance("Hard dance"), true);
+            assert!(is_edm("Dance"));
+            assert!(is_edm("Hard dance"));
+        }
+
+        #[test]
+        fn detects_edm_without_dance_or_trance_in_name() {
+            assert!(is_edm("Techno"));
+            assert!(is_edm("House"));
+            assert!(is_edm("DnB"));
+        }
+
+        #[test]
+        fn leaves_out_lookalike_genres() {
+            assert!(!is_edm("Dancehall"));
+            assert!(!is_edm("Trance-folk"));
         }
     }
 }
@@ -0,0 +1,178 @@
+pub mod normalization {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        db_changes::change_plan::change_plan::{
+            plan_track_change, ChangePlan, ChangeTable, ColumnValue,
+        },
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb,
+        error::error::LibHelperError,
+        track_categorization::taxonomy::taxonomy::GenreTaxonomy,
+    };
+
+    /// The tracks with one spelling of a genre and the name it normalizes to.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct GenreMapping {
+        pub from: String,
+        pub to: String,
+        pub track_ids: Vec<usize>,
+    }
+
+    /// What normalizing the genres of a library would change.
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct GenreNormalization {
+        /// Sorted by canonical name, then by the original spelling
+        pub mappings: Vec<GenreMapping>,
+        /// Genres the taxonomy does not know, with their track counts
+        pub unclassified: Vec<(String, usize)>,
+    }
+
+    /// The canonical name of a genre string: the names of the genres it lists,
+    /// joined with ` / `, or `None` if the taxonomy knows none of them.
+    pub fn canonical_genre(taxonomy: &GenreTaxonomy, genre: &str) -> Option<String> {
+        let names: Vec<String> = taxonomy
+            .classify(genre)
+            .into_iter()
+            .map(|node| node.name)
+            .collect();
+        if names.is_empty() {
+            None
+        } else {
+            Some(names.join(" / "))
+        }
+    }
+
+    /// Maps each distinct genre of the library, leaving out deleted tracks,
+    /// to its canonical name. Genres already spelled canonically are left out.
+    pub fn find_genre_mappings(
+        db: &MixxxDb,
+        taxonomy: &GenreTaxonomy,
+    ) -> Result<GenreNormalization, LibHelperError> {
+        db.require_columns("library", &["id", "genre", "mixxx_deleted"])?;
+        let mut stmt = db.connection().prepare(
+            "SELECT genre, id FROM library
+             WHERE mixxx_deleted IS NOT 1 AND TRIM(COALESCE(genre, '')) != ''
+             ORDER BY id",
+        )?;
+        let mut tracks_by_genre: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for row in stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (genre, id): (String, usize) = row?;
+            tracks_by_genre.entry(genre).or_default().push(id);
+        }
+
+        let mut normalization = GenreNormalization::default();
+        for (genre, track_ids) in tracks_by_genre {
+            match canonical_genre(taxonomy, &genre) {
+                Some(canonical) if canonical == genre => {}
+                Some(canonical) => normalization.mappings.push(GenreMapping {
+                    from: genre,
+                    to: canonical,
+                    track_ids,
+                }),
+                None => normalization.unclassified.push((genre, track_ids.len())),
+            }
+        }
+        normalization
+            .mappings
+            .sort_by(|a, b| a.to.cmp(&b.to).then_with(|| a.from.cmp(&b.from)));
+
+        Ok(normalization)
+    }
+
+    /// Plans setting `library.genre` of every mapped track to its canonical name.
+    pub fn plan_genre_normalization(
+        db: &MixxxDb,
+        mappings: &[GenreMapping],
+    ) -> Result<ChangePlan, LibHelperError> {
+        let mut plan = ChangePlan::default();
+        for mapping in mappings {
+            for &track_id in &mapping.track_ids {
+                let change = plan_track_change(
+                    db,
+                    ChangeTable::Library,
+                    track_id,
+                    "genre",
+                    ColumnValue::Text(mapping.to.clone()),
+                )?;
+                plan.changes.extend(change);
+            }
+        }
+
+        Ok(plan)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_mixxx_db};
+
+        #[test]
+        fn find_genre_mappings_groups_spellings() {
+            // setup
+            let db = open_test_mixxx_db();
+            let genres = [
+                "Trance (Main Floor)",
+                "trance",
+                "Trance",
+                "TRANCE / Uplifting",
+                "trance",
+                "Polka",
+                "",
+            ];
+            for (index, genre) in genres.iter().enumerate() {
+                let id = index + 1;
+                insert_track(
+                    db.connection(),
+                    id,
+                    &format!("/music/{id}.mp3"),
+                    genre,
+                    138.0,
+                );
+            }
+
+            // run
+            let normalization = find_genre_mappings(&db, GenreTaxonomy::builtin()).unwrap();
+
+            // verify
+            let mappings: Vec<(&str, &str, usize)> = normalization
+                .mappings
+                .iter()
+                .map(|mapping| {
+                    (
+                        mapping.from.as_str(),
+                        mapping.to.as_str(),
+                        mapping.track_ids.len(),
+                    )
+                })
+                .collect();
+            assert_eq!(
+                mappings,
+                vec![
+                    ("Trance (Main Floor)", "Trance", 1),
+                    ("trance", "Trance", 2),
+                    ("TRANCE / Uplifting", "Uplifting Trance", 1),
+                ]
+            );
+            assert_eq!(normalization.unclassified, vec![(String::from("Polka"), 1)]);
+        }
+
+        #[test]
+        fn plan_genre_normalization_changes_library_genre() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/1.mp3", "dnb", 174.0);
+            let normalization = find_genre_mappings(&db, GenreTaxonomy::builtin()).unwrap();
+
+            // run
+            let plan = plan_genre_normalization(&db, &normalization.mappings).unwrap();
+
+            // verify
+            assert_eq!(plan.changes.len(), 1);
+            assert_eq!(plan.changes[0].column, "genre");
+            assert_eq!(
+                plan.changes[0].new_value,
+                ColumnValue::Text(String::from("Drum & Bass"))
+            );
+        }
+    }
+}
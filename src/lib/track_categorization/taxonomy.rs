@@ -0,0 +1,363 @@
+pub mod taxonomy {
+    use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+    use regex::Regex;
+    use serde::Deserialize;
+
+    use crate::error::error::LibHelperError;
+
+    const BUILTIN_TAXONOMY: &str = include_str!("genres.toml");
+
+    /// A tree of genres such as Electronic > House > Tech House. Genre strings
+    /// from tags are classified by name, alias or regular expression, so rules
+    /// can ask for a whole family instead of listing every spelling.
+    ///
+    /// User files use the format of the built-in `genres.toml`, one `[[genre]]`
+    /// table per genre, and extend the built-in taxonomy unless they set
+    /// `builtin = false`:
+    ///
+    /// ```toml
+    /// [[genre]]
+    /// name = "Organic House"
+    /// parent = "House"
+    /// aliases = ["Downtempo House"]
+    /// patterns = ["organic.*house"]
+    /// ```
+    #[derive(Clone, Debug)]
+    pub struct GenreTaxonomy {
+        nodes: Vec<GenreNode>,
+        /// Normalized names and aliases to indices into `nodes`
+        index: HashMap<String, usize>,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct GenreNode {
+        pub name: String,
+        /// The names from the top-level family down to this genre
+        pub path: Vec<String>,
+        pub aliases: Vec<String>,
+        /// Case-insensitive, each has to match a whole genre
+        pub patterns: Vec<Regex>,
+        parent: Option<usize>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct TaxonomyFile {
+        builtin: Option<bool>,
+        #[serde(default)]
+        genre: Vec<GenreEntry>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct GenreEntry {
+        name: String,
+        parent: Option<String>,
+        #[serde(default)]
+        aliases: Vec<String>,
+        #[serde(default)]
+        patterns: Vec<String>,
+    }
+
+    impl GenreTaxonomy {
+        /// The taxonomy shipped with this tool.
+        pub fn builtin() -> &'static GenreTaxonomy {
+            static BUILTIN: OnceLock<GenreTaxonomy> = OnceLock::new();
+            BUILTIN.get_or_init(|| {
+                GenreTaxonomy::empty()
+                    .extended_with(BUILTIN_TAXONOMY)
+                    .expect("the built-in genre taxonomy is valid")
+            })
+        }
+
+        pub fn load(path: &str) -> Result<GenreTaxonomy, LibHelperError> {
+            let contents = std::fs::read_to_string(Path::new(path))?;
+            GenreTaxonomy::from_toml(&contents)
+                .map_err(|err| LibHelperError::Config(format!("{path}: {err}")))
+        }
+
+        pub fn from_toml(contents: &str) -> Result<GenreTaxonomy, LibHelperError> {
+            let file: TaxonomyFile =
+                toml::from_str(contents).map_err(|err| LibHelperError::Config(err.to_string()))?;
+
+            let mut taxonomy = if file.builtin.unwrap_or(true) {
+                GenreTaxonomy::builtin().clone()
+            } else {
+                GenreTaxonomy::empty()
+            };
+            for entry in file.genre {
+                let name = entry.name.clone();
+                taxonomy
+                    .add_entry(entry)
+                    .map_err(|err| LibHelperError::Config(format!("genre {name}: {err}")))?;
+            }
+
+            Ok(taxonomy)
+        }
+
+        fn empty() -> GenreTaxonomy {
+            GenreTaxonomy {
+                nodes: vec![],
+                index: HashMap::new(),
+            }
+        }
+
+        fn extended_with(mut self, contents: &str) -> Result<GenreTaxonomy, LibHelperError> {
+            let file: TaxonomyFile =
+                toml::from_str(contents).map_err(|err| LibHelperError::Config(err.to_string()))?;
+            for entry in file.genre {
+                self.add_entry(entry)?;
+            }
+            Ok(self)
+        }
+
+        /// Adds a genre, or more aliases and patterns to a genre that already exists.
+        /// Names and aliases defined later take precedence over earlier ones.
+        fn add_entry(&mut self, entry: GenreEntry) -> Result<(), LibHelperError> {
+            let parent = match &entry.parent {
+                Some(parent) => Some(self.find_index(parent).ok_or_else(|| {
+                    LibHelperError::Config(format!(
+                        "unknown parent {parent}, parents have to be listed first"
+                    ))
+                })?),
+                None => None,
+            };
+            let patterns = entry
+                .patterns
+                .iter()
+                .map(|pattern| Regex::new(&format!("(?i)^(?:{pattern})$")))
+                .collect::<Result<Vec<Regex>, _>>()?;
+
+            let existing = self
+                .nodes
+                .iter()
+                .position(|node| normalize(&node.name) == normalize(&entry.name));
+            let index = match existing {
+                Some(index) => {
+                    let node = &mut self.nodes[index];
+                    if entry.parent.is_some() && parent != node.parent {
+                        return Err(LibHelperError::Config(format!(
+                            "already defined under {}",
+                            node.path[..node.path.len() - 1].join(" > ")
+                        )));
+                    }
+                    node.aliases.extend(entry.aliases.iter().cloned());
+                    node.patterns.extend(patterns);
+                    index
+                }
+                None => {
+                    let mut path = parent
+                        .map(|parent| self.nodes[parent].path.clone())
+                        .unwrap_or_default();
+                    path.push(entry.name.clone());
+                    self.nodes.push(GenreNode {
+                        name: entry.name.clone(),
+                        path,
+                        aliases: entry.aliases.clone(),
+                        patterns,
+                        parent,
+                    });
+                    self.nodes.len() - 1
+                }
+            };
+
+            for key in std::iter::once(&entry.name).chain(&entry.aliases) {
+                let key = normalize(key);
+                if !key.is_empty() {
+                    self.index.insert(key, index);
+                }
+            }
+
+            Ok(())
+        }
+
+        /// Looks a genre up by its name or one of its aliases.
+        pub fn find(&self, name: &str) -> Option<&GenreNode> {
+            self.find_index(name).map(|index| &self.nodes[index])
+        }
+
+        fn find_index(&self, name: &str) -> Option<usize> {
+            self.index.get(&normalize(name)).copied()
+        }
+
+        /// The most specific genres a genre string names. Strings listing several
+        /// genres like `House / Techno` give several nodes, remarks in brackets
+        /// like `Trance (Main Floor)` are ignored and unknown genres give none.
+        pub fn classify(&self, genre: &str) -> Vec<GenreNode> {
+            let mut matched: Vec<usize> = vec![];
+            for candidate in candidates(genre) {
+                let by_name = self.find_index(&candidate);
+                let by_pattern = self.nodes.iter().position(|node| {
+                    node.patterns
+                        .iter()
+                        .any(|pattern| pattern.is_match(&candidate))
+                });
+                if let Some(index) = by_name.or(by_pattern) {
+                    if !matched.contains(&index) {
+                        matched.push(index);
+                    }
+                }
+            }
+
+            let is_ancestor_of_match = |index: usize| {
+                matched
+                    .iter()
+                    .any(|&other| other != index && self.is_ancestor(index, other))
+            };
+            matched
+                .iter()
+                .filter(|&&index| !is_ancestor_of_match(index))
+                .map(|&index| self.nodes[index].clone())
+                .collect()
+        }
+
+        /// Whether the genre string names `family` or a genre below it.
+        /// `family` may be an alias, such as `EDM` for Electronic.
+        pub fn is_in_family(&self, genre: &str, family: &str) -> bool {
+            let Some(family) = self.find(family) else {
+                return false;
+            };
+            self.classify(genre)
+                .iter()
+                .any(|node| node.path.contains(&family.name))
+        }
+
+        fn is_ancestor(&self, ancestor: usize, mut index: usize) -> bool {
+            while let Some(parent) = self.nodes[index].parent {
+                if parent == ancestor {
+                    return true;
+                }
+                index = parent;
+            }
+            false
+        }
+    }
+
+    impl Default for GenreTaxonomy {
+        fn default() -> Self {
+            GenreTaxonomy::builtin().clone()
+        }
+    }
+
+    impl GenreNode {
+        /// The top-level genre this one belongs to.
+        pub fn family(&self) -> &str {
+            &self.path[0]
+        }
+    }
+
+    /// Only letters and digits count, so `Hip-Hop`, `hip hop` and `HipHop` are the same.
+    fn normalize(name: &str) -> String {
+        name.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect()
+    }
+
+    /// The whole genre string and the single genres it lists, without remarks in brackets.
+    fn candidates(genre: &str) -> Vec<String> {
+        let without_remarks = genre.split(['(', '[']).next().unwrap_or_default();
+        let mut candidates = vec![genre.trim().to_string()];
+        candidates.push(without_remarks.trim().to_string());
+        for part in without_remarks.split(['/', ',', ';', '|', '\\']) {
+            candidates.extend(part.split(" - ").map(|part| part.trim().to_string()));
+        }
+
+        let mut unique: Vec<String> = vec![];
+        for candidate in candidates {
+            if !candidate.is_empty() && !unique.contains(&candidate) {
+                unique.push(candidate);
+            }
+        }
+        unique
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn names(nodes: &[GenreNode]) -> Vec<&str> {
+            nodes.iter().map(|node| node.name.as_str()).collect()
+        }
+
+        #[test]
+        fn classify_finds_most_specific_genres() {
+            // setup
+            let taxonomy = GenreTaxonomy::builtin();
+
+            // run
+            let tech_house = taxonomy.classify("tech-house");
+            let listed = taxonomy.classify("TRANCE / Uplifting");
+            let dnb = taxonomy.classify("DnB (Liquid mix)");
+            let unknown = taxonomy.classify("Trance-folk");
+
+            // verify
+            assert_eq!(names(&tech_house), vec!["Tech House"]);
+            assert_eq!(
+                tech_house[0].path,
+                vec!["Electronic", "House", "Tech House"]
+            );
+            assert_eq!(names(&listed), vec!["Uplifting Trance"]);
+            assert_eq!(names(&dnb), vec!["Drum & Bass"]);
+            assert_eq!(dnb[0].family(), "Electronic");
+            assert!(unknown.is_empty());
+        }
+
+        #[test]
+        fn is_in_family_accepts_aliases() {
+            // setup
+            let taxonomy = GenreTaxonomy::builtin();
+
+            // verify
+            assert!(taxonomy.is_in_family("Psy-Trance", "EDM"));
+            assert!(taxonomy.is_in_family("Techno", "electronic"));
+            assert!(taxonomy.is_in_family("Dancehall", "Reggae"));
+            assert!(!taxonomy.is_in_family("Dancehall", "EDM"));
+            assert!(!taxonomy.is_in_family("Techno", "No Such Family"));
+        }
+
+        #[test]
+        fn from_toml_extends_builtin_taxonomy() {
+            // setup
+            let contents = r#"
+                [[genre]]
+                name = "Organic House"
+                parent = "House"
+                patterns = ["organic.*house"]
+
+                [[genre]]
+                name = "Reggae"
+                aliases = ["Reggae Fusion"]
+            "#;
+
+            // run
+            let taxonomy = GenreTaxonomy::from_toml(contents).unwrap();
+
+            // verify
+            let organic = taxonomy.classify("Organic / Downtempo House");
+            assert_eq!(names(&organic), vec!["Organic House"]);
+            assert!(taxonomy.is_in_family("organic deep house", "House"));
+            assert!(taxonomy.is_in_family("Reggae Fusion", "Reggae"));
+            assert!(taxonomy.is_in_family("Techno", "Electronic"));
+        }
+
+        #[test]
+        fn from_toml_rejects_unknown_parent() {
+            // setup
+            let contents = r#"
+                builtin = false
+
+                [[genre]]
+                name = "Tech House"
+                parent = "House"
+            "#;
+
+            // run
+            let result = GenreTaxonomy::from_toml(contents);
+
+            // verify
+            assert!(matches!(result, Err(LibHelperError::Config(_))));
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod duplicates;
+pub mod track_merge;
@@ -0,0 +1,415 @@
+pub mod duplicates {
+    use std::collections::HashMap;
+    use std::{fmt, str::FromStr};
+
+    use crate::db_changes::change_plan::change_plan::ChangePlan;
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+    use crate::error::error::LibHelperError;
+    use crate::track::track::track::Track;
+    use crate::track_duplicates::track_merge::track_merge::{plan_track_merge, MergePolicy};
+    use crate::track_fingerprinting::fingerprint_pool::fingerprint_pool::find_fingerprint;
+    use crate::track_fingerprinting::track_fingerprinting::track_fingerprinting::{
+        Fingerprint, FingerprintAlgorithm,
+    };
+
+    /// How much the durations of tracks with the same artist and title may differ
+    /// for them to count as copies.
+    pub const FUZZY_DURATION_TOLERANCE_SECONDS: f64 = 2.0;
+
+    /// How copies of a track are recognized, from the strictest to the loosest.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+    pub enum DuplicateMatch {
+        /// The files are identical
+        Exact,
+        /// The audio data is identical, only the tags differ
+        Payload,
+        /// Artist and title are the same apart from case and punctuation, and the
+        /// durations are within [`FUZZY_DURATION_TOLERANCE_SECONDS`]
+        Fuzzy,
+    }
+
+    impl DuplicateMatch {
+        pub const ALL: [DuplicateMatch; 3] = [
+            DuplicateMatch::Exact,
+            DuplicateMatch::Payload,
+            DuplicateMatch::Fuzzy,
+        ];
+
+        pub fn name(&self) -> &'static str {
+            match self {
+                DuplicateMatch::Exact => "exact",
+                DuplicateMatch::Payload => "payload",
+                DuplicateMatch::Fuzzy => "fuzzy",
+            }
+        }
+
+        /// The fingerprint the match compares, if any.
+        pub fn algorithm(&self) -> Option<FingerprintAlgorithm> {
+            match self {
+                DuplicateMatch::Exact => Some(FingerprintAlgorithm::SHA265),
+                DuplicateMatch::Payload => Some(FingerprintAlgorithm::AudioPayloadSHA256),
+                DuplicateMatch::Fuzzy => None,
+            }
+        }
+    }
+
+    impl fmt::Display for DuplicateMatch {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.name())
+        }
+    }
+
+    impl FromStr for DuplicateMatch {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Self, Self::Err> {
+            DuplicateMatch::ALL
+                .into_iter()
+                .find(|duplicate_match| duplicate_match.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| {
+                    format!("unknown duplicate match {name}, expected exact, payload or fuzzy")
+                })
+        }
+    }
+
+    /// One of the copies in a [`DuplicateGroup`].
+    #[derive(Clone, Debug)]
+    pub struct TrackCopy {
+        pub track: Track,
+        pub cue_count: usize,
+    }
+
+    impl TrackCopy {
+        pub fn plays(&self) -> u32 {
+            self.track.timesplayed.unwrap_or(0)
+        }
+
+        pub fn bitrate(&self) -> u32 {
+            self.track.bitrate.unwrap_or(0)
+        }
+    }
+
+    /// Tracks of the library that are copies of each other.
+    #[derive(Clone, Debug)]
+    pub struct DuplicateGroup {
+        /// The loosest match that links the copies
+        pub matched_by: DuplicateMatch,
+        /// The copy worth keeping first: the one with the most cues, then plays,
+        /// then the highest bitrate, then the one imported first
+        pub copies: Vec<TrackCopy>,
+    }
+
+    impl DuplicateGroup {
+        pub fn survivor(&self) -> &TrackCopy {
+            &self.copies[0]
+        }
+
+        /// The ids of the copies other than the survivor.
+        pub fn drop_ids(&self) -> Vec<usize> {
+            self.copies[1..].iter().map(|copy| copy.track.id).collect()
+        }
+    }
+
+    /// Groups the tracks that are copies of each other by the given matches.
+    ///
+    /// `fingerprints` holds the fingerprints by location; tracks without the
+    /// fingerprint a match needs are left out of that match. Copies found by
+    /// different matches end up in the same group.
+    pub fn find_duplicates(
+        db: &MixxxDb,
+        tracks: Vec<Track>,
+        fingerprints: &HashMap<String, Vec<Fingerprint>>,
+        matches: &[DuplicateMatch],
+    ) -> Result<Vec<DuplicateGroup>, LibHelperError> {
+        let mut groups = CopyGroups::new(tracks.len());
+        let mut matches = matches.to_vec();
+        matches.sort();
+        matches.dedup();
+        for duplicate_match in matches {
+            for bucket in buckets(&tracks, fingerprints, duplicate_match) {
+                for pair in bucket.windows(2) {
+                    groups.join(pair[0], pair[1], duplicate_match);
+                }
+            }
+        }
+
+        let cue_counts = count_cues(db)?;
+        let mut copies_by_root: HashMap<usize, (DuplicateMatch, Vec<TrackCopy>)> = HashMap::new();
+        for (index, track) in tracks.into_iter().enumerate() {
+            let root = groups.find(index);
+            let Some(matched_by) = groups.matched_by[root] else {
+                continue;
+            };
+            let cue_count = cue_counts.get(&track.id).copied().unwrap_or(0);
+            copies_by_root
+                .entry(root)
+                .or_insert((matched_by, vec![]))
+                .1
+                .push(TrackCopy { track, cue_count });
+        }
+
+        let mut duplicate_groups: Vec<DuplicateGroup> = copies_by_root
+            .into_values()
+            .map(|(matched_by, mut copies)| {
+                copies.sort_by(|a, b| {
+                    b.cue_count
+                        .cmp(&a.cue_count)
+                        .then(b.plays().cmp(&a.plays()))
+                        .then(b.bitrate().cmp(&a.bitrate()))
+                        .then(a.track.id.cmp(&b.track.id))
+                });
+                DuplicateGroup { matched_by, copies }
+            })
+            .collect();
+        duplicate_groups.sort_by_key(|group| group.survivor().track.id);
+
+        Ok(duplicate_groups)
+    }
+
+    /// Plans merging every group into its survivor as [`plan_track_merge`] does.
+    pub fn plan_merges(
+        db: &MixxxDb,
+        groups: &[DuplicateGroup],
+        policy: &MergePolicy,
+    ) -> Result<ChangePlan, LibHelperError> {
+        let mut plan = ChangePlan::default();
+        for group in groups {
+            let group_plan =
+                plan_track_merge(db, group.survivor().track.id, &group.drop_ids(), policy)?;
+            plan.changes.extend(group_plan.changes);
+            plan.track_merges.extend(group_plan.track_merges);
+        }
+        Ok(plan)
+    }
+
+    /// The number of cues per track id, empty for databases without cues.
+    pub fn count_cues(db: &MixxxDb) -> Result<HashMap<usize, usize>, LibHelperError> {
+        if !db.has_column("cues", "track_id") {
+            return Ok(HashMap::new());
+        }
+        let mut stmt = db
+            .connection()
+            .prepare("SELECT track_id, COUNT(*) FROM cues GROUP BY track_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<HashMap<usize, usize>, _>>()?)
+    }
+
+    /// Indexes of tracks that match each other, in an order in which neighbours match.
+    fn buckets(
+        tracks: &[Track],
+        fingerprints: &HashMap<String, Vec<Fingerprint>>,
+        duplicate_match: DuplicateMatch,
+    ) -> Vec<Vec<usize>> {
+        let mut buckets: HashMap<String, Vec<usize>> = HashMap::new();
+        if let Some(algorithm) = duplicate_match.algorithm() {
+            for (index, track) in tracks.iter().enumerate() {
+                let fingerprint = fingerprints
+                    .get(&track.location)
+                    .and_then(|fingerprints| find_fingerprint(fingerprints, &algorithm));
+                if let Some(fingerprint) = fingerprint {
+                    buckets
+                        .entry(fingerprint.fingerprint.clone())
+                        .or_default()
+                        .push(index);
+                }
+            }
+            return buckets.into_values().collect();
+        }
+
+        for (index, track) in tracks.iter().enumerate() {
+            let artist = normalize_name(track.artist.as_deref().unwrap_or_default());
+            let title = normalize_name(track.title.as_deref().unwrap_or_default());
+            if artist.is_empty() || title.is_empty() || track.duration.is_none() {
+                continue;
+            }
+            buckets
+                .entry(format!("{artist}\t{title}"))
+                .or_default()
+                .push(index);
+        }
+        // sorted by duration, neighbours are within the tolerance or the chain breaks
+        let duration = |index: &usize| tracks[*index].duration.unwrap_or_default();
+        let mut runs = vec![];
+        for mut bucket in buckets.into_values() {
+            bucket.sort_by(|a, b| duration(a).total_cmp(&duration(b)));
+            let mut run: Vec<usize> = vec![];
+            for index in bucket {
+                if let Some(last) = run.last() {
+                    if duration(&index) - duration(last) > FUZZY_DURATION_TOLERANCE_SECONDS {
+                        runs.push(std::mem::take(&mut run));
+                    }
+                }
+                run.push(index);
+            }
+            runs.push(run);
+        }
+        runs
+    }
+
+    /// Lower case words of letters and digits, so "The Prodigy - Firestarter (Remastered)"
+    /// and "the prodigy – firestarter [remastered]" are the same.
+    fn normalize_name(name: &str) -> String {
+        name.to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Union-find over track indexes, remembering the loosest match of each group.
+    struct CopyGroups {
+        parents: Vec<usize>,
+        matched_by: Vec<Option<DuplicateMatch>>,
+    }
+
+    impl CopyGroups {
+        fn new(count: usize) -> CopyGroups {
+            CopyGroups {
+                parents: (0..count).collect(),
+                matched_by: vec![None; count],
+            }
+        }
+
+        fn find(&mut self, index: usize) -> usize {
+            let mut root = index;
+            while self.parents[root] != root {
+                root = self.parents[root];
+            }
+            self.parents[index] = root;
+            root
+        }
+
+        fn join(&mut self, a: usize, b: usize, duplicate_match: DuplicateMatch) {
+            let (root_a, root_b) = (self.find(a), self.find(b));
+            if root_a == root_b {
+                return;
+            }
+            self.parents[root_b] = root_a;
+            self.matched_by[root_a] = [
+                self.matched_by[root_a],
+                self.matched_by[root_b],
+                Some(duplicate_match),
+            ]
+            .into_iter()
+            .max()
+            .flatten();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::db_changes::change_plan::change_plan::apply_plan;
+        use crate::test_db::test_db::{insert_cue, insert_track, open_test_mixxx_db};
+        use crate::track::track_query::track_query::TrackQuery;
+
+        fn hash(algorithm: FingerprintAlgorithm, value: &str) -> Fingerprint {
+            Fingerprint {
+                fingerprint: value.to_string(),
+                fingerprint_algorithm: algorithm,
+            }
+        }
+
+        fn set_tags(db: &MixxxDb, id: usize, artist: &str, title: &str, duration: f64) {
+            db.connection()
+                .execute(
+                    "UPDATE library SET artist = ?2, title = ?3, duration = ?4 WHERE id = ?1",
+                    (id, artist, title, duration),
+                )
+                .unwrap();
+        }
+
+        #[test]
+        fn find_duplicates_joins_matches_and_ranks_copies() {
+            // setup
+            let db = open_test_mixxx_db();
+            for id in 1..=5 {
+                insert_track(
+                    db.connection(),
+                    id,
+                    &format!("/music/{id}.mp3"),
+                    "Techno",
+                    130.0,
+                );
+            }
+            set_tags(&db, 4, "The Prodigy", "Firestarter", 280.0);
+            set_tags(&db, 5, "the prodigy", "Firestarter!", 281.5);
+            set_tags(&db, 3, "Someone", "Else", 280.0);
+            insert_cue(db.connection(), 2, 1, 0);
+            let fingerprints = HashMap::from([
+                (
+                    String::from("/music/1.mp3"),
+                    vec![
+                        hash(FingerprintAlgorithm::SHA265, "a"),
+                        hash(FingerprintAlgorithm::AudioPayloadSHA256, "p"),
+                    ],
+                ),
+                (
+                    String::from("/music/2.mp3"),
+                    vec![
+                        hash(FingerprintAlgorithm::SHA265, "b"),
+                        hash(FingerprintAlgorithm::AudioPayloadSHA256, "p"),
+                    ],
+                ),
+                (
+                    String::from("/music/3.mp3"),
+                    vec![hash(FingerprintAlgorithm::SHA265, "a")],
+                ),
+            ]);
+            let tracks = TrackQuery::new().load(&db).unwrap();
+
+            // run
+            let groups = find_duplicates(&db, tracks, &fingerprints, &DuplicateMatch::ALL).unwrap();
+
+            // verify
+            assert_eq!(groups.len(), 2);
+            let ids: Vec<usize> = groups[0].copies.iter().map(|copy| copy.track.id).collect();
+            assert_eq!(ids, vec![2, 1, 3]);
+            assert_eq!(groups[0].matched_by, DuplicateMatch::Payload);
+            let ids: Vec<usize> = groups[1].copies.iter().map(|copy| copy.track.id).collect();
+            assert_eq!(ids, vec![4, 5]);
+            assert_eq!(groups[1].matched_by, DuplicateMatch::Fuzzy);
+        }
+
+        #[test]
+        fn find_duplicates_keeps_different_durations_apart() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/1.mp3", "Techno", 130.0);
+            insert_track(db.connection(), 2, "/music/2.mp3", "Techno", 130.0);
+            set_tags(&db, 1, "Artist", "Title", 280.0);
+            set_tags(&db, 2, "Artist", "Title", 420.0);
+            let tracks = TrackQuery::new().load(&db).unwrap();
+
+            // run
+            let groups =
+                find_duplicates(&db, tracks, &HashMap::new(), &[DuplicateMatch::Fuzzy]).unwrap();
+
+            // verify
+            assert!(groups.is_empty());
+        }
+
+        #[test]
+        fn plan_merges_deletes_the_other_copies() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/1.mp3", "Techno", 130.0);
+            insert_track(db.connection(), 2, "/music/2.mp3", "Techno", 130.0);
+            set_tags(&db, 1, "Artist", "Title", 280.0);
+            set_tags(&db, 2, "Artist", "Title", 280.0);
+            insert_cue(db.connection(), 2, 1, 0);
+            let tracks = TrackQuery::new().load(&db).unwrap();
+            let groups =
+                find_duplicates(&db, tracks, &HashMap::new(), &[DuplicateMatch::Fuzzy]).unwrap();
+
+            // run
+            let plan = plan_merges(&db, &groups, &MergePolicy::default()).unwrap();
+            apply_plan(&mut db, &plan).unwrap();
+
+            // verify
+            let remaining = TrackQuery::new().load(&db).unwrap();
+            assert_eq!(remaining.len(), 1);
+            assert_eq!(remaining[0].id, 2);
+        }
+    }
+}
@@ -0,0 +1,364 @@
+pub mod track_merge {
+    use rusqlite::Transaction;
+    use serde::{Deserialize, Serialize};
+
+    use crate::db_changes::change_plan::change_plan::{
+        plan_track_change, ChangePlan, ChangeTable, ColumnValue,
+    };
+    use crate::db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb;
+    use crate::error::error::LibHelperError;
+    use crate::track::track_query::track_query::TrackQuery;
+
+    /// The tables and columns merging tracks rewrites, besides `library`.
+    pub const MERGE_COLUMNS: &[(&str, &[&str])] = &[
+        ("crate_tracks", &["crate_id", "track_id"]),
+        ("PlaylistTracks", &["track_id"]),
+        ("cues", &["track_id", "type", "hotcue"]),
+    ];
+
+    /// How the values of the copies are combined when they are merged into one track.
+    /// Without a field set, the kept track keeps its own value.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(default)]
+    pub struct MergePolicy {
+        /// Add up the play counts and keep the latest time a copy was played
+        pub sum_plays: bool,
+        /// Keep the highest rating of all copies
+        pub max_rating: bool,
+        /// Move the cues of the copies to the hotcue slots the kept track does not use
+        pub union_hotcues: bool,
+    }
+
+    impl Default for MergePolicy {
+        fn default() -> Self {
+            MergePolicy {
+                sum_plays: true,
+                max_rating: true,
+                union_hotcues: true,
+            }
+        }
+    }
+
+    /// Folding copies of a track into one: crates, playlists, the history and
+    /// cues of the dropped tracks are pointed at the kept one.
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct TrackMerge {
+        pub keep_id: usize,
+        pub drop_ids: Vec<usize>,
+        #[serde(default)]
+        pub policy: MergePolicy,
+    }
+
+    /// Plans merging the tracks `drop_ids` into `keep_id`: combining play counts and
+    /// ratings as `policy` says, rewriting the rows referring to the dropped tracks,
+    /// and marking them deleted. Applying the plan does all of it in one transaction.
+    pub fn plan_track_merge(
+        db: &MixxxDb,
+        keep_id: usize,
+        drop_ids: &[usize],
+        policy: &MergePolicy,
+    ) -> Result<ChangePlan, LibHelperError> {
+        require_merge_columns(db)?;
+        if drop_ids.contains(&keep_id) {
+            return Err(LibHelperError::Plan(format!(
+                "track {keep_id} cannot be merged into itself"
+            )));
+        }
+
+        let mut ids = vec![keep_id];
+        ids.extend(drop_ids);
+        let tracks = TrackQuery::new().ids(&ids).include_deleted(true).load(db)?;
+        if let Some(missing) = ids
+            .iter()
+            .find(|id| !tracks.iter().any(|track| track.id == **id))
+        {
+            return Err(LibHelperError::Plan(format!("There is no track {missing}")));
+        }
+
+        let mut plan = ChangePlan::default();
+        let mut plan_keep_change = |column: &str, value: ColumnValue| {
+            if !db.has_column("library", column) {
+                return Ok(());
+            }
+            plan.changes.extend(plan_track_change(
+                db,
+                ChangeTable::Library,
+                keep_id,
+                column,
+                value,
+            )?);
+            Ok::<(), LibHelperError>(())
+        };
+        if policy.sum_plays {
+            let plays: u32 = tracks
+                .iter()
+                .map(|track| track.timesplayed.unwrap_or(0))
+                .sum();
+            plan_keep_change("timesplayed", ColumnValue::Integer(plays.into()))?;
+            // Mixxx writes ISO 8601 timestamps, which sort like the times they stand for
+            if let Some(last_played_at) = tracks
+                .iter()
+                .filter_map(|track| track.last_played_at.clone())
+                .max()
+            {
+                plan_keep_change("last_played_at", ColumnValue::Text(last_played_at))?;
+            }
+        }
+        if policy.max_rating {
+            let rating = tracks
+                .iter()
+                .filter_map(|track| track.rating)
+                .max()
+                .unwrap_or(0);
+            plan_keep_change("rating", ColumnValue::Integer(rating.into()))?;
+        }
+
+        for drop_id in drop_ids {
+            plan.changes.extend(plan_track_change(
+                db,
+                ChangeTable::Library,
+                *drop_id,
+                "mixxx_deleted",
+                ColumnValue::Integer(1),
+            )?);
+        }
+        plan.track_merges.push(TrackMerge {
+            keep_id,
+            drop_ids: drop_ids.to_vec(),
+            policy: *policy,
+        });
+
+        Ok(plan)
+    }
+
+    pub fn require_merge_columns(db: &MixxxDb) -> Result<(), LibHelperError> {
+        db.require_columns("library", &["id", "mixxx_deleted"])?;
+        for (table, columns) in MERGE_COLUMNS {
+            db.require_columns(table, columns)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the rows referring to the dropped tracks as part of `transaction`.
+    ///
+    /// A crate only lists the kept track once. With [`MergePolicy::union_hotcues`],
+    /// cues of the dropped tracks are moved if the kept track has no cue in the same
+    /// hotcue slot, or for cues without a slot, of the same type; the others stay
+    /// with the dropped track.
+    /// The history is a set of hidden playlists, so it moves with `PlaylistTracks`.
+    pub fn merge_track_rows(
+        transaction: &Transaction,
+        merge: &TrackMerge,
+    ) -> Result<(), LibHelperError> {
+        let kept: usize = transaction.query_row(
+            "SELECT COUNT(*) FROM library WHERE id = ?1 AND mixxx_deleted IS NOT 1",
+            [merge.keep_id],
+            |row| row.get(0),
+        )?;
+        if kept != 1 {
+            return Err(LibHelperError::Plan(format!(
+                "track {} is no longer in the library, the plan is outdated",
+                merge.keep_id
+            )));
+        }
+
+        for drop_id in &merge.drop_ids {
+            if *drop_id == merge.keep_id {
+                return Err(LibHelperError::Plan(format!(
+                    "track {drop_id} cannot be merged into itself"
+                )));
+            }
+            let ids = (merge.keep_id, drop_id);
+            transaction.execute(
+                "UPDATE OR IGNORE crate_tracks SET track_id = ?1 WHERE track_id = ?2",
+                ids,
+            )?;
+            transaction.execute("DELETE FROM crate_tracks WHERE track_id = ?2", ids)?;
+            transaction.execute(
+                "UPDATE PlaylistTracks SET track_id = ?1 WHERE track_id = ?2",
+                ids,
+            )?;
+            if !merge.policy.union_hotcues {
+                continue;
+            }
+            transaction.execute(
+                "UPDATE cues SET track_id = ?1 WHERE track_id = ?2 AND NOT EXISTS (
+                     SELECT 1 FROM cues kept WHERE kept.track_id = ?1 AND CASE
+                         WHEN cues.hotcue >= 0 THEN kept.hotcue = cues.hotcue
+                         ELSE kept.hotcue < 0 AND kept.type = cues.type
+                     END
+                 )",
+                ids,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::db_changes::change_plan::change_plan::apply_plan;
+        use crate::test_db::test_db::{insert_cue, insert_track, open_test_mixxx_db};
+
+        fn track_ids(db: &MixxxDb, sql: &str) -> Vec<usize> {
+            let mut stmt = db.connection().prepare(sql).unwrap();
+            let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+            rows.collect::<Result<_, _>>().unwrap()
+        }
+
+        #[test]
+        fn merge_track_rows_repoints_references() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/a/track.mp3", "Trance", 138.0);
+            insert_track(db.connection(), 2, "/music/b/track.mp3", "Trance", 138.0);
+            db.connection()
+                .execute_batch(
+                    "INSERT INTO crates (id, name) VALUES (1, 'Both'), (2, 'Copy');
+                     INSERT INTO crate_tracks VALUES (1, 1), (1, 2), (2, 2);
+                     INSERT INTO Playlists (id, name, hidden) VALUES (1, 'History', 2);
+                     INSERT INTO PlaylistTracks (playlist_id, track_id, position) VALUES (1, 2, 1);",
+                )
+                .unwrap();
+            insert_cue(db.connection(), 1, 1, 0);
+            insert_cue(db.connection(), 2, 1, 0);
+            insert_cue(db.connection(), 2, 1, 3);
+            insert_cue(db.connection(), 2, 2, -1);
+            let merge = TrackMerge {
+                keep_id: 1,
+                drop_ids: vec![2],
+                policy: MergePolicy::default(),
+            };
+
+            // run
+            let transaction = db.connection_mut().transaction().unwrap();
+            merge_track_rows(&transaction, &merge).unwrap();
+            transaction.commit().unwrap();
+
+            // verify
+            assert_eq!(
+                track_ids(&db, "SELECT track_id FROM crate_tracks ORDER BY crate_id"),
+                vec![1, 1]
+            );
+            assert_eq!(
+                track_ids(&db, "SELECT track_id FROM PlaylistTracks"),
+                vec![1]
+            );
+            // the cue in the occupied hotcue slot 0 stays with the dropped track
+            assert_eq!(
+                track_ids(&db, "SELECT track_id FROM cues ORDER BY id"),
+                vec![1, 2, 1, 1]
+            );
+        }
+
+        #[test]
+        fn merge_track_rows_fails_for_deleted_survivor() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/a/track.mp3", "Trance", 138.0);
+            insert_track(db.connection(), 2, "/music/b/track.mp3", "Trance", 138.0);
+            db.connection()
+                .execute("UPDATE library SET mixxx_deleted = 1 WHERE id = 1", [])
+                .unwrap();
+            let merge = TrackMerge {
+                keep_id: 1,
+                drop_ids: vec![2],
+                policy: MergePolicy::default(),
+            };
+
+            // run
+            let transaction = db.connection_mut().transaction().unwrap();
+            let result = merge_track_rows(&transaction, &merge);
+
+            // verify
+            assert!(result.is_err());
+        }
+
+        #[test]
+        fn plan_track_merge_combines_plays_and_ratings() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            for id in 1..=3 {
+                insert_track(
+                    db.connection(),
+                    id,
+                    &format!("/music/{id}.mp3"),
+                    "Techno",
+                    130.0,
+                );
+            }
+            db.connection()
+                .execute_batch(
+                    "UPDATE library SET timesplayed = 4, rating = 2, last_played_at = '2024-05-01 22:00:00' WHERE id = 1;
+                     UPDATE library SET timesplayed = 3, rating = 5, last_played_at = '2025-01-10 23:30:00' WHERE id = 2;
+                     UPDATE library SET timesplayed = 1, rating = 0 WHERE id = 3;",
+                )
+                .unwrap();
+
+            // run
+            let plan = plan_track_merge(&db, 1, &[2, 3], &MergePolicy::default()).unwrap();
+            apply_plan(&mut db, &plan).unwrap();
+
+            // verify
+            let tracks = TrackQuery::new()
+                .ids(&[1, 2, 3])
+                .include_deleted(true)
+                .load(&db)
+                .unwrap();
+            assert_eq!(tracks[0].timesplayed, Some(8));
+            assert_eq!(tracks[0].rating, Some(5));
+            assert_eq!(
+                tracks[0].last_played_at.as_deref(),
+                Some("2025-01-10 23:30:00")
+            );
+            assert!(!tracks[0].mixxx_deleted);
+            assert!(tracks[1].mixxx_deleted && tracks[2].mixxx_deleted);
+        }
+
+        #[test]
+        fn plan_track_merge_keeps_own_values_without_policy() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/1.mp3", "Techno", 130.0);
+            insert_track(db.connection(), 2, "/music/2.mp3", "Techno", 130.0);
+            db.connection()
+                .execute(
+                    "UPDATE library SET timesplayed = 3, rating = 5 WHERE id = 2",
+                    [],
+                )
+                .unwrap();
+            insert_cue(db.connection(), 2, 1, 0);
+            let policy = MergePolicy {
+                sum_plays: false,
+                max_rating: false,
+                union_hotcues: false,
+            };
+
+            // run
+            let plan = plan_track_merge(&db, 1, &[2], &policy).unwrap();
+            apply_plan(&mut db, &plan).unwrap();
+
+            // verify
+            let kept = &TrackQuery::new().ids(&[1]).load(&db).unwrap()[0];
+            assert_eq!(kept.timesplayed, Some(0));
+            assert_eq!(kept.rating, Some(0));
+            assert_eq!(track_ids(&db, "SELECT track_id FROM cues"), vec![2]);
+        }
+
+        #[test]
+        fn plan_track_merge_rejects_unknown_tracks() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/1.mp3", "Techno", 130.0);
+
+            // run
+            let unknown = plan_track_merge(&db, 1, &[2], &MergePolicy::default());
+            let itself = plan_track_merge(&db, 1, &[1], &MergePolicy::default());
+
+            // verify
+            assert!(unknown.is_err());
+            assert!(itself.is_err());
+        }
+    }
+}
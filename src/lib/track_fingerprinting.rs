@@ -1 +1,6 @@
+pub mod acoustic;
+pub mod audio_payload;
+pub mod fingerprint_cache;
+pub mod fingerprint_pool;
+pub mod read_control;
 pub mod track_fingerprinting;
@@ -0,0 +1,220 @@
+pub mod acoustic {
+    use std::f64::consts::PI;
+
+    use crate::track_analysis::audio_decode::audio_decode::ANALYSIS_SAMPLE_RATE;
+
+    /// How much of the start of a track the fingerprint covers.
+    pub const ACOUSTIC_FINGERPRINT_SECONDS: u32 = 120;
+
+    /// Similarities from here on are taken as the same recording.
+    /// Unrelated audio scores close to 0, re-encodes of the same audio close to 1.
+    pub const SAME_RECORDING_SIMILARITY: f64 = 0.5;
+
+    /// About 190 ms at the analysis sample rate
+    const FRAME_LENGTH: usize = 2048;
+    /// About 46 ms, fine enough that encoder delays shift the frames by less than one
+    const HOP_LENGTH: usize = 512;
+    /// One bit per pair of neighbouring bands
+    const BAND_COUNT: usize = 33;
+    /// The range the fingerprint bands cover, where most of the tonal content is.
+    const MIN_FREQUENCY: f64 = 300.0;
+    const MAX_FREQUENCY: f64 = 2000.0;
+    /// How far, in frames, two fingerprints are shifted against each other to line up.
+    const MAX_OFFSET_FRAMES: usize = 24;
+
+    /// Computes one 32-bit sub-fingerprint per frame of mono samples at
+    /// [`ANALYSIS_SAMPLE_RATE`], as in the Philips audio fingerprint: each bit tells
+    /// whether the energy difference between two neighbouring bands grew since the
+    /// previous frame. Those signs survive re-encoding, resampling and volume changes.
+    pub fn acoustic_fingerprint(samples: &[f32]) -> Vec<u32> {
+        let band_bins = band_bins();
+        let window: Vec<f64> = (0..FRAME_LENGTH)
+            .map(|index| 0.5 - 0.5 * (2.0 * PI * index as f64 / FRAME_LENGTH as f64).cos())
+            .collect();
+
+        let mut previous: Option<[f64; BAND_COUNT]> = None;
+        let mut sub_fingerprints = vec![];
+        let mut start = 0;
+        while start + FRAME_LENGTH <= samples.len() {
+            let mut spectrum: Vec<(f64, f64)> = samples[start..start + FRAME_LENGTH]
+                .iter()
+                .zip(&window)
+                .map(|(&sample, &weight)| (f64::from(sample) * weight, 0.0))
+                .collect();
+            fft(&mut spectrum);
+
+            let mut energies = [0.0; BAND_COUNT];
+            for (band, (first_bin, last_bin)) in band_bins.iter().enumerate() {
+                energies[band] = spectrum[*first_bin..*last_bin]
+                    .iter()
+                    .map(|(re, im)| re * re + im * im)
+                    .sum();
+            }
+
+            if let Some(previous) = previous {
+                let mut bits = 0u32;
+                for band in 0..BAND_COUNT - 1 {
+                    let difference = (energies[band] - energies[band + 1])
+                        - (previous[band] - previous[band + 1]);
+                    if difference > 0.0 {
+                        bits |= 1 << band;
+                    }
+                }
+                sub_fingerprints.push(bits);
+            }
+            previous = Some(energies);
+            start += HOP_LENGTH;
+        }
+
+        sub_fingerprints
+    }
+
+    /// How alike two fingerprints are, from 0 for unrelated audio to 1 for the same.
+    ///
+    /// The fingerprints are lined up at the shift with the fewest differing bits,
+    /// which makes up for different encoder delays and leading silence.
+    pub fn acoustic_similarity(a: &[u32], b: &[u32]) -> f64 {
+        let mut lowest_error_rate: Option<f64> = None;
+        for offset in -(MAX_OFFSET_FRAMES as isize)..=MAX_OFFSET_FRAMES as isize {
+            let (a, b) = if offset < 0 {
+                (a, b.get(offset.unsigned_abs()..).unwrap_or_default())
+            } else {
+                (a.get(offset as usize..).unwrap_or_default(), b)
+            };
+            let overlap = a.len().min(b.len());
+            // too little overlap says nothing
+            if overlap == 0 || overlap < a.len().max(b.len()) / 2 {
+                continue;
+            }
+
+            let differing_bits: u32 = a.iter().zip(b).map(|(a, b)| (a ^ b).count_ones()).sum();
+            let error_rate = f64::from(differing_bits) / (overlap * 32) as f64;
+            if lowest_error_rate.is_none_or(|lowest| error_rate < lowest) {
+                lowest_error_rate = Some(error_rate);
+            }
+        }
+
+        // unrelated fingerprints differ in about half of their bits
+        lowest_error_rate.map_or(0.0, |error_rate| (1.0 - 2.0 * error_rate).clamp(0.0, 1.0))
+    }
+
+    /// Lowercase hex, eight digits per sub-fingerprint.
+    pub fn encode_acoustic_fingerprint(sub_fingerprints: &[u32]) -> String {
+        sub_fingerprints
+            .iter()
+            .map(|bits| format!("{bits:08x}"))
+            .collect()
+    }
+
+    /// The reverse of [`encode_acoustic_fingerprint`], `None` for anything else.
+    pub fn decode_acoustic_fingerprint(encoded: &str) -> Option<Vec<u32>> {
+        if !encoded.len().is_multiple_of(8) || !encoded.is_ascii() {
+            return None;
+        }
+        (0..encoded.len())
+            .step_by(8)
+            .map(|start| u32::from_str_radix(&encoded[start..start + 8], 16).ok())
+            .collect()
+    }
+
+    /// The FFT bins, as half-open ranges, of bands spaced evenly in pitch.
+    fn band_bins() -> Vec<(usize, usize)> {
+        let bin = |frequency: f64| {
+            (frequency * FRAME_LENGTH as f64 / f64::from(ANALYSIS_SAMPLE_RATE)).round() as usize
+        };
+        let ratio = (MAX_FREQUENCY / MIN_FREQUENCY).powf(1.0 / BAND_COUNT as f64);
+        (0..BAND_COUNT)
+            .map(|band| {
+                let low = MIN_FREQUENCY * ratio.powi(band as i32);
+                (bin(low), bin(low * ratio).max(bin(low) + 1))
+            })
+            .collect()
+    }
+
+    /// In-place radix-2 FFT of (real, imaginary) pairs. The length has to be a power of two.
+    fn fft(values: &mut [(f64, f64)]) {
+        let length = values.len();
+        let mut j = 0;
+        for i in 1..length {
+            let mut bit = length >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                values.swap(i, j);
+            }
+        }
+
+        let mut size = 2;
+        while size <= length {
+            let angle = -2.0 * PI / size as f64;
+            for start in (0..length).step_by(size) {
+                for k in 0..size / 2 {
+                    let (sin, cos) = (angle * k as f64).sin_cos();
+                    let (re, im) = values[start + k + size / 2];
+                    let twiddled = (re * cos - im * sin, re * sin + im * cos);
+                    let even = values[start + k];
+                    values[start + k] = (even.0 + twiddled.0, even.1 + twiddled.1);
+                    values[start + k + size / 2] = (even.0 - twiddled.0, even.1 - twiddled.1);
+                }
+            }
+            size *= 2;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::music;
+
+        #[test]
+        fn acoustic_similarity_tells_copies_from_other_audio() {
+            // setup
+            let original = music(1, 20);
+            // quieter, with some noise and a delay, as a lossy re-encode would have
+            let noise = music(3, 21);
+            let mut copy: Vec<f32> = vec![0.0; 300];
+            copy.extend(
+                original
+                    .iter()
+                    .zip(&noise)
+                    .map(|(sample, noise)| 0.6 * sample + 0.02 * noise),
+            );
+            let other = music(2, 20);
+
+            // run
+            let original = acoustic_fingerprint(&original);
+            let copy_similarity = acoustic_similarity(&original, &acoustic_fingerprint(&copy));
+            let other_similarity = acoustic_similarity(&original, &acoustic_fingerprint(&other));
+
+            // verify
+            assert!(
+                copy_similarity > SAME_RECORDING_SIMILARITY,
+                "{copy_similarity}"
+            );
+            assert!(
+                other_similarity < SAME_RECORDING_SIMILARITY,
+                "{other_similarity}"
+            );
+        }
+
+        #[test]
+        fn acoustic_fingerprint_encoding_round_trips() {
+            // setup
+            let sub_fingerprints = vec![0, 1, 0xdeadbeef, u32::MAX];
+
+            // run
+            let encoded = encode_acoustic_fingerprint(&sub_fingerprints);
+
+            // verify
+            assert_eq!(encoded, "0000000000000001deadbeefffffffff");
+            assert_eq!(
+                decode_acoustic_fingerprint(&encoded),
+                Some(sub_fingerprints)
+            );
+            assert_eq!(decode_acoustic_fingerprint("xyz"), None);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+pub mod audio_payload {
+    use std::collections::HashMap;
+    use std::io::{self, BufReader, Read, Seek, SeekFrom};
+    use std::path::Path;
+
+    use sha2::{Digest, Sha256};
+
+    use crate::error::error::LibHelperError;
+    use crate::track::file_type::file_type::{detect_format, id3v2_length, AudioFormat};
+    use crate::track_fingerprinting::read_control::read_control::{
+        ControlledFile, ReadControl, HASH_BUFFER_BYTES,
+    };
+
+    /// SHA-256 of the audio data of a file with its tags left out, so the hash
+    /// stays the same when the tags are edited. `None` for content that is no
+    /// audio format this module can find the audio data of.
+    /// The file is read in chunks of [`HASH_BUFFER_BYTES`] through `control`.
+    pub fn hash_audio_payload(
+        path: &Path,
+        control: &ReadControl,
+    ) -> Result<Option<String>, LibHelperError> {
+        let Some(format) = detect_format(path)? else {
+            return Ok(None);
+        };
+        let mut file = BufReader::with_capacity(HASH_BUFFER_BYTES, control.open(path)?);
+        let mut hasher = Sha256::new();
+
+        let result = match format {
+            AudioFormat::Mp3 | AudioFormat::Aac => hash_frames(&mut file, &mut hasher),
+            AudioFormat::Flac => hash_flac_frames(&mut file, &mut hasher),
+            AudioFormat::OggVorbis | AudioFormat::Opus | AudioFormat::Ogg => {
+                hash_ogg_packets(&mut file, &mut hasher)
+            }
+            AudioFormat::Wav => hash_chunk(&mut file, &mut hasher, b"data", u32::from_le_bytes),
+            AudioFormat::Aiff => hash_chunk(&mut file, &mut hasher, b"SSND", u32::from_be_bytes),
+            AudioFormat::Mp4 => hash_mdat(&mut file, &mut hasher),
+        };
+        result.map_err(|message| LibHelperError::Fingerprint {
+            path: path.to_string_lossy().to_string(),
+            message,
+        })?;
+
+        Ok(Some(format!("{:x}", hasher.finalize())))
+    }
+
+    type Reader = BufReader<ControlledFile>;
+
+    /// MPEG or ADTS frames, between a leading ID3v2 tag and trailing APE, Lyrics3 and ID3v1 tags.
+    fn hash_frames(file: &mut Reader, hasher: &mut Sha256) -> Result<(), String> {
+        let start = leading_id3v2_length(file)?;
+        let end = audio_end(file)?;
+        hash_range(file, hasher, start, end.saturating_sub(start))
+    }
+
+    /// The audio frames after the metadata blocks.
+    fn hash_flac_frames(file: &mut Reader, hasher: &mut Sha256) -> Result<(), String> {
+        let mut position = leading_id3v2_length(file)?;
+        let end = audio_end(file)?;
+        let mut marker = [0; 4];
+        read_at(file, position, &mut marker)?;
+        if &marker != b"fLaC" {
+            return Err(String::from("not a FLAC file"));
+        }
+        position += 4;
+
+        loop {
+            let mut header = [0; 4];
+            read_at(file, position, &mut header)?;
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+            position += 4 + u64::from(length);
+            if header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+
+        hash_range(file, hasher, position, end.saturating_sub(position))
+    }
+
+    /// Every packet of every logical stream, except the comment header, which is the
+    /// second packet of a stream for Vorbis, Opus, FLAC and Speex alike.
+    /// Page headers are left out too, as their sequence numbers and checksums
+    /// change when a longer comment header needs more pages.
+    fn hash_ogg_packets(file: &mut Reader, hasher: &mut Sha256) -> Result<(), String> {
+        file.seek(SeekFrom::Start(0))
+            .map_err(|err| err.to_string())?;
+        let mut packet_numbers: HashMap<u32, usize> = HashMap::new();
+        loop {
+            let mut header = [0; 27];
+            match file.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err.to_string()),
+            }
+            if &header[0..4] != b"OggS" {
+                return Err(String::from("lost Ogg page sync"));
+            }
+            let serial = u32::from_le_bytes([header[14], header[15], header[16], header[17]]);
+            let mut lacing = vec![0; header[26] as usize];
+            file.read_exact(&mut lacing)
+                .map_err(|_| String::from("file ends unexpectedly"))?;
+
+            let packet_number = packet_numbers.entry(serial).or_default();
+            for &length in &lacing {
+                let mut segment = vec![0; length as usize];
+                file.read_exact(&mut segment)
+                    .map_err(|_| String::from("file ends unexpectedly"))?;
+                if *packet_number != 1 {
+                    hasher.update(&segment);
+                }
+                if length < 255 {
+                    *packet_number += 1;
+                }
+            }
+        }
+    }
+
+    /// The body of the first chunk named `name` of a RIFF or IFF file.
+    fn hash_chunk(
+        file: &mut Reader,
+        hasher: &mut Sha256,
+        name: &[u8; 4],
+        read_size: fn([u8; 4]) -> u32,
+    ) -> Result<(), String> {
+        let file_length = length(file)?;
+        // after the RIFF or FORM header
+        let mut position = 12;
+        while position + 8 <= file_length {
+            let mut header = [0; 8];
+            read_at(file, position, &mut header)?;
+            let size = u64::from(read_size(header[4..8].try_into().unwrap()));
+            if &header[0..4] == name {
+                let size = size.min(file_length - position - 8);
+                return hash_range(file, hasher, position + 8, size);
+            }
+            // chunks are padded to an even length
+            position += 8 + size + size % 2;
+        }
+
+        Err(format!("no {} chunk", String::from_utf8_lossy(name)))
+    }
+
+    /// The bodies of the top-level `mdat` atoms, which hold the encoded audio.
+    fn hash_mdat(file: &mut Reader, hasher: &mut Sha256) -> Result<(), String> {
+        let file_length = length(file)?;
+        let mut position = 0;
+        let mut found = false;
+        while position + 8 <= file_length {
+            let mut header = [0; 8];
+            read_at(file, position, &mut header)?;
+            let mut size = u64::from(u32::from_be_bytes(header[0..4].try_into().unwrap()));
+            let mut header_length = 8;
+            if size == 1 {
+                let mut large_size = [0; 8];
+                read_at(file, position + 8, &mut large_size)?;
+                size = u64::from_be_bytes(large_size);
+                header_length = 16;
+            } else if size == 0 {
+                size = file_length - position;
+            }
+            if size < header_length {
+                return Err(String::from("invalid atom size"));
+            }
+
+            if &header[4..8] == b"mdat" {
+                let body_length =
+                    (size - header_length).min(file_length - position - header_length);
+                hash_range(file, hasher, position + header_length, body_length)?;
+                found = true;
+            }
+            position = position
+                .checked_add(size)
+                .ok_or_else(|| String::from("invalid atom size"))?;
+        }
+
+        if !found {
+            return Err(String::from("no mdat atom"));
+        }
+        Ok(())
+    }
+
+    fn leading_id3v2_length(file: &mut Reader) -> Result<u64, String> {
+        file.seek(SeekFrom::Start(0))
+            .map_err(|err| err.to_string())?;
+        // shorter files have no tag
+        let mut header = vec![];
+        file.take(10)
+            .read_to_end(&mut header)
+            .map_err(|err| err.to_string())?;
+        Ok(id3v2_length(&header).unwrap_or(0))
+    }
+
+    /// Where the audio data ends, before the tags MP3 files can have at their end.
+    fn audio_end(file: &mut Reader) -> Result<u64, String> {
+        let mut end = length(file)?;
+        loop {
+            if end >= 128 {
+                let mut marker = [0; 3];
+                read_at(file, end - 128, &mut marker)?;
+                if &marker == b"TAG" {
+                    end -= 128;
+                    continue;
+                }
+            }
+            if end >= 32 {
+                let mut footer = [0; 32];
+                read_at(file, end - 32, &mut footer)?;
+                if footer.starts_with(b"APETAGEX") {
+                    // the size covers items and footer, the flags tell whether a header precedes them
+                    let size = u64::from(u32::from_le_bytes(footer[12..16].try_into().unwrap()));
+                    let flags = u32::from_le_bytes(footer[20..24].try_into().unwrap());
+                    let header = if flags & 0x8000_0000 != 0 { 32 } else { 0 };
+                    end = end.saturating_sub(size + header);
+                    continue;
+                }
+            }
+            if end >= 15 {
+                let mut footer = [0; 15];
+                read_at(file, end - 15, &mut footer)?;
+                if &footer[6..15] == b"LYRICS200" {
+                    if let Some(size) = std::str::from_utf8(&footer[0..6])
+                        .ok()
+                        .and_then(|size| size.parse::<u64>().ok())
+                    {
+                        end = end.saturating_sub(size + 15);
+                        continue;
+                    }
+                }
+            }
+            return Ok(end);
+        }
+    }
+
+    fn hash_range(
+        file: &mut Reader,
+        hasher: &mut Sha256,
+        start: u64,
+        length: u64,
+    ) -> Result<(), String> {
+        file.seek(SeekFrom::Start(start))
+            .map_err(|err| err.to_string())?;
+        let copied = io::copy(&mut file.take(length), hasher).map_err(|err| err.to_string())?;
+        if copied < length {
+            return Err(String::from("file ends unexpectedly"));
+        }
+        Ok(())
+    }
+
+    fn read_at(file: &mut Reader, position: u64, buffer: &mut [u8]) -> Result<(), String> {
+        file.seek(SeekFrom::Start(position))
+            .map_err(|err| err.to_string())?;
+        file.read_exact(buffer)
+            .map_err(|_| String::from("file ends unexpectedly"))
+    }
+
+    fn length(file: &mut Reader) -> Result<u64, String> {
+        file.get_ref()
+            .file()
+            .metadata()
+            .map(|metadata| metadata.len())
+            .map_err(|err| err.to_string())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const MPEG_FRAMES: &[u8] = b"\xFF\xFB\x90\x00frame one\xFF\xFB\x90\x00frame two";
+
+        fn hash_of(dir: &Path, file_name: &str, content: &[u8]) -> Option<String> {
+            let path = dir.join(file_name);
+            std::fs::write(&path, content).unwrap();
+            hash_audio_payload(&path, &ReadControl::default()).unwrap()
+        }
+
+        fn id3v2_tag(body: &[u8]) -> Vec<u8> {
+            let mut tag = b"ID3\x04\x00\x00\x00\x00\x00".to_vec();
+            tag.push(body.len() as u8);
+            tag.extend_from_slice(body);
+            tag
+        }
+
+        fn id3v1_tag(title: &str) -> Vec<u8> {
+            let mut tag = b"TAG".to_vec();
+            tag.extend_from_slice(title.as_bytes());
+            tag.resize(128, 0);
+            tag
+        }
+
+        fn ogg_page(serial: u32, packet: &[u8]) -> Vec<u8> {
+            let mut page = b"OggS\0\0".to_vec();
+            page.extend_from_slice(&[0; 8]);
+            page.extend_from_slice(&serial.to_le_bytes());
+            page.extend_from_slice(&[0; 8]);
+            page.push(1);
+            page.push(packet.len() as u8);
+            page.extend_from_slice(packet);
+            page
+        }
+
+        #[test]
+        fn hash_audio_payload_ignores_mp3_tags() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let mut tagged = id3v2_tag(b"TIT2 a title");
+            tagged.extend_from_slice(MPEG_FRAMES);
+            tagged.extend_from_slice(&id3v1_tag("a title"));
+
+            // run
+            let untagged = hash_of(dir.path(), "untagged.mp3", MPEG_FRAMES);
+            let tagged = hash_of(dir.path(), "tagged.mp3", &tagged);
+            let other = hash_of(dir.path(), "other.mp3", b"\xFF\xFB\x90\x00\x01");
+
+            // verify
+            assert!(untagged.is_some());
+            assert_eq!(untagged, tagged);
+            assert_ne!(untagged, other);
+        }
+
+        #[test]
+        fn hash_audio_payload_ignores_ogg_comment_header() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let ogg = |comments: &[u8]| {
+                let mut ogg = ogg_page(7, b"OpusHead");
+                ogg.extend_from_slice(&ogg_page(7, comments));
+                ogg.extend_from_slice(&ogg_page(7, b"audio packet"));
+                ogg
+            };
+
+            // run
+            let before = hash_of(dir.path(), "before.opus", &ogg(b"OpusTags one"));
+            let after = hash_of(dir.path(), "after.opus", &ogg(b"OpusTags another"));
+
+            // verify
+            assert!(before.is_some());
+            assert_eq!(before, after);
+        }
+
+        #[test]
+        fn hash_audio_payload_hashes_wav_data_chunk() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let wav = |extra_chunk: &[u8]| {
+                let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+                wav.extend_from_slice(extra_chunk);
+                wav.extend_from_slice(b"data\x04\0\0\0\x01\x02\x03\x04");
+                wav
+            };
+
+            // run
+            let plain = hash_of(dir.path(), "plain.wav", &wav(b""));
+            let tagged = hash_of(dir.path(), "tagged.wav", &wav(b"id3 \x03\0\0\0ID3\0"));
+            let unknown = hash_of(dir.path(), "unknown.dat", b"no audio");
+
+            // verify
+            assert!(plain.is_some());
+            assert_eq!(plain, tagged);
+            assert_eq!(unknown, None);
+        }
+
+        #[test]
+        fn hash_audio_payload_rejects_mp4_atom_larger_than_possible() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.m4a");
+            let mut mp4 = b"\0\0\0\x10ftypM4A \0\0\0\0".to_vec();
+            mp4.extend_from_slice(b"\0\0\0\x0dmdatAUDIO");
+            mp4.extend_from_slice(b"\0\0\0\x01free");
+            mp4.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+            std::fs::write(&path, &mp4).unwrap();
+
+            // run
+            let result = hash_audio_payload(&path, &ReadControl::default());
+
+            // verify
+            assert!(matches!(
+                result,
+                Err(LibHelperError::Fingerprint { message, .. }) if message == "invalid atom size"
+            ));
+        }
+    }
+}
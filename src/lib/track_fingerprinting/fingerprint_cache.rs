@@ -0,0 +1,343 @@
+pub mod fingerprint_cache {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::time::UNIX_EPOCH;
+
+    use rusqlite::{Connection, OptionalExtension};
+
+    use crate::error::error::LibHelperError;
+    use crate::track_fingerprinting::track_fingerprinting::track_fingerprinting::{
+        get_track_fingerprint, Fingerprint, FingerprintAlgorithm,
+    };
+
+    /// Name of the cache file, kept next to the Mixxx database but never inside it.
+    pub const FINGERPRINT_CACHE_FILE_NAME: &str = "libhelper-fingerprints.sqlite";
+
+    /// Bumped when the layout of the cache changes; older caches are emptied.
+    const CACHE_VERSION: i64 = 1;
+
+    /// What identifies a version of a file: a fingerprint is only reused while all of it is the same.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct FileStamp {
+        /// `None` on platforms without inodes
+        pub inode: Option<u64>,
+        pub size: u64,
+        /// Modification time in nanoseconds since the Unix epoch
+        pub mtime_ns: i64,
+    }
+
+    impl FileStamp {
+        pub fn of(path: &Path) -> Result<FileStamp, std::io::Error> {
+            let metadata = fs::metadata(path)?;
+            let mtime_ns = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_nanos() as i64)
+                .unwrap_or_default();
+            Ok(FileStamp {
+                inode: inode(&metadata),
+                size: metadata.len(),
+                mtime_ns,
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    fn inode(metadata: &fs::Metadata) -> Option<u64> {
+        use std::os::unix::fs::MetadataExt;
+        Some(metadata.ino())
+    }
+
+    #[cfg(not(unix))]
+    fn inode(_metadata: &fs::Metadata) -> Option<u64> {
+        None
+    }
+
+    /// Whether a fingerprint came from the cache or had to be computed.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum CacheOutcome {
+        Cached,
+        Computed,
+    }
+
+    #[derive(Clone, Debug, Default, PartialEq)]
+    pub struct CacheStats {
+        pub files: usize,
+        /// Stored fingerprints per algorithm name, including files that have none
+        pub entries: BTreeMap<String, usize>,
+        /// Files that changed or disappeared since they were fingerprinted
+        pub stale_files: usize,
+        /// Size of the cache file in bytes
+        pub size: u64,
+    }
+
+    /// Fingerprints of files, stored in a SQLite file of their own so computing
+    /// them once is enough as long as the files do not change.
+    pub struct FingerprintCache {
+        connection: Connection,
+        path: PathBuf,
+    }
+
+    impl FingerprintCache {
+        /// Opens the cache, creating it if it does not exist yet.
+        pub fn open(path: &Path) -> Result<FingerprintCache, LibHelperError> {
+            let connection = Connection::open(path).map_err(|source| LibHelperError::DbOpen {
+                path: path.to_string_lossy().to_string(),
+                source,
+            })?;
+            let version: i64 = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+            if version != CACHE_VERSION {
+                connection.execute_batch("DROP TABLE IF EXISTS fingerprints;")?;
+            }
+            connection.execute_batch(&format!(
+                "CREATE TABLE IF NOT EXISTS fingerprints (
+                     path TEXT NOT NULL,
+                     algorithm TEXT NOT NULL,
+                     inode INTEGER,
+                     size INTEGER NOT NULL,
+                     mtime_ns INTEGER NOT NULL,
+                     fingerprint TEXT,
+                     PRIMARY KEY (path, algorithm)
+                 );
+                 PRAGMA user_version = {CACHE_VERSION};"
+            ))?;
+
+            Ok(FingerprintCache {
+                connection,
+                path: path.to_path_buf(),
+            })
+        }
+
+        /// The cache next to the Mixxx database at `mixxx_db_path`.
+        pub fn default_path(mixxx_db_path: &str) -> PathBuf {
+            Path::new(mixxx_db_path)
+                .parent()
+                .unwrap_or(Path::new("."))
+                .join(FINGERPRINT_CACHE_FILE_NAME)
+        }
+
+        /// The stored fingerprint if the file is unchanged since it was computed.
+        /// The outer `None` means there is none stored, the inner one that the file
+        /// has no fingerprint of this kind, e.g. no audio payload that could be found.
+        pub fn get(
+            &self,
+            path: &Path,
+            algorithm: &FingerprintAlgorithm,
+            stamp: &FileStamp,
+        ) -> Result<Option<Option<Fingerprint>>, LibHelperError> {
+            let row: Option<(Option<i64>, i64, i64, Option<String>)> = self
+                .connection
+                .query_row(
+                    "SELECT inode, size, mtime_ns, fingerprint FROM fingerprints
+                     WHERE path = ?1 AND algorithm = ?2",
+                    (path.to_string_lossy(), algorithm.name()),
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )
+                .optional()?;
+
+            Ok(row
+                .filter(|(inode, size, mtime_ns, _)| {
+                    *inode == stamp.inode.map(|inode| inode as i64)
+                        && *size == stamp.size as i64
+                        && *mtime_ns == stamp.mtime_ns
+                })
+                .map(|(_, _, _, fingerprint)| {
+                    fingerprint.map(|fingerprint| Fingerprint {
+                        fingerprint,
+                        fingerprint_algorithm: algorithm.clone(),
+                    })
+                }))
+        }
+
+        /// The fingerprint last stored for the file, even if it changed or is gone
+        /// since, e.g. to recognize a file that was moved and re-encoded.
+        pub fn last_known(
+            &self,
+            path: &Path,
+            algorithm: &FingerprintAlgorithm,
+        ) -> Result<Option<Fingerprint>, LibHelperError> {
+            let fingerprint: Option<Option<String>> = self
+                .connection
+                .query_row(
+                    "SELECT fingerprint FROM fingerprints WHERE path = ?1 AND algorithm = ?2",
+                    (path.to_string_lossy(), algorithm.name()),
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            Ok(fingerprint.flatten().map(|fingerprint| Fingerprint {
+                fingerprint,
+                fingerprint_algorithm: algorithm.clone(),
+            }))
+        }
+
+        /// Stores the fingerprint of the file in the version `stamp` describes, replacing older ones.
+        pub fn put(
+            &self,
+            path: &Path,
+            algorithm: &FingerprintAlgorithm,
+            stamp: &FileStamp,
+            fingerprint: Option<&Fingerprint>,
+        ) -> Result<(), LibHelperError> {
+            self.connection.execute(
+                "INSERT OR REPLACE INTO fingerprints (path, algorithm, inode, size, mtime_ns, fingerprint)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                (
+                    path.to_string_lossy(),
+                    algorithm.name(),
+                    stamp.inode.map(|inode| inode as i64),
+                    stamp.size as i64,
+                    stamp.mtime_ns,
+                    fingerprint.map(|fingerprint| fingerprint.fingerprint.as_str()),
+                ),
+            )?;
+            Ok(())
+        }
+
+        /// Runs `write` in a single transaction, which makes many [`FingerprintCache::put`]s much faster.
+        pub fn batch<T>(
+            &self,
+            write: impl FnOnce(&FingerprintCache) -> Result<T, LibHelperError>,
+        ) -> Result<T, LibHelperError> {
+            let transaction = self.connection.unchecked_transaction()?;
+            let result = write(self)?;
+            transaction.commit()?;
+            Ok(result)
+        }
+
+        /// The fingerprint from the cache, or computed and stored if the file changed or is new.
+        pub fn fingerprint(
+            &self,
+            path: &Path,
+            algorithm: &FingerprintAlgorithm,
+        ) -> Result<(Option<Fingerprint>, CacheOutcome), LibHelperError> {
+            let stamp = FileStamp::of(path)?;
+            if let Some(fingerprint) = self.get(path, algorithm, &stamp)? {
+                return Ok((fingerprint, CacheOutcome::Cached));
+            }
+
+            let fingerprint = get_track_fingerprint(path, algorithm)?;
+            self.put(path, algorithm, &stamp, fingerprint.as_ref())?;
+            Ok((fingerprint, CacheOutcome::Computed))
+        }
+
+        /// Counts the stored fingerprints and checks every file for changes.
+        pub fn stats(&self) -> Result<CacheStats, LibHelperError> {
+            let mut stats = CacheStats {
+                size: fs::metadata(&self.path).map_or(0, |metadata| metadata.len()),
+                ..CacheStats::default()
+            };
+
+            let mut stmt = self
+                .connection
+                .prepare("SELECT algorithm, COUNT(*) FROM fingerprints GROUP BY algorithm")?;
+            let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            stats.entries = rows.collect::<Result<BTreeMap<String, usize>, _>>()?;
+
+            let mut stmt = self.connection.prepare(
+                "SELECT path, inode, size, mtime_ns FROM fingerprints
+                 GROUP BY path, inode, size, mtime_ns",
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    FileStamp {
+                        inode: row.get::<_, Option<i64>>(1)?.map(|inode| inode as u64),
+                        size: row.get::<_, i64>(2)? as u64,
+                        mtime_ns: row.get(3)?,
+                    },
+                ))
+            })?;
+            // a file counts as stale if any of its fingerprints is
+            let mut stale_by_path: BTreeMap<String, bool> = BTreeMap::new();
+            for row in rows {
+                let (path, stamp) = row?;
+                let stale = FileStamp::of(Path::new(&path)).ok() != Some(stamp);
+                *stale_by_path.entry(path).or_default() |= stale;
+            }
+            stats.files = stale_by_path.len();
+            stats.stale_files = stale_by_path.values().filter(|stale| **stale).count();
+
+            Ok(stats)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn fingerprint_is_reused_until_the_file_changes() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let cache = FingerprintCache::open(&dir.path().join("cache.sqlite")).unwrap();
+            let file = dir.path().join("track.dat");
+            fs::write(&file, "first").unwrap();
+
+            // run
+            let (first, first_outcome) = cache
+                .fingerprint(&file, &FingerprintAlgorithm::SHA265)
+                .unwrap();
+            let (_, second_outcome) = cache
+                .fingerprint(&file, &FingerprintAlgorithm::SHA265)
+                .unwrap();
+            fs::write(&file, "second, longer").unwrap();
+            let (changed, changed_outcome) = cache
+                .fingerprint(&file, &FingerprintAlgorithm::SHA265)
+                .unwrap();
+
+            // verify
+            assert_eq!(first_outcome, CacheOutcome::Computed);
+            assert_eq!(second_outcome, CacheOutcome::Cached);
+            assert_eq!(changed_outcome, CacheOutcome::Computed);
+            assert_ne!(first, changed);
+        }
+
+        #[test]
+        fn fingerprint_caches_files_without_payload() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let cache = FingerprintCache::open(&dir.path().join("cache.sqlite")).unwrap();
+            let file = dir.path().join("notes.txt");
+            fs::write(&file, "no audio").unwrap();
+            let payload = FingerprintAlgorithm::AudioPayloadSHA256;
+
+            // run
+            cache.fingerprint(&file, &payload).unwrap();
+            let cached = cache.fingerprint(&file, &payload).unwrap();
+
+            // verify
+            assert_eq!(cached, (None, CacheOutcome::Cached));
+        }
+
+        #[test]
+        fn stats_counts_stale_files() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let cache = FingerprintCache::open(&dir.path().join("cache.sqlite")).unwrap();
+            let kept = dir.path().join("kept.dat");
+            let removed = dir.path().join("removed.dat");
+            fs::write(&kept, "kept").unwrap();
+            fs::write(&removed, "removed").unwrap();
+            for file in [&kept, &removed] {
+                cache
+                    .fingerprint(file, &FingerprintAlgorithm::SHA265)
+                    .unwrap();
+                cache
+                    .fingerprint(file, &FingerprintAlgorithm::AudioPayloadSHA256)
+                    .unwrap();
+            }
+            fs::remove_file(&removed).unwrap();
+
+            // run
+            let stats = cache.stats().unwrap();
+
+            // verify
+            assert_eq!(stats.files, 2);
+            assert_eq!(stats.stale_files, 1);
+            assert_eq!(stats.entries.get("sha256"), Some(&2));
+            assert_eq!(stats.entries.get("payload"), Some(&2));
+        }
+    }
+}
@@ -0,0 +1,200 @@
+pub mod fingerprint_pool {
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+
+    use crate::error::error::LibHelperError;
+    use crate::track_fingerprinting::read_control::read_control::{
+        CancelToken, ReadControl, Throttle,
+    };
+    use crate::track_fingerprinting::track_fingerprinting::track_fingerprinting::{
+        get_track_fingerprint_with, Fingerprint, FingerprintAlgorithm,
+    };
+
+    /// How fingerprints of many files are computed.
+    #[derive(Clone, Debug, Default)]
+    pub struct PoolOptions {
+        /// How many files are fingerprinted at the same time, 0 for one per CPU core.
+        /// 1 suits spinning disks best, as they are slow to seek between files.
+        pub jobs: usize,
+        /// Limit for how fast all workers together read, `None` for no limit
+        pub max_bytes_per_second: Option<u64>,
+        /// Stops the workers; files already started are not reported
+        pub cancel: CancelToken,
+    }
+
+    /// A file and the fingerprints to compute for it.
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FingerprintJob {
+        pub path: PathBuf,
+        pub algorithms: Vec<FingerprintAlgorithm>,
+    }
+
+    #[derive(Clone, Copy, Debug, Default, PartialEq)]
+    pub struct Progress {
+        /// Jobs finished, successfully or not
+        pub done: usize,
+        pub total: usize,
+        pub bytes_read: u64,
+    }
+
+    /// The outcome of a job: the fingerprints the file has, leaving out those of
+    /// algorithms that do not apply to it, or the first error.
+    pub type JobResult = Result<Vec<Fingerprint>, LibHelperError>;
+
+    /// Fingerprints the files of `jobs` on a pool of worker threads.
+    ///
+    /// `on_result` is called on the calling thread whenever a job finishes, in
+    /// no particular order, so it can store the results without locking.
+    /// Files are read with a bounded buffer, however large they are.
+    /// Returns once all jobs are done or [`PoolOptions::cancel`] was triggered.
+    pub fn fingerprint_files(
+        jobs: &[FingerprintJob],
+        options: &PoolOptions,
+        mut on_result: impl FnMut(&FingerprintJob, JobResult, &Progress),
+    ) {
+        let control = ReadControl {
+            cancel: options.cancel.clone(),
+            throttle: options
+                .max_bytes_per_second
+                .map(|bytes_per_second| Arc::new(Throttle::new(bytes_per_second))),
+            ..ReadControl::default()
+        };
+        let worker_count = match options.jobs {
+            0 => thread::available_parallelism().map_or(1, |count| count.get()),
+            jobs => jobs,
+        }
+        .min(jobs.len().max(1));
+        let next_job = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..worker_count {
+                let sender = sender.clone();
+                let control = &control;
+                let next_job = &next_job;
+                scope.spawn(move || loop {
+                    let index = next_job.fetch_add(1, Ordering::Relaxed);
+                    if index >= jobs.len() || control.cancel.is_cancelled() {
+                        break;
+                    }
+                    let result = fingerprint_job(&jobs[index], control);
+                    if control.cancel.is_cancelled() {
+                        break;
+                    }
+                    if sender.send((index, result)).is_err() {
+                        break;
+                    }
+                });
+            }
+            // the receiver ends once every worker dropped its sender
+            drop(sender);
+
+            let mut progress = Progress {
+                total: jobs.len(),
+                ..Progress::default()
+            };
+            for (index, result) in receiver {
+                progress.done += 1;
+                progress.bytes_read = control.bytes_read.load(Ordering::Relaxed);
+                on_result(&jobs[index], result, &progress);
+            }
+        });
+    }
+
+    fn fingerprint_job(job: &FingerprintJob, control: &ReadControl) -> JobResult {
+        let mut fingerprints = vec![];
+        for algorithm in &job.algorithms {
+            fingerprints.extend(get_track_fingerprint_with(&job.path, algorithm, control)?);
+        }
+        Ok(fingerprints)
+    }
+
+    /// The fingerprint of `algorithm` among the results of a job.
+    pub fn find_fingerprint<'a>(
+        fingerprints: &'a [Fingerprint],
+        algorithm: &FingerprintAlgorithm,
+    ) -> Option<&'a Fingerprint> {
+        fingerprints
+            .iter()
+            .find(|fingerprint| &fingerprint.fingerprint_algorithm == algorithm)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::path::Path;
+
+        use super::*;
+
+        /// Jobs for the same algorithms for every path.
+        fn jobs_for(paths: &[&Path], algorithms: &[FingerprintAlgorithm]) -> Vec<FingerprintJob> {
+            paths
+                .iter()
+                .map(|path| FingerprintJob {
+                    path: path.to_path_buf(),
+                    algorithms: algorithms.to_vec(),
+                })
+                .collect()
+        }
+
+        #[test]
+        fn fingerprint_files_reports_every_job() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let mut paths = vec![];
+            for index in 0..20 {
+                let path = dir.path().join(format!("{index}.dat"));
+                std::fs::write(&path, format!("content {index}")).unwrap();
+                paths.push(path);
+            }
+            paths.push(dir.path().join("missing.dat"));
+            let paths: Vec<&Path> = paths.iter().map(|path| path.as_path()).collect();
+            let jobs = jobs_for(&paths, &[FingerprintAlgorithm::SHA265]);
+            let options = PoolOptions {
+                jobs: 4,
+                ..PoolOptions::default()
+            };
+
+            // run
+            let mut results = vec![];
+            let mut last_progress = Progress::default();
+            fingerprint_files(&jobs, &options, |job, result, progress| {
+                results.push((job.path.clone(), result.is_ok()));
+                last_progress = *progress;
+            });
+
+            // verify
+            assert_eq!(results.len(), 21);
+            assert_eq!(results.iter().filter(|(_, ok)| !ok).count(), 1);
+            assert_eq!(last_progress.done, 21);
+            assert_eq!(last_progress.total, 21);
+            assert!(last_progress.bytes_read > 0);
+        }
+
+        #[test]
+        fn fingerprint_files_stops_when_cancelled() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.dat");
+            std::fs::write(&path, vec![0; 10_000]).unwrap();
+            let jobs = jobs_for(&vec![path.as_path(); 50], &[FingerprintAlgorithm::SHA265]);
+            // throttled to a file every 0.1 s, so the worker cannot finish before the cancel
+            let options = PoolOptions {
+                jobs: 1,
+                max_bytes_per_second: Some(100_000),
+                ..PoolOptions::default()
+            };
+
+            // run
+            let mut reported = 0;
+            fingerprint_files(&jobs, &options, |_, _, _| {
+                reported += 1;
+                options.cancel.cancel();
+            });
+
+            // verify
+            assert!(reported < 50);
+        }
+    }
+}
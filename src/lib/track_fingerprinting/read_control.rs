@@ -0,0 +1,152 @@
+pub mod read_control {
+    use std::fs::File;
+    use std::io::{self, Read, Seek, SeekFrom};
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// How much of a file is read at once while hashing, whatever its size.
+    pub const HASH_BUFFER_BYTES: usize = 256 * 1024;
+
+    /// Stops fingerprinting from another thread. Clones share the same state.
+    #[derive(Clone, Debug, Default)]
+    pub struct CancelToken(Arc<AtomicBool>);
+
+    impl CancelToken {
+        pub fn new() -> CancelToken {
+            CancelToken::default()
+        }
+
+        pub fn cancel(&self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+
+        pub fn is_cancelled(&self) -> bool {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Limits how fast all readers sharing it read together, so a spinning disk
+    /// is not kept busy to the point that Mixxx or other programs stall.
+    #[derive(Debug)]
+    pub struct Throttle {
+        bytes_per_second: u64,
+        /// When throttling started and how much was read since
+        state: Mutex<(Instant, u64)>,
+    }
+
+    impl Throttle {
+        pub fn new(bytes_per_second: u64) -> Throttle {
+            Throttle {
+                bytes_per_second: bytes_per_second.max(1),
+                state: Mutex::new((Instant::now(), 0)),
+            }
+        }
+
+        /// Waits until reading `bytes` more keeps the rate below the limit.
+        fn acquire(&self, bytes: u64) {
+            let wait = {
+                let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+                state.1 += bytes;
+                let due = state.0
+                    + Duration::from_secs_f64(state.1 as f64 / self.bytes_per_second as f64);
+                due.saturating_duration_since(Instant::now())
+            };
+            if !wait.is_zero() {
+                thread::sleep(wait);
+            }
+        }
+    }
+
+    /// What applies to every read of the fingerprinting code: cancellation,
+    /// throttling and counting the bytes read.
+    #[derive(Clone, Debug, Default)]
+    pub struct ReadControl {
+        pub cancel: CancelToken,
+        pub throttle: Option<Arc<Throttle>>,
+        pub bytes_read: Arc<AtomicU64>,
+    }
+
+    impl ReadControl {
+        pub fn open(&self, path: &Path) -> Result<ControlledFile, io::Error> {
+            Ok(ControlledFile {
+                file: File::open(path)?,
+                control: self.clone(),
+            })
+        }
+    }
+
+    /// A file whose reads go through a [`ReadControl`].
+    pub struct ControlledFile {
+        file: File,
+        control: ReadControl,
+    }
+
+    impl ControlledFile {
+        pub fn file(&self) -> &File {
+            &self.file
+        }
+    }
+
+    impl Read for ControlledFile {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            if self.control.cancel.is_cancelled() {
+                return Err(io::Error::other("cancelled"));
+            }
+            let read = self.file.read(buffer)?;
+            if let Some(throttle) = &self.control.throttle {
+                throttle.acquire(read as u64);
+            }
+            self.control
+                .bytes_read
+                .fetch_add(read as u64, Ordering::Relaxed);
+            Ok(read)
+        }
+    }
+
+    impl Seek for ControlledFile {
+        fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+            self.file.seek(position)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn controlled_file_counts_and_stops_reading() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("track.dat");
+            std::fs::write(&path, vec![0; 1000]).unwrap();
+            let control = ReadControl::default();
+
+            // run
+            let read = io::copy(&mut control.open(&path).unwrap(), &mut io::sink()).unwrap();
+            control.cancel.cancel();
+            let cancelled = control.open(&path).unwrap().read(&mut [0; 10]);
+
+            // verify
+            assert_eq!(read, 1000);
+            assert_eq!(control.bytes_read.load(Ordering::Relaxed), 1000);
+            assert!(cancelled.is_err());
+        }
+
+        #[test]
+        fn throttle_limits_the_rate() {
+            // setup
+            let throttle = Throttle::new(10_000);
+            let start = Instant::now();
+
+            // run
+            throttle.acquire(1_000);
+            throttle.acquire(1_000);
+
+            // verify
+            assert!(start.elapsed() >= Duration::from_millis(200));
+        }
+    }
+}
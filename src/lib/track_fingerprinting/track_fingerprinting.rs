@@ -1,36 +1,155 @@
 pub mod track_fingerprinting {
 
+    use std::{fmt, io::BufReader, path::Path, str::FromStr};
+
     use sha2::{Digest, Sha256};
 
-    #[derive(PartialEq, Debug)]
+    use crate::error::error::LibHelperError;
+    use crate::track_analysis::audio_decode::audio_decode::decode_mono_with;
+    use crate::track_fingerprinting::acoustic::acoustic::{
+        acoustic_fingerprint, acoustic_similarity, decode_acoustic_fingerprint,
+        encode_acoustic_fingerprint, ACOUSTIC_FINGERPRINT_SECONDS,
+    };
+    use crate::track_fingerprinting::audio_payload::audio_payload::hash_audio_payload;
+    use crate::track_fingerprinting::read_control::read_control::{ReadControl, HASH_BUFFER_BYTES};
+
+    #[derive(Clone, PartialEq, Debug)]
     pub enum FingerprintAlgorithm {
+        /// SHA-256 of the whole file
         SHA265,
+        /// SHA-256 of the audio data alone, which stays the same when the tags change
+        AudioPayloadSHA256,
+        /// Perceptual fingerprint of the decoded audio, which stays similar when the
+        /// file is re-encoded, e.g. from WAV to FLAC or MP3
+        Acoustic,
+    }
+
+    impl FingerprintAlgorithm {
+        pub const ALL: [FingerprintAlgorithm; 3] = [
+            FingerprintAlgorithm::SHA265,
+            FingerprintAlgorithm::AudioPayloadSHA256,
+            FingerprintAlgorithm::Acoustic,
+        ];
+
+        /// The name used on the command line and in the fingerprint cache.
+        pub fn name(&self) -> &'static str {
+            match self {
+                FingerprintAlgorithm::SHA265 => "sha256",
+                FingerprintAlgorithm::AudioPayloadSHA256 => "payload",
+                FingerprintAlgorithm::Acoustic => "acoustic",
+            }
+        }
+    }
+
+    impl fmt::Display for FingerprintAlgorithm {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.name())
+        }
     }
 
+    impl FromStr for FingerprintAlgorithm {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Self, Self::Err> {
+            FingerprintAlgorithm::ALL
+                .into_iter()
+                .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("unknown fingerprint algorithm {name}, expected sha256, payload or acoustic"))
+        }
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
     pub struct Fingerprint {
         pub fingerprint: String,
         pub fingerprint_algorithm: FingerprintAlgorithm,
     }
 
+    impl Fingerprint {
+        /// How alike the audio of two fingerprints is, from 0 to 1.
+        ///
+        /// Hashes are either equal or not, acoustic fingerprints score in between,
+        /// see [`SAME_RECORDING_SIMILARITY`](crate::track_fingerprinting::acoustic::acoustic::SAME_RECORDING_SIMILARITY).
+        /// `None` if the fingerprints were made with different algorithms.
+        pub fn similarity(&self, other: &Fingerprint) -> Option<f64> {
+            if self.fingerprint_algorithm != other.fingerprint_algorithm {
+                return None;
+            }
+            if self.fingerprint_algorithm != FingerprintAlgorithm::Acoustic {
+                return Some(if self.fingerprint == other.fingerprint {
+                    1.0
+                } else {
+                    0.0
+                });
+            }
+
+            let a = decode_acoustic_fingerprint(&self.fingerprint)?;
+            let b = decode_acoustic_fingerprint(&other.fingerprint)?;
+            Some(acoustic_similarity(&a, &b))
+        }
+    }
+
+    /// The fingerprints that do not need the audio decoded: the hash of the whole
+    /// file and, for formats whose audio data can be found, the hash of that.
     pub fn get_track_fingerprints_for_file(
         file_path: &str,
-    ) -> Result<Vec<Fingerprint>, Box<dyn std::error::Error>> {
+    ) -> Result<Vec<Fingerprint>, LibHelperError> {
+        let file_path = Path::new(file_path);
         let mut result = Vec::new();
+        for algorithm in [
+            FingerprintAlgorithm::SHA265,
+            FingerprintAlgorithm::AudioPayloadSHA256,
+        ] {
+            result.extend(get_track_fingerprint(file_path, &algorithm)?);
+        }
 
-        // get whole fs file fingerprint
-        let file_path = std::path::Path::new(&file_path);
-        let file_bytes = std::fs::read(&file_path)?;
+        Ok(result)
+    }
 
-        let mut hasher = Sha256::new();
-        hasher.update(&file_bytes);
-        let hash = hasher.finalize();
+    /// Computes one kind of fingerprint. `None` if the audio data of the file cannot
+    /// be found for [`FingerprintAlgorithm::AudioPayloadSHA256`]. The acoustic
+    /// fingerprint decodes the file with GStreamer and fails if that is not possible.
+    pub fn get_track_fingerprint(
+        file_path: &Path,
+        algorithm: &FingerprintAlgorithm,
+    ) -> Result<Option<Fingerprint>, LibHelperError> {
+        get_track_fingerprint_with(file_path, algorithm, &ReadControl::default())
+    }
 
-        result.push(Fingerprint {
-            fingerprint: format!("{:x}", hash),
-            fingerprint_algorithm: FingerprintAlgorithm::SHA265,
-        });
+    /// Like [`get_track_fingerprint`], reading the file through `control`, which can
+    /// throttle and cancel the hashes. Decoding for the acoustic fingerprint is left to
+    /// GStreamer, which is not throttled, but stops between samples when cancelled.
+    pub fn get_track_fingerprint_with(
+        file_path: &Path,
+        algorithm: &FingerprintAlgorithm,
+        control: &ReadControl,
+    ) -> Result<Option<Fingerprint>, LibHelperError> {
+        let fingerprint = match algorithm {
+            FingerprintAlgorithm::SHA265 => {
+                // streamed, as files can be larger than the memory
+                let mut file =
+                    BufReader::with_capacity(HASH_BUFFER_BYTES, control.open(file_path)?);
+                let mut hasher = Sha256::new();
+                std::io::copy(&mut file, &mut hasher)?;
+                Some(format!("{:x}", hasher.finalize()))
+            }
+            FingerprintAlgorithm::AudioPayloadSHA256 => hash_audio_payload(file_path, control)?,
+            FingerprintAlgorithm::Acoustic => {
+                if control.cancel.is_cancelled() {
+                    return Err(LibHelperError::Fingerprint {
+                        path: file_path.to_string_lossy().to_string(),
+                        message: String::from("cancelled"),
+                    });
+                }
+                let samples =
+                    decode_mono_with(file_path, ACOUSTIC_FINGERPRINT_SECONDS, &control.cancel)?;
+                Some(encode_acoustic_fingerprint(&acoustic_fingerprint(&samples)))
+            }
+        };
 
-        Ok(result)
+        Ok(fingerprint.map(|fingerprint| Fingerprint {
+            fingerprint,
+            fingerprint_algorithm: algorithm.clone(),
+        }))
     }
 
     #[cfg(test)]
@@ -39,7 +158,7 @@ pub mod track_fingerprinting {
 
         #[test]
         fn get_track_fingerprints_for_file_works() {
-            let result = get_track_fingerprints_for_file(&"test-data/sample.dat").unwrap();
+            let result = get_track_fingerprints_for_file("test-data/sample.dat").unwrap();
             assert_eq!(
                 result[0].fingerprint_algorithm,
                 FingerprintAlgorithm::SHA265
@@ -49,5 +168,25 @@ pub mod track_fingerprinting {
                 "a665a45920422f9d417e4867efdc4fb8a04a1f3fff1fa07e998e86f7f7a27ae3"
             );
         }
+
+        #[test]
+        fn similarity_compares_only_same_algorithm() {
+            // setup
+            let fingerprint = |fingerprint: &str, fingerprint_algorithm| Fingerprint {
+                fingerprint: fingerprint.to_string(),
+                fingerprint_algorithm,
+            };
+            let payload = fingerprint("ab", FingerprintAlgorithm::AudioPayloadSHA256);
+            let acoustic = fingerprint("0000ffff", FingerprintAlgorithm::Acoustic);
+
+            // run & verify
+            assert_eq!(payload.similarity(&payload.clone()), Some(1.0));
+            assert_eq!(
+                payload.similarity(&fingerprint("cd", FingerprintAlgorithm::AudioPayloadSHA256)),
+                Some(0.0)
+            );
+            assert_eq!(payload.similarity(&acoustic), None);
+            assert_eq!(acoustic.similarity(&acoustic.clone()), Some(1.0));
+        }
     }
 }
@@ -1 +1,3 @@
+pub mod file_walk;
 pub mod move_fns;
+pub mod relocation;
@@ -0,0 +1,54 @@
+pub mod file_walk {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    /// Lists all regular files below `root`, descending into subdirectories.
+    /// Symlinked directories are not followed to avoid walking in circles.
+    pub fn list_files_recursively(root: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        let mut files = vec![];
+        let mut pending_dirs = vec![root.to_path_buf()];
+
+        while let Some(dir) = pending_dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                if file_type.is_dir() {
+                    pending_dirs.push(entry.path());
+                } else if file_type.is_file() {
+                    files.push(entry.path());
+                }
+            }
+        }
+
+        files.sort();
+        Ok(files)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn list_files_recursively_descends_into_subdirectories() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            fs::create_dir_all(root.path().join("a/b")).unwrap();
+            fs::write(root.path().join("top.mp3"), "1").unwrap();
+            fs::write(root.path().join("a/b/nested.mp3"), "2").unwrap();
+
+            // run
+            let files = list_files_recursively(root.path()).unwrap();
+
+            // verify
+            assert_eq!(
+                files,
+                vec![
+                    root.path().join("a/b/nested.mp3"),
+                    root.path().join("top.mp3")
+                ]
+            );
+        }
+    }
+}
@@ -1,46 +1,340 @@
 pub mod movefns {
     use std::{
-        fs,
+        fs, io,
         path::{Path, PathBuf},
     };
 
-    pub fn move_folder(
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        db_changes::change_plan::change_plan::{
+            plan_directory_change, plan_track_change, ChangePlan, ChangeTable, ColumnValue,
+        },
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb,
+        error::error::LibHelperError,
+        track_movement::file_walk::file_walk::list_files_recursively,
+    };
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    pub struct FileMove {
+        pub source: PathBuf,
+        pub target: PathBuf,
+    }
+
+    /// Plans moving a file or the contents of a directory tree into `target_path`,
+    /// keeping `track_locations` and `directories` in sync with the new paths.
+    ///
+    /// Applying the plan moves the files back and leaves the database untouched
+    /// if any step fails, so disk and library stay in sync.
+    pub fn plan_move_folder(
+        db: &MixxxDb,
         source_path: &str,
         target_path: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // get affected files list
-        let source_path = Path::new(&source_path);
-        let is_dir = source_path.is_dir();
-        let mut files = vec![];
-        if is_dir {
-            let read_dir = fs::read_dir(&source_path)?;
-            for file in read_dir {
-                if !file.is_err() {
-                    files.push(file.unwrap().path())
+    ) -> Result<ChangePlan, LibHelperError> {
+        let source_path = fs::canonicalize(source_path)?;
+        let target_path = std::path::absolute(target_path)?;
+
+        let mut plan = ChangePlan {
+            file_moves: plan_moves(&source_path, &target_path)?,
+            ..ChangePlan::default()
+        };
+
+        db.require_columns("library", &["id", "location"])?;
+        db.require_columns("track_locations", &["id", "location"])?;
+        let mut stmt = db.connection().prepare(
+            "SELECT l.id FROM library l
+             INNER JOIN track_locations tl
+             ON tl.id = l.location
+             WHERE tl.location = ?1;",
+        )?;
+        for file_move in &plan.file_moves {
+            let track_ids = stmt
+                .query_map([file_move.source.to_string_lossy()], |row| row.get(0))?
+                .collect::<Result<Vec<usize>, _>>()?;
+            for track_id in track_ids {
+                let new_values = [
+                    ("location", file_move.target.to_string_lossy().to_string()),
+                    ("filename", file_name(&file_move.target)),
+                    ("directory", directory(&file_move.target)),
+                ];
+                for (column, new_value) in new_values {
+                    let change = plan_track_change(
+                        db,
+                        ChangeTable::TrackLocations,
+                        track_id,
+                        column,
+                        ColumnValue::Text(new_value),
+                    )?;
+                    plan.changes.extend(change);
                 }
             }
+        }
+
+        if source_path.is_dir() {
+            db.require_columns("directories", &["directory"])?;
+            let mut stmt = db
+                .connection()
+                .prepare("SELECT directory FROM directories")?;
+            let directories = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            for old_directory in directories {
+                if let Ok(relative) = Path::new(&old_directory).strip_prefix(&source_path) {
+                    let new_directory = if relative.as_os_str().is_empty() {
+                        target_path.clone()
+                    } else {
+                        target_path.join(relative)
+                    };
+                    plan.changes.push(plan_directory_change(
+                        &old_directory,
+                        &new_directory.to_string_lossy(),
+                    ));
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Lists the moves needed to move `source_path` into `target_path`,
+    /// keeping the relative layout of subdirectories.
+    pub fn plan_moves(source_path: &Path, target_path: &Path) -> Result<Vec<FileMove>, io::Error> {
+        let mut moves = vec![];
+        if source_path.is_dir() {
+            for file in list_files_recursively(source_path)? {
+                let relative = file.strip_prefix(source_path).unwrap().to_path_buf();
+                moves.push(FileMove {
+                    source: file,
+                    target: target_path.join(relative),
+                });
+            }
         } else if source_path.is_file() {
-            files.push(PathBuf::from(&source_path))
+            moves.push(FileMove {
+                source: source_path.to_path_buf(),
+                target: target_path.join(source_path.file_name().unwrap()),
+            });
+        }
+
+        for file_move in &moves {
+            if file_move.target.exists() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", file_move.target.display()),
+                ));
+            }
+        }
+
+        Ok(moves)
+    }
+
+    /// Moves a single file, falling back to copy and delete across file systems.
+    /// The directories created for the target are added to `created_dirs`,
+    /// parents before their children, so [`undo_moves`] can remove them again.
+    pub fn move_file(
+        source: &Path,
+        target: &Path,
+        created_dirs: &mut Vec<PathBuf>,
+    ) -> Result<(), io::Error> {
+        if let Some(parent) = target.parent() {
+            let mut missing: Vec<&Path> = parent
+                .ancestors()
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+                .collect();
+            missing.reverse();
+            for dir in missing {
+                fs::create_dir(dir)?;
+                created_dirs.push(dir.to_path_buf());
+            }
+        }
+
+        match fs::rename(source, target) {
+            Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+                fs::copy(source, target)?;
+                fs::remove_file(source)
+            }
+            result => result,
         }
+    }
 
-        // move files
-        for file in files {
-            let path = Path::new(file.as_path());
+    /// Moves already moved files back to where they came from, newest move first,
+    /// then removes the directories the moves created, deepest first.
+    pub fn undo_moves(moved_files: &[FileMove], created_dirs: &[PathBuf]) {
+        let mut recreated_dirs = vec![];
+        for file_move in moved_files.iter().rev() {
+            if let Err(err) = move_file(&file_move.target, &file_move.source, &mut recreated_dirs) {
+                eprintln!(
+                    "Could not move {} back to {}: {err}",
+                    file_move.target.display(),
+                    file_move.source.display()
+                );
+            }
+        }
+        for dir in created_dirs.iter().rev() {
+            // fails on purpose if something else was put there in the meantime
+            let _ = fs::remove_dir(dir);
+        }
+    }
 
-            let mut target = String::from(target_path.clone());
-            if !target_path.ends_with("/") {
-                target += "/";
+    fn file_name(path: &Path) -> String {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn directory(path: &Path) -> String {
+        path.parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Removes the directories below `dir` that are empty after a move, keeping `dir` itself.
+    pub fn remove_empty_subdirectories(dir: &Path) {
+        let Ok(read_dir) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in read_dir.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                remove_empty_subdirectories(&path);
+                // fails on purpose if the directory still has content
+                let _ = fs::remove_dir(&path);
             }
-            target += path.file_name().unwrap().to_str().unwrap();
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{
+            db_changes::change_plan::change_plan::apply_plan,
+            test_db::test_db::{insert_track, open_test_mixxx_db},
+        };
 
-            fs::rename(path, Path::new(target.as_str()))?;
+        fn move_folder(
+            db: &mut MixxxDb,
+            source_path: &str,
+            target_path: &str,
+        ) -> Result<ChangePlan, LibHelperError> {
+            let plan = plan_move_folder(db, source_path, target_path)?;
+            apply_plan(db, &plan)?;
+            remove_empty_subdirectories(Path::new(source_path));
+            Ok(plan)
+        }
 
-            ()
+        fn location_of(connection: &rusqlite::Connection, id: usize) -> String {
+            connection
+                .query_row(
+                    "SELECT location FROM track_locations WHERE id = ?1",
+                    [id],
+                    |row| row.get(0),
+                )
+                .unwrap()
         }
 
-        // find affected tracks in db
+        #[test]
+        fn move_folder_moves_tree_and_updates_db() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let root_path = fs::canonicalize(root.path()).unwrap();
+            let source = root_path.join("music");
+            let target = root_path.join("other-drive");
+            fs::create_dir_all(source.join("house")).unwrap();
+            fs::write(source.join("house/track.mp3"), "1").unwrap();
+            fs::write(source.join("top.mp3"), "2").unwrap();
+
+            let mut db = open_test_mixxx_db();
+            insert_track(
+                db.connection(),
+                1,
+                source.join("house/track.mp3").to_str().unwrap(),
+                "House",
+                124.0,
+            );
+            db.connection()
+                .execute(
+                    "INSERT INTO directories (directory) VALUES (?1)",
+                    [source.to_str().unwrap()],
+                )
+                .unwrap();
+
+            // run
+            let plan =
+                move_folder(&mut db, source.to_str().unwrap(), target.to_str().unwrap()).unwrap();
+
+            // verify
+            assert_eq!(plan.file_moves.len(), 2);
+            assert_eq!(plan.changes.len(), 3);
+            assert!(target.join("house/track.mp3").is_file());
+            assert!(target.join("top.mp3").is_file());
+            assert!(!source.join("house").exists());
+            assert_eq!(
+                location_of(db.connection(), 1),
+                target.join("house/track.mp3").to_str().unwrap()
+            );
+            let directory: String = db
+                .connection()
+                .query_row("SELECT directory FROM directories", [], |row| row.get(0))
+                .unwrap();
+            assert_eq!(directory, target.to_str().unwrap());
+        }
+
+        #[test]
+        fn move_folder_refuses_to_overwrite_existing_files() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let root_path = fs::canonicalize(root.path()).unwrap();
+            fs::create_dir_all(root_path.join("source")).unwrap();
+            fs::create_dir_all(root_path.join("target")).unwrap();
+            fs::write(root_path.join("source/track.mp3"), "new").unwrap();
+            fs::write(root_path.join("target/track.mp3"), "old").unwrap();
+            let mut db = open_test_mixxx_db();
+
+            // run
+            let result = move_folder(
+                &mut db,
+                root_path.join("source").to_str().unwrap(),
+                root_path.join("target").to_str().unwrap(),
+            );
 
-        // update mixxx database
-        Ok(())
+            // verify
+            assert!(result.is_err());
+            assert!(root_path.join("source/track.mp3").is_file());
+            assert_eq!(
+                fs::read_to_string(root_path.join("target/track.mp3")).unwrap(),
+                "old"
+            );
+        }
+
+        #[test]
+        fn move_folder_moves_files_back_when_db_update_fails() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let root_path = fs::canonicalize(root.path()).unwrap();
+            let source_file = root_path.join("source/track.mp3");
+            let target_file = root_path.join("target/nested/track.mp3");
+            fs::create_dir_all(source_file.parent().unwrap()).unwrap();
+            fs::write(&source_file, "1").unwrap();
+
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, source_file.to_str().unwrap(), "", 120.0);
+            // a stale row already claims the target location, violating UNIQUE on update
+            insert_track(db.connection(), 2, target_file.to_str().unwrap(), "", 120.0);
+
+            // run
+            let result = move_folder(
+                &mut db,
+                root_path.join("source").to_str().unwrap(),
+                root_path.join("target/nested").to_str().unwrap(),
+            );
+
+            // verify
+            assert!(result.is_err());
+            assert!(source_file.is_file());
+            assert!(!root_path.join("target").exists());
+            assert_eq!(
+                location_of(db.connection(), 1),
+                source_file.to_str().unwrap()
+            );
+        }
     }
 }
@@ -0,0 +1,751 @@
+pub mod relocation {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use crate::{
+        db_changes::change_plan::change_plan::{
+            plan_track_change, ChangePlan, ChangeTable, ColumnValue,
+        },
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb,
+        error::error::LibHelperError,
+        track::file_type::file_type::detect_format,
+        track_fingerprinting::{
+            acoustic::acoustic::SAME_RECORDING_SIMILARITY,
+            fingerprint_cache::fingerprint_cache::{FileStamp, FingerprintCache},
+            fingerprint_pool::fingerprint_pool::{
+                find_fingerprint, fingerprint_files, FingerprintJob, PoolOptions,
+            },
+            track_fingerprinting::track_fingerprinting::{Fingerprint, FingerprintAlgorithm},
+        },
+        track_movement::file_walk::file_walk::list_files_recursively,
+    };
+
+    /// A track whose file Mixxx could not find anymore (`track_locations.fs_deleted = 1`).
+    #[derive(Clone, Debug)]
+    pub struct MissingTrack {
+        pub id: usize,
+        pub location: String,
+        pub filename: String,
+        pub filesize: Option<u64>,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Relocation {
+        pub track: MissingTrack,
+        pub new_location: PathBuf,
+    }
+
+    #[derive(Debug, Default)]
+    pub struct RelocationReport {
+        pub relocations: Vec<Relocation>,
+        pub ambiguous: Vec<(MissingTrack, Vec<PathBuf>)>,
+        pub not_found: Vec<MissingTrack>,
+    }
+
+    /// What tells copies of a file from other files when several candidates match.
+    const CONTENT_ALGORITHMS: [FingerprintAlgorithm; 2] = [
+        FingerprintAlgorithm::SHA265,
+        FingerprintAlgorithm::AudioPayloadSHA256,
+    ];
+
+    #[derive(Clone, Debug)]
+    struct Candidate {
+        path: PathBuf,
+        filename: String,
+    }
+
+    pub fn find_missing_tracks(db: &MixxxDb) -> Result<Vec<MissingTrack>, LibHelperError> {
+        db.require_columns("library", &["id", "location"])?;
+        db.require_columns(
+            "track_locations",
+            &["id", "location", "filename", "filesize", "fs_deleted"],
+        )?;
+        let mut stmt = db.connection().prepare(
+            "SELECT l.id, tl.location, tl.filename, tl.filesize FROM library l
+             INNER JOIN track_locations tl
+             ON tl.id = l.location
+             WHERE tl.fs_deleted = 1;",
+        )?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(MissingTrack {
+                id: row.get(0)?,
+                location: row.get(1)?,
+                filename: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                filesize: row.get(3)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<Vec<MissingTrack>, _>>()?)
+    }
+
+    /// Scans `search_roots` for files that are likely the moved versions of missing tracks.
+    ///
+    /// Candidates are matched on the file size Mixxx recorded for the missing
+    /// file, or on the file name if no size is known or no file has that size.
+    /// When several candidates remain, their fingerprints decide: copies of the
+    /// same audio are interchangeable, even if their tags differ, different audio
+    /// makes the match ambiguous, and candidates that cannot be read are left out.
+    /// Tracks without any candidate are compared by sound with the audio files
+    /// no other track matched, if `cache` still holds their acoustic fingerprint
+    /// from before they went missing. That finds re-encoded copies, e.g. a WAV
+    /// converted to FLAC, whose size and name both changed.
+    /// Files that are already part of the library are never considered.
+    /// Fingerprints are taken from `cache` while the files are unchanged, and
+    /// stored there once computed.
+    pub fn find_relocations(
+        db: &MixxxDb,
+        search_roots: &[&str],
+        cache: &FingerprintCache,
+    ) -> Result<RelocationReport, LibHelperError> {
+        let missing_tracks = find_missing_tracks(db)?;
+        let mut report = RelocationReport::default();
+        if missing_tracks.is_empty() {
+            return Ok(report);
+        }
+
+        let known_locations = find_known_locations(db)?;
+        let mut candidates_by_size: HashMap<u64, Vec<Candidate>> = HashMap::new();
+        let mut candidates_by_name: HashMap<String, Vec<Candidate>> = HashMap::new();
+        let mut files: Vec<PathBuf> = vec![];
+        for search_root in search_roots {
+            let search_root = fs::canonicalize(search_root)?;
+            for path in list_files_recursively(&search_root)? {
+                let (Some(location), Some(filename)) = (
+                    path.to_str(),
+                    path.file_name().and_then(|name| name.to_str()),
+                ) else {
+                    continue;
+                };
+                if known_locations.contains(location) {
+                    continue;
+                }
+
+                let candidate = Candidate {
+                    filename: filename.to_string(),
+                    path: path.clone(),
+                };
+                files.push(path.clone());
+                let size = fs::metadata(&path)?.len();
+                candidates_by_size
+                    .entry(size)
+                    .or_default()
+                    .push(candidate.clone());
+                candidates_by_name
+                    .entry(candidate.filename.clone())
+                    .or_default()
+                    .push(candidate);
+            }
+        }
+
+        // fingerprint every file that may have to tell candidates apart up front,
+        // in parallel, rather than one by one while matching
+        let no_claims = HashSet::new();
+        let mut to_fingerprint: Vec<PathBuf> = vec![];
+        let mut seen: HashSet<PathBuf> = HashSet::new();
+        for track in &missing_tracks {
+            let candidates =
+                find_candidates(track, &candidates_by_size, &candidates_by_name, &no_claims);
+            let preferred = preferred_candidates(track, &candidates);
+            if preferred.len() > 1 {
+                for candidate in preferred {
+                    if seen.insert(candidate.path.clone()) {
+                        to_fingerprint.push(candidate.path.clone());
+                    }
+                }
+            }
+        }
+        let to_fingerprint: Vec<&Path> = to_fingerprint.iter().map(|path| path.as_path()).collect();
+        let mut fingerprints = fingerprint_candidates(&to_fingerprint, &CONTENT_ALGORITHMS, cache)?;
+
+        let mut claimed: HashSet<PathBuf> = HashSet::new();
+        let mut unmatched = vec![];
+        for track in missing_tracks {
+            let candidates =
+                find_candidates(&track, &candidates_by_size, &candidates_by_name, &claimed);
+            if candidates.is_empty() {
+                unmatched.push(track);
+                continue;
+            }
+
+            // files that only became candidates once others were claimed
+            let preferred = preferred_candidates(&track, &candidates);
+            let unknown: Vec<&Path> = preferred
+                .iter()
+                .map(|candidate| candidate.path.as_path())
+                .filter(|path| !fingerprints.contains_key(*path))
+                .collect();
+            if preferred.len() > 1 && !unknown.is_empty() {
+                fingerprints.extend(fingerprint_candidates(
+                    &unknown,
+                    &CONTENT_ALGORITHMS,
+                    cache,
+                )?);
+            }
+
+            match choose_candidate(&track, &candidates, &fingerprints) {
+                Some(new_location) => {
+                    claimed.insert(new_location.clone());
+                    report.relocations.push(Relocation {
+                        track,
+                        new_location,
+                    });
+                }
+                None => {
+                    let paths = candidates.into_iter().map(|candidate| candidate.path);
+                    report.ambiguous.push((track, paths.collect()));
+                }
+            }
+        }
+
+        match_acoustically(unmatched, &files, &mut claimed, cache, &mut report)?;
+
+        Ok(report)
+    }
+
+    /// Relocates each track to the one unclaimed audio file that sounds like the
+    /// acoustic fingerprint `cache` last stored for it. Several such files make the
+    /// match ambiguous.
+    fn match_acoustically(
+        tracks: Vec<MissingTrack>,
+        files: &[PathBuf],
+        claimed: &mut HashSet<PathBuf>,
+        cache: &FingerprintCache,
+        report: &mut RelocationReport,
+    ) -> Result<(), LibHelperError> {
+        let mut known = vec![];
+        for track in tracks {
+            let path = Path::new(&track.location);
+            match cache.last_known(path, &FingerprintAlgorithm::Acoustic)? {
+                Some(fingerprint) => known.push((track, fingerprint)),
+                None => report.not_found.push(track),
+            }
+        }
+        if known.is_empty() {
+            return Ok(());
+        }
+
+        // only audio files are decoded, everything else cannot sound alike
+        let audio_files: Vec<&Path> = files
+            .iter()
+            .filter(|path| !claimed.contains(*path))
+            .filter(|path| matches!(detect_format(path), Ok(Some(_))))
+            .map(|path| path.as_path())
+            .collect();
+        let fingerprints =
+            fingerprint_candidates(&audio_files, &[FingerprintAlgorithm::Acoustic], cache)?;
+
+        for (track, fingerprint) in known {
+            let matches: Vec<PathBuf> = audio_files
+                .iter()
+                .filter(|path| !claimed.contains(**path))
+                .filter(|path| {
+                    fingerprints[**path].iter().any(|candidate| {
+                        fingerprint
+                            .similarity(candidate)
+                            .is_some_and(|similarity| similarity >= SAME_RECORDING_SIMILARITY)
+                    })
+                })
+                .map(|path| path.to_path_buf())
+                .collect();
+            match matches.as_slice() {
+                [] => report.not_found.push(track),
+                [new_location] => {
+                    claimed.insert(new_location.clone());
+                    report.relocations.push(Relocation {
+                        track,
+                        new_location: new_location.clone(),
+                    });
+                }
+                _ => report.ambiguous.push((track, matches)),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The unclaimed files of the size Mixxx recorded, or of the same name if there are none.
+    fn find_candidates(
+        track: &MissingTrack,
+        candidates_by_size: &HashMap<u64, Vec<Candidate>>,
+        candidates_by_name: &HashMap<String, Vec<Candidate>>,
+        claimed: &HashSet<PathBuf>,
+    ) -> Vec<Candidate> {
+        let unclaimed = |candidates: Option<&Vec<Candidate>>| -> Vec<Candidate> {
+            candidates
+                .map(|candidates| {
+                    candidates
+                        .iter()
+                        .filter(|candidate| !claimed.contains(&candidate.path))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        let candidates = match track.filesize {
+            Some(size) if size > 0 => unclaimed(candidates_by_size.get(&size)),
+            _ => vec![],
+        };
+        if !candidates.is_empty() {
+            return candidates;
+        }
+        // editing the tags changes the size, but usually not the name
+        unclaimed(candidates_by_name.get(&track.filename))
+    }
+
+    /// The candidates with the name of the missing file, or all if none has it.
+    fn preferred_candidates<'a>(
+        track: &MissingTrack,
+        candidates: &'a [Candidate],
+    ) -> Vec<&'a Candidate> {
+        let same_name: Vec<&Candidate> = candidates
+            .iter()
+            .filter(|candidate| candidate.filename == track.filename)
+            .collect();
+        if same_name.is_empty() {
+            candidates.iter().collect()
+        } else {
+            same_name
+        }
+    }
+
+    /// Fingerprints the candidates that are not in `cache` in parallel and stores
+    /// their fingerprints there. A candidate that cannot be read gets no
+    /// fingerprints, which leaves it out when choosing between candidates.
+    fn fingerprint_candidates(
+        paths: &[&Path],
+        algorithms: &[FingerprintAlgorithm],
+        cache: &FingerprintCache,
+    ) -> Result<HashMap<PathBuf, Vec<Fingerprint>>, LibHelperError> {
+        let mut fingerprints: HashMap<PathBuf, Vec<Fingerprint>> = HashMap::new();
+        let mut jobs = vec![];
+        let mut stamps: HashMap<PathBuf, FileStamp> = HashMap::new();
+        for path in paths {
+            let Ok(stamp) = FileStamp::of(path) else {
+                fingerprints.insert(path.to_path_buf(), vec![]);
+                continue;
+            };
+            let mut cached = vec![];
+            let mut outdated = vec![];
+            for algorithm in algorithms {
+                match cache.get(path, algorithm, &stamp)? {
+                    Some(fingerprint) => cached.extend(fingerprint),
+                    None => outdated.push(algorithm.clone()),
+                }
+            }
+            if !outdated.is_empty() {
+                stamps.insert(path.to_path_buf(), stamp);
+                jobs.push(FingerprintJob {
+                    path: path.to_path_buf(),
+                    algorithms: outdated,
+                });
+            }
+            fingerprints.insert(path.to_path_buf(), cached);
+        }
+
+        let mut computed: Vec<(FingerprintJob, Vec<Fingerprint>)> = vec![];
+        fingerprint_files(
+            &jobs,
+            &PoolOptions::default(),
+            |job, result, _| match result {
+                Ok(result) => computed.push((job.clone(), result)),
+                Err(_) => {
+                    fingerprints.insert(job.path.clone(), vec![]);
+                }
+            },
+        );
+        cache.batch(|cache| {
+            for (job, result) in &computed {
+                for algorithm in &job.algorithms {
+                    let fingerprint = find_fingerprint(result, algorithm);
+                    cache.put(&job.path, algorithm, &stamps[&job.path], fingerprint)?;
+                }
+            }
+            Ok(())
+        })?;
+        for (job, result) in computed {
+            fingerprints.entry(job.path).or_default().extend(result);
+        }
+
+        Ok(fingerprints)
+    }
+
+    /// The candidate all readable preferred candidates are copies of, if any.
+    fn choose_candidate(
+        track: &MissingTrack,
+        candidates: &[Candidate],
+        fingerprints: &HashMap<PathBuf, Vec<Fingerprint>>,
+    ) -> Option<PathBuf> {
+        let preferred = preferred_candidates(track, candidates);
+        if preferred.len() == 1 {
+            return Some(preferred[0].path.clone());
+        }
+
+        let mut readable: Vec<&Candidate> = vec![];
+        let mut distinct_contents: Vec<&Fingerprint> = vec![];
+        for candidate in &preferred {
+            // copies that only differ in their tags have the same audio data
+            let Some(candidate_fingerprints) = fingerprints.get(&candidate.path) else {
+                continue;
+            };
+            let content = find_fingerprint(
+                candidate_fingerprints,
+                &FingerprintAlgorithm::AudioPayloadSHA256,
+            )
+            .or(candidate_fingerprints.first());
+            if let Some(content) = content {
+                readable.push(candidate);
+                if !distinct_contents.contains(&content) {
+                    distinct_contents.push(content);
+                }
+            }
+        }
+
+        if distinct_contents.len() == 1 {
+            return Some(readable[0].path.clone());
+        }
+
+        None
+    }
+
+    fn find_known_locations(db: &MixxxDb) -> Result<HashSet<String>, LibHelperError> {
+        db.require_columns("track_locations", &["location"])?;
+        let mut stmt = db
+            .connection()
+            .prepare("SELECT location FROM track_locations;")?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<HashSet<String>, _>>()?)
+    }
+
+    /// Plans pointing the location rows of the relocated tracks at their new files.
+    /// Cues, beatgrids and play counts stay attached because the `library` rows are not touched.
+    /// `needs_verification` is only reset on schemas that have it.
+    pub fn plan_relocations(
+        db: &MixxxDb,
+        relocations: &[Relocation],
+    ) -> Result<ChangePlan, LibHelperError> {
+        let mut plan = ChangePlan::default();
+        for relocation in relocations {
+            let new_location = relocation.new_location.as_path();
+            let mut new_values = vec![
+                (
+                    "location",
+                    ColumnValue::Text(new_location.to_string_lossy().to_string()),
+                ),
+                ("filename", ColumnValue::Text(file_name(new_location))),
+                ("directory", ColumnValue::Text(directory(new_location))),
+                ("fs_deleted", ColumnValue::Integer(0)),
+            ];
+            if db.has_column("track_locations", "needs_verification") {
+                new_values.push(("needs_verification", ColumnValue::Integer(0)));
+            }
+            for (column, new_value) in new_values {
+                let change = plan_track_change(
+                    db,
+                    ChangeTable::TrackLocations,
+                    relocation.track.id,
+                    column,
+                    new_value,
+                )?;
+                plan.changes.extend(change);
+            }
+        }
+
+        Ok(plan)
+    }
+
+    fn file_name(path: &Path) -> String {
+        path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn directory(path: &Path) -> String {
+        path.parent()
+            .map(|dir| dir.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{
+            db_changes::change_plan::change_plan::apply_plan,
+            test_db::test_db::{insert_track, music, open_test_mixxx_db},
+            track_fingerprinting::acoustic::acoustic::{
+                acoustic_fingerprint, encode_acoustic_fingerprint,
+            },
+        };
+
+        fn mark_missing(
+            connection: &rusqlite::Connection,
+            id: usize,
+            location: &str,
+            filesize: u64,
+        ) {
+            connection
+                .execute(
+                    "UPDATE track_locations SET location = ?1, filename = ?2, filesize = ?3, fs_deleted = 1
+                     WHERE id = ?4",
+                    (location, file_name(Path::new(location)), filesize, id),
+                )
+                .unwrap();
+        }
+
+        fn test_cache() -> FingerprintCache {
+            FingerprintCache::open(Path::new(":memory:")).unwrap()
+        }
+
+        #[test]
+        fn find_relocations_matches_moved_file_by_size_and_name() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let moved = root.path().join("crate/track.mp3");
+            fs::create_dir_all(moved.parent().unwrap()).unwrap();
+            fs::write(&moved, "moved content").unwrap();
+            fs::write(root.path().join("other.mp3"), "something else").unwrap();
+
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/drive/track.mp3", "Trance", 138.0);
+            mark_missing(db.connection(), 1, "/old/drive/track.mp3", 13);
+
+            // run
+            let report =
+                find_relocations(&db, &[root.path().to_str().unwrap()], &test_cache()).unwrap();
+
+            // verify
+            assert_eq!(report.relocations.len(), 1);
+            assert_eq!(
+                report.relocations[0].new_location,
+                fs::canonicalize(&moved).unwrap()
+            );
+            assert!(report.ambiguous.is_empty());
+            assert!(report.not_found.is_empty());
+        }
+
+        #[test]
+        fn find_relocations_reports_ambiguous_when_contents_differ() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            fs::create_dir_all(root.path().join("a")).unwrap();
+            fs::create_dir_all(root.path().join("b")).unwrap();
+            fs::write(root.path().join("a/track.mp3"), "aaaa").unwrap();
+            fs::write(root.path().join("b/track.mp3"), "bbbb").unwrap();
+
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/track.mp3", "", 120.0);
+            mark_missing(db.connection(), 1, "/old/track.mp3", 4);
+
+            // run
+            let report =
+                find_relocations(&db, &[root.path().to_str().unwrap()], &test_cache()).unwrap();
+
+            // verify
+            assert!(report.relocations.is_empty());
+            assert_eq!(report.ambiguous.len(), 1);
+            assert_eq!(report.ambiguous[0].1.len(), 2);
+        }
+
+        #[test]
+        fn find_relocations_stores_candidate_fingerprints_in_cache() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let candidate = fs::canonicalize(root.path()).unwrap().join("a/track.mp3");
+            fs::create_dir_all(candidate.parent().unwrap()).unwrap();
+            fs::create_dir_all(root.path().join("b")).unwrap();
+            fs::write(&candidate, "aaaa").unwrap();
+            fs::write(root.path().join("b/track.mp3"), "bbbb").unwrap();
+
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/track.mp3", "", 120.0);
+            mark_missing(db.connection(), 1, "/old/track.mp3", 4);
+            let cache = test_cache();
+
+            // run
+            find_relocations(&db, &[root.path().to_str().unwrap()], &cache).unwrap();
+
+            // verify
+            let stamp = FileStamp::of(&candidate).unwrap();
+            let cached = cache
+                .get(&candidate, &FingerprintAlgorithm::SHA265, &stamp)
+                .unwrap();
+            assert!(matches!(cached, Some(Some(_))));
+        }
+
+        #[test]
+        fn find_relocations_matches_retagged_copies_by_name() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            fs::create_dir_all(root.path().join("a")).unwrap();
+            fs::create_dir_all(root.path().join("b")).unwrap();
+            let audio = b"\xFF\xFB\x90\x00audio frames".to_vec();
+            let mut retagged = audio.clone();
+            retagged.extend_from_slice(b"TAG");
+            retagged.resize(audio.len() + 128, b' ');
+            fs::write(root.path().join("a/track.mp3"), &audio).unwrap();
+            fs::write(root.path().join("b/track.mp3"), &retagged).unwrap();
+
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/track.mp3", "", 120.0);
+            mark_missing(db.connection(), 1, "/old/track.mp3", 1000);
+
+            // run
+            let report =
+                find_relocations(&db, &[root.path().to_str().unwrap()], &test_cache()).unwrap();
+
+            // verify
+            assert_eq!(report.relocations.len(), 1);
+            assert!(report.ambiguous.is_empty());
+        }
+
+        #[test]
+        fn choose_candidate_leaves_out_unreadable_candidates() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let readable = root.path().join("a/track.mp3");
+            fs::create_dir_all(readable.parent().unwrap()).unwrap();
+            fs::write(&readable, "audio").unwrap();
+            let candidates: Vec<Candidate> = [readable.clone(), root.path().join("b/track.mp3")]
+                .into_iter()
+                .map(|path| Candidate {
+                    path,
+                    filename: String::from("track.mp3"),
+                })
+                .collect();
+            let paths: Vec<&Path> = candidates.iter().map(|c| c.path.as_path()).collect();
+            let fingerprints =
+                fingerprint_candidates(&paths, &CONTENT_ALGORITHMS, &test_cache()).unwrap();
+            let track = MissingTrack {
+                id: 1,
+                location: String::from("/old/track.mp3"),
+                filename: String::from("track.mp3"),
+                filesize: None,
+            };
+
+            // run
+            let chosen = choose_candidate(&track, &candidates, &fingerprints);
+
+            // verify
+            assert_eq!(chosen, Some(readable));
+        }
+
+        #[test]
+        fn find_relocations_matches_reencoded_copy_by_sound() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let converted = fs::canonicalize(root.path()).unwrap().join("converted");
+            fs::create_dir_all(&converted).unwrap();
+            let copy = converted.join("track.flac");
+            let other = converted.join("other.flac");
+            fs::write(&copy, "fLaC re-encoded audio").unwrap();
+            fs::write(&other, "fLaC other audio").unwrap();
+
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/track.wav", "", 120.0);
+            mark_missing(db.connection(), 1, "/old/track.wav", 1_000_000);
+
+            // fingerprints as `fingerprint index` stored them, the copy quieter and
+            // shifted a little like after a lossy encoder
+            let original = music(1, 20);
+            let mut reencoded = vec![0.0; 300];
+            reencoded.extend(original.iter().map(|sample| 0.6 * sample));
+            let acoustic = |samples: &[f32]| Fingerprint {
+                fingerprint: encode_acoustic_fingerprint(&acoustic_fingerprint(samples)),
+                fingerprint_algorithm: FingerprintAlgorithm::Acoustic,
+            };
+            let cache = test_cache();
+            let gone = FileStamp {
+                inode: None,
+                size: 1_000_000,
+                mtime_ns: 0,
+            };
+            for (path, stamp, samples) in [
+                (Path::new("/old/track.wav"), gone, original),
+                (&copy, FileStamp::of(&copy).unwrap(), reencoded),
+                (&other, FileStamp::of(&other).unwrap(), music(2, 20)),
+            ] {
+                let fingerprint = acoustic(&samples);
+                cache
+                    .put(
+                        path,
+                        &FingerprintAlgorithm::Acoustic,
+                        &stamp,
+                        Some(&fingerprint),
+                    )
+                    .unwrap();
+            }
+
+            // run
+            let report = find_relocations(&db, &[root.path().to_str().unwrap()], &cache).unwrap();
+
+            // verify
+            assert_eq!(report.relocations.len(), 1);
+            assert_eq!(report.relocations[0].new_location, copy);
+            assert!(report.ambiguous.is_empty());
+            assert!(report.not_found.is_empty());
+        }
+
+        #[test]
+        fn find_relocations_skips_files_already_in_library() {
+            // setup
+            let root = tempfile::tempdir().unwrap();
+            let existing = fs::canonicalize(root.path()).unwrap().join("track.mp3");
+            fs::write(&existing, "1234").unwrap();
+
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/track.mp3", "", 120.0);
+            mark_missing(db.connection(), 1, "/old/track.mp3", 4);
+            insert_track(db.connection(), 2, existing.to_str().unwrap(), "", 120.0);
+
+            // run
+            let report =
+                find_relocations(&db, &[root.path().to_str().unwrap()], &test_cache()).unwrap();
+
+            // verify
+            assert!(report.relocations.is_empty());
+            assert_eq!(report.not_found.len(), 1);
+        }
+
+        #[test]
+        fn plan_relocations_rewrites_location_columns() {
+            // setup
+            let mut db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/old/track.mp3", "", 120.0);
+            mark_missing(db.connection(), 1, "/old/track.mp3", 4);
+            let track = find_missing_tracks(&db).unwrap().remove(0);
+
+            // run
+            let plan = plan_relocations(
+                &db,
+                &[Relocation {
+                    track,
+                    new_location: PathBuf::from("/new/dir/track.mp3"),
+                }],
+            )
+            .unwrap();
+            apply_plan(&mut db, &plan).unwrap();
+
+            // verify
+            let row: (String, String, String, i64) = db
+                .connection()
+                .query_row(
+                    "SELECT location, filename, directory, fs_deleted FROM track_locations WHERE id = 1",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )
+                .unwrap();
+            assert_eq!(
+                row,
+                (
+                    String::from("/new/dir/track.mp3"),
+                    String::from("track.mp3"),
+                    String::from("/new/dir"),
+                    0
+                )
+            );
+        }
+    }
+}
@@ -0,0 +1,185 @@
+pub mod selection {
+    use regex::Regex;
+    use rusqlite::types::Value;
+
+    use crate::{
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb, error::error::LibHelperError,
+    };
+
+    /// Which tracks a command works on. All given criteria have to match.
+    #[derive(Clone, Debug, Default)]
+    pub struct TrackSelection {
+        /// Case-insensitive regular expression for `library.genre`
+        pub genre: Option<String>,
+        /// Name of a crate the track is in
+        pub crate_name: Option<String>,
+        /// Name of a playlist the track is in
+        pub playlist: Option<String>,
+        /// Directory the file is in, including subdirectories
+        pub directory: Option<String>,
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct SelectedTrack {
+        pub id: usize,
+        pub location: String,
+        pub genre: String,
+    }
+
+    impl TrackSelection {
+        pub fn is_empty(&self) -> bool {
+            self.genre.is_none()
+                && self.crate_name.is_none()
+                && self.playlist.is_none()
+                && self.directory.is_none()
+        }
+    }
+
+    /// Finds the tracks in the library, leaving out deleted ones, that match `selection`.
+    /// An empty selection is refused rather than taken to mean the whole library.
+    pub fn select_tracks(
+        db: &MixxxDb,
+        selection: &TrackSelection,
+    ) -> Result<Vec<SelectedTrack>, LibHelperError> {
+        if selection.is_empty() {
+            return Err(LibHelperError::Plan(String::from(
+                "Select tracks by genre, crate, playlist or directory",
+            )));
+        }
+        let genre_pattern = match &selection.genre {
+            Some(genre) => Some(Regex::new(&format!("(?i){genre}"))?),
+            None => None,
+        };
+
+        db.require_columns("library", &["id", "location", "genre", "mixxx_deleted"])?;
+        db.require_columns("track_locations", &["id", "location", "directory"])?;
+        let mut query = String::from(
+            "SELECT l.id, tl.location, l.genre FROM library l
+             INNER JOIN track_locations tl
+             ON tl.id = l.location
+             WHERE l.mixxx_deleted IS NOT 1",
+        );
+        let mut params: Vec<Value> = vec![];
+
+        if let Some(crate_name) = &selection.crate_name {
+            db.require_columns("crates", &["id", "name"])?;
+            db.require_columns("crate_tracks", &["crate_id", "track_id"])?;
+            params.push(Value::Text(crate_name.clone()));
+            query.push_str(&format!(
+                " AND l.id IN (SELECT ct.track_id FROM crate_tracks ct
+                   INNER JOIN crates c ON c.id = ct.crate_id
+                   WHERE c.name = ?{})",
+                params.len()
+            ));
+        }
+        if let Some(playlist) = &selection.playlist {
+            db.require_columns("Playlists", &["id", "name"])?;
+            db.require_columns("PlaylistTracks", &["playlist_id", "track_id"])?;
+            params.push(Value::Text(playlist.clone()));
+            query.push_str(&format!(
+                " AND l.id IN (SELECT pt.track_id FROM PlaylistTracks pt
+                   INNER JOIN Playlists p ON p.id = pt.playlist_id
+                   WHERE p.name = ?{})",
+                params.len()
+            ));
+        }
+        if let Some(directory) = &selection.directory {
+            let directory = directory.trim_end_matches('/');
+            params.push(Value::Text(directory.to_string()));
+            params.push(Value::Text(format!("{directory}/")));
+            query.push_str(&format!(
+                " AND (tl.directory = ?{} OR substr(tl.directory, 1, length(?{})) = ?{})",
+                params.len() - 1,
+                params.len(),
+                params.len()
+            ));
+        }
+        query.push_str(" ORDER BY l.id");
+
+        let mut stmt = db.connection().prepare(&query)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok(SelectedTrack {
+                id: row.get(0)?,
+                location: row.get(1)?,
+                genre: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+            })
+        })?;
+        let tracks = rows.collect::<Result<Vec<SelectedTrack>, _>>()?;
+
+        Ok(tracks
+            .into_iter()
+            .filter(|track| {
+                genre_pattern
+                    .as_ref()
+                    .is_none_or(|pattern| pattern.is_match(&track.genre))
+            })
+            .collect())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_db::test_db::{insert_track, open_test_mixxx_db};
+
+        fn selected_ids(db: &MixxxDb, selection: &TrackSelection) -> Vec<usize> {
+            select_tracks(db, selection)
+                .unwrap()
+                .iter()
+                .map(|track| track.id)
+                .collect()
+        }
+
+        #[test]
+        fn select_tracks_combines_criteria() {
+            // setup
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, "/music/trance/a.mp3", "Trance", 138.0);
+            insert_track(
+                db.connection(),
+                2,
+                "/music/trance/deep/b.mp3",
+                "Trance",
+                138.0,
+            );
+            insert_track(db.connection(), 3, "/music/trancefolk/c.mp3", "Folk", 100.0);
+            insert_track(db.connection(), 4, "/music/house/d.mp3", "House", 124.0);
+            db.connection()
+                .execute_batch(
+                    "INSERT INTO crates (id, name) VALUES (1, 'Peak time');
+                     INSERT INTO crate_tracks (crate_id, track_id) VALUES (1, 2), (1, 4);
+                     INSERT INTO Playlists (id, name) VALUES (1, 'Friday');
+                     INSERT INTO PlaylistTracks (playlist_id, track_id, position) VALUES (1, 4, 1);",
+                )
+                .unwrap();
+
+            // run & verify
+            let by_directory = TrackSelection {
+                directory: Some(String::from("/music/trance/")),
+                ..TrackSelection::default()
+            };
+            assert_eq!(selected_ids(&db, &by_directory), vec![1, 2]);
+
+            let by_crate_and_genre = TrackSelection {
+                crate_name: Some(String::from("Peak time")),
+                genre: Some(String::from("trance")),
+                ..TrackSelection::default()
+            };
+            assert_eq!(selected_ids(&db, &by_crate_and_genre), vec![2]);
+
+            let by_playlist = TrackSelection {
+                playlist: Some(String::from("Friday")),
+                ..TrackSelection::default()
+            };
+            assert_eq!(selected_ids(&db, &by_playlist), vec![4]);
+        }
+
+        #[test]
+        fn select_tracks_refuses_empty_selection() {
+            // run
+            let result = select_tracks(&open_test_mixxx_db(), &TrackSelection::default());
+
+            // verify
+            assert!(result.is_err());
+        }
+    }
+}
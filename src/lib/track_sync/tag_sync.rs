@@ -0,0 +1,630 @@
+pub mod tag_sync {
+    use std::{collections::HashSet, fmt, path::Path, str::FromStr, time::UNIX_EPOCH};
+
+    use crate::{
+        db_changes::change_plan::change_plan::{
+            plan_track_change, ChangePlan, ChangeTable, ColumnValue,
+        },
+        db_schema::mixxx_db_handle::mixxx_db_handle::MixxxDb,
+        error::error::LibHelperError,
+        track::{
+            metadata::metadata::{
+                format_bpm, read_metadata, TagField, TagFormat, TagProblemKind, TrackMetadata,
+            },
+            tag_report::tag_report::TagReadReport,
+            tags::tags::supports_tag_writing,
+        },
+        track_beats::beat_grid::beat_grid::plan_beats_change,
+        track_selection::selection::selection::{select_tracks, TrackSelection},
+    };
+
+    /// Which way a field is synchronized when the library and the file's tag disagree.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum SyncDirection {
+        /// The library value is written to the file
+        ToFile,
+        /// The tag value is written to the library
+        ToDb,
+        /// The side changed last wins: the file if it was modified after Mixxx
+        /// last read its tags (`library.source_synchronized_ms`), otherwise the library
+        Newest,
+        /// The field is left alone
+        Skip,
+    }
+
+    /// How [`find_differences`] compares the library with the tags.
+    #[derive(Clone, Debug)]
+    pub struct SyncOptions {
+        /// The direction of every field without one of its own
+        pub direction: SyncDirection,
+        /// Directions of single fields, later ones take precedence
+        pub field_directions: Vec<(TagField, SyncDirection)>,
+        /// The tracks to compare, the whole library if empty
+        pub selection: TrackSelection,
+    }
+
+    /// What happens to a field whose library and tag values differ.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum Resolution {
+        ToFile,
+        ToDb,
+        /// The difference is shown, but left alone for the given reason
+        Unresolved(String),
+    }
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub struct FieldDifference {
+        pub track_id: usize,
+        pub location: String,
+        pub field: TagField,
+        /// `None` if the value is missing or blank
+        pub db_value: Option<String>,
+        /// `None` if the value is missing or blank
+        pub file_value: Option<String>,
+        pub resolution: Resolution,
+    }
+
+    /// A track of the library with the values of the fields to compare.
+    struct LibraryTrack {
+        id: usize,
+        location: String,
+        values: Vec<(TagField, Option<String>)>,
+        bpm_locked: bool,
+        synchronized_ms: Option<i64>,
+    }
+
+    impl SyncDirection {
+        fn name(&self) -> &'static str {
+            match self {
+                SyncDirection::ToFile => "to-file",
+                SyncDirection::ToDb => "to-db",
+                SyncDirection::Newest => "newest",
+                SyncDirection::Skip => "skip",
+            }
+        }
+    }
+
+    impl fmt::Display for SyncDirection {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}", self.name())
+        }
+    }
+
+    impl FromStr for SyncDirection {
+        type Err = String;
+
+        fn from_str(name: &str) -> Result<Self, Self::Err> {
+            [
+                SyncDirection::ToFile,
+                SyncDirection::ToDb,
+                SyncDirection::Newest,
+                SyncDirection::Skip,
+            ]
+            .into_iter()
+            .find(|direction| direction.name().eq_ignore_ascii_case(name.trim()))
+            .ok_or_else(|| {
+                format!("unknown direction {name}, expected to-file, to-db, newest or skip")
+            })
+        }
+    }
+
+    impl Default for SyncOptions {
+        fn default() -> Self {
+            SyncOptions {
+                direction: SyncDirection::Newest,
+                field_directions: vec![],
+                selection: TrackSelection::default(),
+            }
+        }
+    }
+
+    impl SyncOptions {
+        pub fn direction_for(&self, field: TagField) -> SyncDirection {
+            self.field_directions
+                .iter()
+                .rev()
+                .find(|(other, _)| *other == field)
+                .map(|(_, direction)| *direction)
+                .unwrap_or(self.direction)
+        }
+    }
+
+    impl fmt::Display for FieldDifference {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let show = |value: &Option<String>| match value {
+                Some(value) => format!("{value:?}"),
+                None => String::from("empty"),
+            };
+            write!(
+                f,
+                "{}: library {}, file {}",
+                self.field,
+                show(&self.db_value),
+                show(&self.file_value)
+            )?;
+            match &self.resolution {
+                Resolution::ToFile => write!(f, " -> file"),
+                Resolution::ToDb => write!(f, " -> library"),
+                Resolution::Unresolved(reason) => write!(f, ", left alone: {reason}"),
+            }
+        }
+    }
+
+    /// Compares the library with the tags of the files, leaving out deleted
+    /// tracks and missing files, and decides for every differing field which
+    /// side wins. An empty value never overwrites a value on the other side.
+    /// Files whose tags cannot be read are noted in `tag_report`.
+    pub fn find_differences(
+        db: &MixxxDb,
+        options: &SyncOptions,
+        tag_report: &mut TagReadReport,
+    ) -> Result<Vec<FieldDifference>, LibHelperError> {
+        let fields: Vec<TagField> = TagField::ALL
+            .into_iter()
+            .filter(|field| options.direction_for(*field) != SyncDirection::Skip)
+            .collect();
+        if fields.is_empty() {
+            return Ok(vec![]);
+        }
+        let selected: Option<HashSet<usize>> = if options.selection.is_empty() {
+            None
+        } else {
+            let tracks = select_tracks(db, &options.selection)?;
+            Some(tracks.into_iter().map(|track| track.id).collect())
+        };
+
+        let mut differences = vec![];
+        for track in read_library_tracks(db, &fields)? {
+            if selected
+                .as_ref()
+                .is_some_and(|selected| !selected.contains(&track.id))
+            {
+                continue;
+            }
+            let path = Path::new(&track.location);
+            if !path.is_file() {
+                continue;
+            }
+            let Some(metadata) = read_tags(path, tag_report) else {
+                continue;
+            };
+            let is_writable = supports_tag_writing(path);
+            let newest = newest_side(track.synchronized_ms, modified_ms(path));
+
+            for (field, db_value) in &track.values {
+                let file_value = metadata.get(*field);
+                if values_equal(*field, db_value, &file_value) {
+                    continue;
+                }
+                let direction = match options.direction_for(*field) {
+                    SyncDirection::Newest => newest,
+                    direction => Some(direction),
+                };
+                let unresolved = |reason: &str| Resolution::Unresolved(reason.to_string());
+                let resolution = match direction {
+                    Some(SyncDirection::ToFile) if db_value.is_none() => {
+                        unresolved("empty in the library")
+                    }
+                    Some(SyncDirection::ToFile) if !is_writable => {
+                        unresolved("tags of this format cannot be written")
+                    }
+                    Some(SyncDirection::ToFile) => Resolution::ToFile,
+                    Some(SyncDirection::ToDb) if file_value.is_none() => {
+                        unresolved("empty in the file")
+                    }
+                    Some(SyncDirection::ToDb) if *field == TagField::Bpm && track.bpm_locked => {
+                        unresolved("the BPM is locked")
+                    }
+                    // Mixxx reads the key from the `keys` blob, not the `key` column
+                    Some(SyncDirection::ToDb) if *field == TagField::Key => {
+                        unresolved("the key is stored in the keys blob")
+                    }
+                    Some(SyncDirection::ToDb) => Resolution::ToDb,
+                    _ => unresolved("Mixxx has not recorded when it last read the tags"),
+                };
+                differences.push(FieldDifference {
+                    track_id: track.id,
+                    location: track.location.clone(),
+                    field: *field,
+                    db_value: db_value.clone(),
+                    file_value,
+                    resolution,
+                });
+            }
+        }
+
+        Ok(differences)
+    }
+
+    /// Plans writing the tag values of the differences resolved towards the
+    /// library. BPM changes rescale the beats like `fix_bpm` does, keys are
+    /// left alone.
+    pub fn plan_sync_to_db(
+        db: &MixxxDb,
+        differences: &[FieldDifference],
+    ) -> Result<ChangePlan, LibHelperError> {
+        let mut plan = ChangePlan::default();
+        for difference in differences {
+            let (Resolution::ToDb, Some(value)) = (&difference.resolution, &difference.file_value)
+            else {
+                continue;
+            };
+            if difference.field == TagField::Key {
+                continue;
+            }
+            let new_value = if difference.field == TagField::Bpm {
+                let Ok(bpm) = value.parse::<f64>() else {
+                    continue;
+                };
+                match plan_beats_change(db, difference.track_id, bpm) {
+                    Ok(beats_changes) => plan.changes.extend(beats_changes),
+                    Err(err @ LibHelperError::InvalidBeats(_)) => {
+                        println!(
+                            "Skipping the BPM of track {} ({}): {err}",
+                            difference.track_id, difference.location
+                        );
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                }
+                ColumnValue::Real(bpm)
+            } else {
+                ColumnValue::Text(value.clone())
+            };
+
+            let change = plan_track_change(
+                db,
+                ChangeTable::Library,
+                difference.track_id,
+                difference.field.name(),
+                new_value,
+            )?;
+            plan.changes.extend(change);
+        }
+
+        Ok(plan)
+    }
+
+    /// The library values of the differences resolved towards the files,
+    /// grouped by file so each is written once.
+    pub fn plan_tag_writes(
+        differences: &[FieldDifference],
+    ) -> Vec<(String, Vec<(TagField, String)>)> {
+        let mut writes: Vec<(String, Vec<(TagField, String)>)> = vec![];
+        for difference in differences {
+            let (Resolution::ToFile, Some(value)) = (&difference.resolution, &difference.db_value)
+            else {
+                continue;
+            };
+            let field = (difference.field, value.clone());
+            match writes.last_mut() {
+                Some((location, fields)) if *location == difference.location => fields.push(field),
+                _ => writes.push((difference.location.clone(), vec![field])),
+            }
+        }
+
+        writes
+    }
+
+    fn read_library_tracks(
+        db: &MixxxDb,
+        fields: &[TagField],
+    ) -> Result<Vec<LibraryTrack>, LibHelperError> {
+        let field_columns: Vec<&str> = fields.iter().map(|field| field.name()).collect();
+        db.require_columns("library", &["id", "location", "mixxx_deleted"])?;
+        db.require_columns("library", &field_columns)?;
+        db.require_columns("track_locations", &["id", "location"])?;
+        // older schemas lack these, which only limits what can be decided
+        let optional_column = |column: &str| {
+            if db.has_column("library", column) {
+                format!("l.{column}")
+            } else {
+                String::from("NULL")
+            }
+        };
+
+        let selected_columns: Vec<String> = field_columns
+            .iter()
+            .map(|column| format!("l.{column}"))
+            .collect();
+        let mut stmt = db.connection().prepare(&format!(
+            "SELECT l.id, tl.location, {}, {}, {} FROM library l
+             INNER JOIN track_locations tl
+             ON tl.id = l.location
+             WHERE l.mixxx_deleted IS NOT 1
+             ORDER BY l.id",
+            optional_column("bpm_lock"),
+            optional_column("source_synchronized_ms"),
+            selected_columns.join(", ")
+        ))?;
+        let tracks = stmt
+            .query_map([], |row| {
+                let mut values = vec![];
+                for (index, field) in fields.iter().enumerate() {
+                    let value: ColumnValue = row.get(4 + index)?;
+                    values.push((*field, column_text(*field, value)));
+                }
+                Ok(LibraryTrack {
+                    id: row.get(0)?,
+                    location: row.get(1)?,
+                    bpm_locked: row.get::<_, Option<i64>>(2)?.unwrap_or(0) != 0,
+                    synchronized_ms: row.get(3)?,
+                    values,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(tracks)
+    }
+
+    /// A library value as the text a tag would hold, `None` if it is empty.
+    fn column_text(field: TagField, value: ColumnValue) -> Option<String> {
+        let text = match value {
+            ColumnValue::Null | ColumnValue::Blob(_) => return None,
+            // Mixxx stores 0 for tracks without a BPM
+            ColumnValue::Real(bpm) if field == TagField::Bpm => {
+                return (bpm > 0.0).then(|| format_bpm(bpm))
+            }
+            ColumnValue::Real(value) => value.to_string(),
+            ColumnValue::Integer(value) if field == TagField::Bpm => {
+                return (value > 0).then(|| value.to_string())
+            }
+            ColumnValue::Integer(value) => value.to_string(),
+            ColumnValue::Text(value) => value,
+        };
+        (!text.trim().is_empty()).then_some(text)
+    }
+
+    /// Reads the tags of a file. A file without a tag counts as one with an
+    /// empty tag if tags can be written to it, so library values can fill it.
+    fn read_tags(path: &Path, tag_report: &mut TagReadReport) -> Option<TrackMetadata> {
+        match read_metadata(path) {
+            Ok(metadata) => {
+                tag_report.read += 1;
+                Some(metadata)
+            }
+            Err(problem) if problem.kind == TagProblemKind::NoTag && supports_tag_writing(path) => {
+                tag_report.read += 1;
+                Some(TrackMetadata::empty(TagFormat::Id3))
+            }
+            Err(problem) => {
+                tag_report.problems.push(problem);
+                None
+            }
+        }
+    }
+
+    fn values_equal(
+        field: TagField,
+        db_value: &Option<String>,
+        file_value: &Option<String>,
+    ) -> bool {
+        match (db_value, file_value) {
+            (Some(db_value), Some(file_value)) if field == TagField::Bpm => {
+                match (db_value.parse::<f64>(), file_value.parse::<f64>()) {
+                    (Ok(db_bpm), Ok(file_bpm)) => format_bpm(db_bpm) == format_bpm(file_bpm),
+                    _ => db_value.trim() == file_value.trim(),
+                }
+            }
+            (Some(db_value), Some(file_value)) => db_value.trim() == file_value.trim(),
+            (db_value, file_value) => db_value.is_none() && file_value.is_none(),
+        }
+    }
+
+    /// Which side changed last, or `None` if Mixxx did not record when it read the tags.
+    fn newest_side(
+        synchronized_ms: Option<i64>,
+        modified_ms: Option<i64>,
+    ) -> Option<SyncDirection> {
+        let synchronized_ms = synchronized_ms.filter(|&ms| ms > 0)?;
+        Some(if modified_ms? > synchronized_ms {
+            SyncDirection::ToDb
+        } else {
+            SyncDirection::ToFile
+        })
+    }
+
+    /// The modification time of a file in milliseconds since the Unix epoch,
+    /// the unit of `library.source_synchronized_ms`.
+    fn modified_ms(path: &Path) -> Option<i64> {
+        let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+        let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+        i64::try_from(since_epoch.as_millis()).ok()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::{
+            test_db::test_db::{insert_track, open_test_mixxx_db},
+            track::tags::tags::write_fields,
+        };
+
+        fn tagged_mp3(dir: &Path, name: &str) -> String {
+            let path = dir.join(name);
+            std::fs::write(&path, [0xFF, 0xFB, 0x90, 0x00]).unwrap();
+            write_fields(
+                &path,
+                &[
+                    (TagField::Artist, String::from("Tag Artist")),
+                    (TagField::Genre, String::from("House")),
+                    (TagField::Bpm, String::from("128")),
+                ],
+            )
+            .unwrap();
+            path.to_string_lossy().to_string()
+        }
+
+        #[test]
+        fn find_differences_uses_direction_of_each_field() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let location = tagged_mp3(dir.path(), "track.mp3");
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, &location, "Trance", 128.0);
+            db.connection()
+                .execute(
+                    "UPDATE library SET artist = 'DB Artist', comment = 'Intro', bpm_lock = 1",
+                    [],
+                )
+                .unwrap();
+            let options = SyncOptions {
+                direction: SyncDirection::ToDb,
+                field_directions: vec![(TagField::Genre, SyncDirection::ToFile)],
+                ..SyncOptions::default()
+            };
+
+            // run
+            let differences =
+                find_differences(&db, &options, &mut TagReadReport::default()).unwrap();
+
+            // verify
+            let resolutions: Vec<(TagField, Resolution)> = differences
+                .iter()
+                .map(|difference| (difference.field, difference.resolution.clone()))
+                .collect();
+            assert_eq!(
+                resolutions,
+                vec![
+                    (TagField::Artist, Resolution::ToDb),
+                    (TagField::Genre, Resolution::ToFile),
+                    (
+                        TagField::Comment,
+                        Resolution::Unresolved(String::from("empty in the file"))
+                    ),
+                ]
+            );
+            assert_eq!(
+                differences[0].to_string(),
+                "artist: library \"DB Artist\", file \"Tag Artist\" -> library"
+            );
+        }
+
+        #[test]
+        fn find_differences_leaves_key_towards_library_unresolved() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let location = tagged_mp3(dir.path(), "track.mp3");
+            write_fields(Path::new(&location), &[(TagField::Key, String::from("8A"))]).unwrap();
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, &location, "House", 128.0);
+            db.connection()
+                .execute("UPDATE library SET artist = 'Tag Artist', key = '5A'", [])
+                .unwrap();
+            let options = SyncOptions {
+                direction: SyncDirection::ToDb,
+                ..SyncOptions::default()
+            };
+
+            // run
+            let differences =
+                find_differences(&db, &options, &mut TagReadReport::default()).unwrap();
+            let plan = plan_sync_to_db(&db, &differences).unwrap();
+
+            // verify
+            assert_eq!(differences.len(), 1);
+            assert_eq!(differences[0].field, TagField::Key);
+            assert_eq!(
+                differences[0].resolution,
+                Resolution::Unresolved(String::from("the key is stored in the keys blob"))
+            );
+            assert!(plan.changes.is_empty());
+        }
+
+        #[test]
+        fn find_differences_lets_newest_side_win() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let db = open_test_mixxx_db();
+            for id in 1..=3 {
+                let location = tagged_mp3(dir.path(), &format!("{id}.mp3"));
+                let modified = modified_ms(Path::new(&location)).unwrap();
+                let synchronized_ms = match id {
+                    1 => Some(modified - 1000),
+                    2 => Some(modified),
+                    _ => None,
+                };
+                insert_track(db.connection(), id, &location, "Trance", 128.0);
+                db.connection()
+                    .execute(
+                        "UPDATE library SET source_synchronized_ms = ?1 WHERE id = ?2",
+                        (synchronized_ms, id),
+                    )
+                    .unwrap();
+            }
+
+            // run
+            let differences =
+                find_differences(&db, &SyncOptions::default(), &mut TagReadReport::default())
+                    .unwrap();
+
+            // verify
+            let resolutions: Vec<(usize, TagField, Resolution)> = differences
+                .iter()
+                .map(|difference| {
+                    (
+                        difference.track_id,
+                        difference.field,
+                        difference.resolution.clone(),
+                    )
+                })
+                .collect();
+            let not_recorded = Resolution::Unresolved(String::from(
+                "Mixxx has not recorded when it last read the tags",
+            ));
+            assert_eq!(
+                resolutions,
+                vec![
+                    (1, TagField::Artist, Resolution::ToDb),
+                    (1, TagField::Genre, Resolution::ToDb),
+                    (
+                        2,
+                        TagField::Artist,
+                        Resolution::Unresolved(String::from("empty in the library"))
+                    ),
+                    (2, TagField::Genre, Resolution::ToFile),
+                    (3, TagField::Artist, not_recorded.clone()),
+                    (3, TagField::Genre, not_recorded),
+                ]
+            );
+        }
+
+        #[test]
+        fn plan_sync_to_db_and_plan_tag_writes_split_differences() {
+            // setup
+            let dir = tempfile::tempdir().unwrap();
+            let location = tagged_mp3(dir.path(), "track.mp3");
+            let db = open_test_mixxx_db();
+            insert_track(db.connection(), 1, &location, "Trance", 64.0);
+            let options = SyncOptions {
+                direction: SyncDirection::ToDb,
+                field_directions: vec![(TagField::Genre, SyncDirection::ToFile)],
+                ..SyncOptions::default()
+            };
+            let differences =
+                find_differences(&db, &options, &mut TagReadReport::default()).unwrap();
+
+            // run
+            let plan = plan_sync_to_db(&db, &differences).unwrap();
+            let tag_writes = plan_tag_writes(&differences);
+
+            // verify
+            let columns: Vec<(&str, &ColumnValue)> = plan
+                .changes
+                .iter()
+                .map(|change| (change.column.as_str(), &change.new_value))
+                .collect();
+            assert_eq!(
+                columns,
+                vec![
+                    ("artist", &ColumnValue::Text(String::from("Tag Artist"))),
+                    ("bpm", &ColumnValue::Real(128.0)),
+                ]
+            );
+            assert_eq!(
+                tag_writes,
+                vec![(location, vec![(TagField::Genre, String::from("Trance"))])]
+            );
+        }
+    }
+}
@@ -1,68 +1,554 @@
-use core::panic;
+use clap::{Args, Parser, Subcommand};
 use mixxx_libhelper::mixxx_db;
+use mixxx_libhelper::mixxx_db::{
+    BpmRuleSet, DuplicateMatch, FingerprintAlgorithm, FingerprintCache, FixBpmOptions,
+    GenreTaxonomy, MergePolicy, PoolOptions, SyncDirection, SyncOptions, TagField, TrackSelection,
+    WriteOptions, DEFAULT_KEEP_BACKUPS, DEFAULT_MIN_AUDIO_CONFIDENCE,
+};
 use mixxx_libhelper::mixxx_logfile;
-use std::env;
+use mixxx_libhelper::mixxx_settings;
+use mixxx_libhelper::LibHelperError;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
-const COMMAND_DB: &str = "db";
-const COMMAND_LOGFILE: &str = "logfile_anonymize";
+/// A tiny utility to automatically fix common issues with the track library of Mixxx DJ software
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Print more details, including full error information
+    #[arg(short, long, global = true)]
+    verbose: bool,
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args: Vec<String> = env::args().collect();
-    let command = &get_command(&args);
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Inspect and fix the Mixxx track library
+    Db(DbArgs),
+    /// Clean up the genres of the Mixxx track library
+    Genre(GenreArgs),
+    /// Fingerprint the audio of the tracks to recognize copies
+    Fingerprint(FingerprintArgs),
+    /// Work with Mixxx log files
+    #[command(subcommand)]
+    Logfile(LogfileCommand),
+}
+
+#[derive(Args, Debug)]
+struct DbArgs {
+    /// Path to mixxxdb.sqlite [default: mixxxdb.sqlite in the Mixxx settings directory,
+    /// which can be overridden with $MIXXX_SETTINGS_PATH]
+    #[arg(long, global = true, value_name = "FILE")]
+    db: Option<String>,
+
+    #[command(flatten)]
+    write: WriteArgs,
+
+    #[command(subcommand)]
+    command: DbCommand,
+}
+
+#[derive(Args, Debug)]
+struct GenreArgs {
+    /// Path to mixxxdb.sqlite [default: mixxxdb.sqlite in the Mixxx settings directory,
+    /// which can be overridden with $MIXXX_SETTINGS_PATH]
+    #[arg(long, global = true, value_name = "FILE")]
+    db: Option<String>,
+
+    /// TOML file extending the built-in genre taxonomy
+    #[arg(long, global = true, value_name = "FILE")]
+    taxonomy: Option<String>,
+
+    #[command(flatten)]
+    write: WriteArgs,
+
+    #[command(subcommand)]
+    command: GenreCommand,
+}
+
+#[derive(Args, Debug)]
+struct FingerprintArgs {
+    /// Path to mixxxdb.sqlite [default: mixxxdb.sqlite in the Mixxx settings directory,
+    /// which can be overridden with $MIXXX_SETTINGS_PATH]
+    #[arg(long, global = true, value_name = "FILE")]
+    db: Option<String>,
+
+    /// Where to keep the fingerprints [default: libhelper-fingerprints.sqlite next to the database]
+    #[arg(long, global = true, value_name = "FILE")]
+    cache: Option<String>,
+
+    #[command(subcommand)]
+    command: FingerprintCommand,
+}
+
+#[derive(Args, Debug)]
+struct WriteArgs {
+    /// Answer all confirmation prompts with yes
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Show the changes that would be made without writing anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Save the changes that would be made to a file for `db apply` instead of writing them
+    #[arg(long, global = true, value_name = "FILE")]
+    plan_out: Option<String>,
+
+    /// Where to keep database backups [default: libhelper-backups next to the database]
+    #[arg(long, global = true, value_name = "DIR")]
+    backup_dir: Option<String>,
+
+    /// How many database backups to keep
+    #[arg(long, global = true, default_value_t = DEFAULT_KEEP_BACKUPS,
+          value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    keep_backups: usize,
+
+    /// Wait for Mixxx to close instead of failing when it is running
+    #[arg(long, global = true)]
+    wait: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum DbCommand {
+    /// Correct BPMs detected at the wrong tempo, by default those of EDM tracks
+    /// detected at 2/3 of their tempo
+    FixBpm {
+        /// TOML file with the rules to apply instead of the EDM default
+        #[arg(long, value_name = "FILE")]
+        rules: Option<String>,
+
+        /// Also change tracks whose BPM was locked in Mixxx
+        #[arg(long)]
+        include_locked: bool,
+
+        /// Analyze the audio of each track and only correct it if the analysis agrees
+        #[arg(long)]
+        verify_audio: bool,
+
+        /// How clearly the audio has to favour the new BPM, from 0.5 to 1
+        #[arg(long, default_value_t = DEFAULT_MIN_AUDIO_CONFIDENCE, requires = "verify_audio")]
+        min_confidence: f64,
+
+        /// Write the files whose tags could not be read to a CSV file, or JSON if FILE ends in .json
+        #[arg(long, value_name = "FILE")]
+        tag_report: Option<String>,
+
+        /// Also write the corrected BPMs to the tags of the files
+        #[arg(long)]
+        write_tags: bool,
+
+        /// Keep the modification times of the files whose tags are written
+        #[arg(long, requires = "write_tags")]
+        preserve_mtime: bool,
+    },
+    /// Lock the BPM of the selected tracks so it is neither re-analyzed nor fixed
+    BpmLock(SelectionArgs),
+    /// Unlock the BPM of the selected tracks
+    BpmUnlock(SelectionArgs),
+    /// Find missing tracks below the given directories and point the library at them
+    Relocate {
+        #[arg(required = true, value_name = "SEARCH_DIR")]
+        search_roots: Vec<String>,
+
+        /// Where to keep the fingerprints [default: libhelper-fingerprints.sqlite next to the database]
+        #[arg(long, value_name = "FILE")]
+        cache: Option<String>,
+    },
+    /// List tracks whose file type in the library does not match the content of the file
+    CheckFiletypes,
+    /// Compare artist, title, album, genre, BPM, key, comment and year in the library
+    /// with the tags of the files and bring both in line
+    Sync {
+        /// Which side wins for fields without a --field direction: to-file, to-db,
+        /// newest (the side changed last) or skip
+        #[arg(long, default_value = "newest")]
+        direction: SyncDirection,
+
+        /// The direction of a single field, e.g. genre=to-file; can be given several times
+        #[arg(long = "field", value_name = "FIELD=DIRECTION", value_parser = parse_field_direction)]
+        field_directions: Vec<(TagField, SyncDirection)>,
 
-    if command == COMMAND_DB {
-        let db_path = get_db_path(&args);
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    /// List tracks imported several times and optionally merge the copies into one
+    Duplicates {
+        /// How copies are recognized: exact for identical files, payload for the same
+        /// audio data with different tags, fuzzy for the same artist and title with
+        /// about the same duration; can be given several times
+        #[arg(long = "by", value_name = "MATCH", default_values = ["exact", "payload", "fuzzy"])]
+        matches: Vec<DuplicateMatch>,
 
-        // TODO detect whether mixxx is still running and ask to close first
-        mixxx_db::fix_edm_bpm(db_path)?;
+        /// Move crates, playlists, history and cues of the copies to the one with the
+        /// most cues, plays and the highest bitrate, and mark the others deleted
+        #[arg(long)]
+        merge: bool,
+
+        #[command(flatten)]
+        policy: MergePolicyArgs,
+
+        /// Where to keep the fingerprints [default: libhelper-fingerprints.sqlite next to the database]
+        #[arg(long, value_name = "FILE")]
+        cache: Option<String>,
+
+        /// How many files to read at the same time [default: one per CPU core];
+        /// 1 is fastest for spinning disks
+        #[arg(long, short = 'j', default_value_t = 0, hide_default_value = true)]
+        jobs: usize,
+    },
+    /// Merge tracks that are the same recording into one and mark the others deleted
+    Merge {
+        /// The track to keep
+        keep_id: usize,
+
+        /// The tracks to merge into it
+        #[arg(required = true)]
+        drop_ids: Vec<usize>,
+
+        #[command(flatten)]
+        policy: MergePolicyArgs,
+    },
+    /// Move a file or directory and update the library to match
+    Move { source: String, target: String },
+    /// Apply the changes saved with --plan-out
+    Apply { plan: String },
+    /// List database backups and restore one
+    Restore {
+        /// Number of the backup to restore, as shown in the list
+        backup: Option<usize>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum GenreCommand {
+    /// Rewrite the genres the taxonomy knows to their canonical names,
+    /// e.g. "trance" and "Trance (Main Floor)" to "Trance"
+    Normalize {
+        /// Also write the new genres to the tags of the files
+        #[arg(long)]
+        write_tags: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum FingerprintCommand {
+    /// Fingerprint the files of the library, skipping those unchanged since the last run
+    Index {
+        /// Which fingerprints to compute: sha256 of the whole file, payload for the
+        /// audio data without tags, or acoustic for the decoded audio
+        #[arg(long = "algorithm", value_name = "ALGORITHM", default_values = ["sha256", "payload"])]
+        algorithms: Vec<FingerprintAlgorithm>,
+
+        /// How many files to read at the same time [default: one per CPU core];
+        /// 1 is fastest for spinning disks
+        #[arg(long, short = 'j', default_value_t = 0, hide_default_value = true)]
+        jobs: usize,
+
+        /// Read at most this many MiB per second, to keep the disk usable for Mixxx
+        #[arg(long, value_name = "MIB_PER_SECOND")]
+        max_read_rate: Option<u64>,
+
+        /// Only fingerprint some tracks instead of the whole library
+        #[command(flatten)]
+        selection: SelectionArgs,
+    },
+    /// Show how many files are fingerprinted and how many changed since
+    Stats,
+}
+
+/// How merged tracks are combined; by default play counts are added up, the highest
+/// rating is kept and cues move to free hotcue slots
+#[derive(Args, Debug)]
+struct MergePolicyArgs {
+    /// Keep the play count and last played time of the kept track
+    #[arg(long)]
+    keep_plays: bool,
+
+    /// Keep the rating of the kept track
+    #[arg(long)]
+    keep_rating: bool,
+
+    /// Leave the cues of the merged tracks where they are
+    #[arg(long)]
+    keep_cues: bool,
+}
+
+/// Selects tracks for a command; all given criteria have to match
+#[derive(Args, Debug)]
+struct SelectionArgs {
+    /// Case-insensitive regular expression for the genre
+    #[arg(long)]
+    genre: Option<String>,
+
+    /// Name of a crate
+    #[arg(long = "crate", value_name = "CRATE")]
+    crate_name: Option<String>,
+
+    /// Name of a playlist
+    #[arg(long)]
+    playlist: Option<String>,
+
+    /// Directory the files are in, including subdirectories
+    #[arg(long, value_name = "DIR")]
+    directory: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+enum LogfileCommand {
+    /// Hide serial numbers and device ids, writing the result to <LOGFILE>.anonymized
+    Anonymize { logfile: String },
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if matches!(err.downcast_ref(), Some(LibHelperError::UserAbort)) => {
+            println!("Aborted, nothing was changed");
+            ExitCode::FAILURE
+        }
+        Err(err) => {
+            if cli.verbose {
+                eprintln!("Error: {err:?}");
+            } else {
+                eprintln!("Error: {err}");
+            }
+            ExitCode::FAILURE
+        }
     }
-    if command == COMMAND_LOGFILE {
-        let logfile_path = get_logfile_path(&args);
+}
+
+fn run(cli: &Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match &cli.command {
+        Command::Db(db_args) => {
+            let db_path = resolve_db_path(db_args.db.as_deref())?;
+            if cli.verbose {
+                println!("Using database {db_path}");
+            }
+            let options = get_write_options(&db_args.write);
+
+            match &db_args.command {
+                DbCommand::FixBpm {
+                    rules,
+                    include_locked,
+                    verify_audio,
+                    min_confidence,
+                    tag_report,
+                    write_tags,
+                    preserve_mtime,
+                } => {
+                    let rule_set = match rules {
+                        Some(rules) => BpmRuleSet::load(rules)?,
+                        None => BpmRuleSet::edm_default(),
+                    };
+                    let bpm_options = FixBpmOptions {
+                        include_locked: *include_locked,
+                        verify_audio: *verify_audio,
+                        min_confidence: *min_confidence,
+                        tag_report: tag_report.clone(),
+                        write_tags: *write_tags,
+                        preserve_mtime: *preserve_mtime,
+                    };
+                    mixxx_db::fix_bpm(&db_path, &rule_set, &bpm_options, &options)?
+                }
+                DbCommand::BpmLock(selection_args) => {
+                    let selection = get_selection(selection_args);
+                    mixxx_db::set_bpm_lock(&db_path, &selection, true, &options)?
+                }
+                DbCommand::BpmUnlock(selection_args) => {
+                    let selection = get_selection(selection_args);
+                    mixxx_db::set_bpm_lock(&db_path, &selection, false, &options)?
+                }
+                DbCommand::Relocate {
+                    search_roots,
+                    cache,
+                } => {
+                    let search_roots: Vec<&str> =
+                        search_roots.iter().map(|root| root.as_str()).collect();
+                    let cache_path = match cache {
+                        Some(cache) => PathBuf::from(cache),
+                        None => FingerprintCache::default_path(&db_path),
+                    };
+                    mixxx_db::relocate_tracks(&db_path, &search_roots, &cache_path, &options)?
+                }
+                DbCommand::CheckFiletypes => mixxx_db::check_filetypes(&db_path)?,
+                DbCommand::Sync {
+                    direction,
+                    field_directions,
+                    selection,
+                } => {
+                    let sync_options = SyncOptions {
+                        direction: *direction,
+                        field_directions: field_directions.clone(),
+                        selection: get_selection(selection),
+                    };
+                    mixxx_db::sync_tags(&db_path, &sync_options, &options)?
+                }
+                DbCommand::Duplicates {
+                    matches,
+                    merge,
+                    policy,
+                    cache,
+                    jobs,
+                } => {
+                    let cache_path = match cache {
+                        Some(cache) => PathBuf::from(cache),
+                        None => FingerprintCache::default_path(&db_path),
+                    };
+                    let pool_options = PoolOptions {
+                        jobs: *jobs,
+                        ..PoolOptions::default()
+                    };
+                    mixxx_db::find_duplicate_tracks(
+                        &db_path,
+                        &cache_path,
+                        matches,
+                        merge.then(|| get_merge_policy(policy)).as_ref(),
+                        &pool_options,
+                        &options,
+                    )?
+                }
+                DbCommand::Merge {
+                    keep_id,
+                    drop_ids,
+                    policy,
+                } => mixxx_db::merge_tracks(
+                    &db_path,
+                    *keep_id,
+                    drop_ids,
+                    &get_merge_policy(policy),
+                    &options,
+                )?,
+                DbCommand::Move { source, target } => {
+                    mixxx_db::move_tracks(&db_path, source, target, &options)?
+                }
+                DbCommand::Apply { plan } => mixxx_db::apply_plan_file(&db_path, plan, &options)?,
+                DbCommand::Restore { backup } => {
+                    mixxx_db::restore_database(&db_path, *backup, &options)?
+                }
+            }
+        }
+        Command::Genre(genre_args) => {
+            let db_path = resolve_db_path(genre_args.db.as_deref())?;
+            if cli.verbose {
+                println!("Using database {db_path}");
+            }
+            let options = get_write_options(&genre_args.write);
+            let taxonomy = match &genre_args.taxonomy {
+                Some(taxonomy) => GenreTaxonomy::load(taxonomy)?,
+                None => GenreTaxonomy::default(),
+            };
 
-        let logfile_anonymized = mixxx_logfile::anonymize_logfile(&logfile_path);
-        let target_filename = String::from(format!("{}.anonymized", logfile_path));
-        let target_path = std::path::Path::new(&target_filename);
-        std::fs::write(target_path, logfile_anonymized?)?;
+            match &genre_args.command {
+                GenreCommand::Normalize { write_tags } => {
+                    mixxx_db::normalize_genres(&db_path, &taxonomy, *write_tags, &options)?
+                }
+            }
+        }
+        Command::Fingerprint(fingerprint_args) => {
+            let cache_path = match &fingerprint_args.cache {
+                Some(cache) => PathBuf::from(cache),
+                None => FingerprintCache::default_path(&resolve_db_path(
+                    fingerprint_args.db.as_deref(),
+                )?),
+            };
+            if cli.verbose {
+                println!("Using fingerprint cache {}", cache_path.display());
+            }
+
+            match &fingerprint_args.command {
+                FingerprintCommand::Index {
+                    algorithms,
+                    jobs,
+                    max_read_rate,
+                    selection,
+                } => {
+                    let db_path = resolve_db_path(fingerprint_args.db.as_deref())?;
+                    let selection = get_selection(selection);
+                    let pool_options = PoolOptions {
+                        jobs: *jobs,
+                        max_bytes_per_second: max_read_rate.map(|rate| rate * 1024 * 1024),
+                        ..PoolOptions::default()
+                    };
+                    mixxx_db::index_fingerprints(
+                        &db_path,
+                        &cache_path,
+                        algorithms,
+                        &selection,
+                        &pool_options,
+                    )?
+                }
+                FingerprintCommand::Stats => mixxx_db::print_fingerprint_stats(&cache_path)?,
+            }
+        }
+        Command::Logfile(LogfileCommand::Anonymize { logfile }) => {
+            let logfile_anonymized = mixxx_logfile::anonymize_logfile(logfile)?;
+            let target_filename = format!("{logfile}.anonymized");
+            std::fs::write(&target_filename, logfile_anonymized)?;
+            if cli.verbose {
+                println!("Wrote {target_filename}");
+            }
+        }
     }
 
     Ok(())
 }
 
-fn get_command(args: &[String]) -> String {
-    if args.len() < 2 {
-        panic!("Nee")
+fn get_merge_policy(policy_args: &MergePolicyArgs) -> MergePolicy {
+    MergePolicy {
+        sum_plays: !policy_args.keep_plays,
+        max_rating: !policy_args.keep_rating,
+        union_hotcues: !policy_args.keep_cues,
     }
-    let mut valid_commands = vec![COMMAND_DB.to_string(), COMMAND_LOGFILE.to_string()];
-    valid_commands.sort();
+}
 
-    let command = &args[2];
-    if !valid_commands.contains(&command) {
-        println!("Invalid command: {command}");
-        println!();
-        println!("Valid commands are");
-        for valid_command in valid_commands {
-            println!("{valid_command}");
-        }
-        panic!()
+fn get_write_options(write_args: &WriteArgs) -> WriteOptions {
+    WriteOptions {
+        dry_run: write_args.dry_run,
+        plan_out: write_args.plan_out.clone(),
+        backup_dir: write_args.backup_dir.clone(),
+        keep_backups: write_args.keep_backups,
+        wait: write_args.wait,
+        assume_yes: write_args.yes,
     }
-
-    command.to_string()
 }
 
-fn get_logfile_path(args: &[String]) -> &str {
-    if args.len() < 2 {
-        panic!("No log file path provided");
+fn get_selection(selection_args: &SelectionArgs) -> TrackSelection {
+    TrackSelection {
+        genre: selection_args.genre.clone(),
+        crate_name: selection_args.crate_name.clone(),
+        playlist: selection_args.playlist.clone(),
+        directory: selection_args.directory.clone(),
     }
+}
 
-    return &args[1];
+fn parse_field_direction(value: &str) -> Result<(TagField, SyncDirection), String> {
+    let (field, direction) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=DIRECTION, got {value}"))?;
+    Ok((field.parse()?, direction.parse()?))
 }
 
-fn get_db_path(args: &[String]) -> &str {
-    if args.len() < 2 {
-        return "~/mixxxdb.sqlite";
+/// Uses the given path or finds the database in the Mixxx settings directory.
+/// Fails if the file does not exist, since opening it would create an empty database.
+fn resolve_db_path(db_arg: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
+    let db_path = match db_arg {
+        Some(db_path) => db_path.to_string(),
+        None => mixxx_settings::default_db_path()
+            .ok_or("Could not determine the Mixxx settings directory, use --db")?
+            .to_string_lossy()
+            .to_string(),
+    };
+
+    if !Path::new(&db_path).is_file() {
+        return Err(format!("Mixxx database {db_path} does not exist, use --db").into());
     }
 
-    return &args[1];
+    Ok(db_path)
 }
 
 #[cfg(test)]
@@ -70,26 +556,263 @@ mod tests {
     use super::*;
 
     #[test]
-    fn get_db_path_defaults_to_mixxxdb_in_home() {
+    fn cli_parses_db_subcommand_with_global_options() {
         // setup
-        let vec: Vec<String> = vec![String::from("test")];
+        let args = [
+            "mixxx_libhelper",
+            "db",
+            "--db",
+            "db.sqlite",
+            "relocate",
+            "/mnt/a",
+            "/mnt/b",
+            "--dry-run",
+            "--yes",
+        ];
+
+        // run
+        let cli = Cli::try_parse_from(args).unwrap();
+
+        // verify
+        let Command::Db(db_args) = cli.command else {
+            panic!("expected db command");
+        };
+        assert_eq!(db_args.db, Some(String::from("db.sqlite")));
+        assert!(db_args.write.dry_run);
+        assert!(db_args.write.yes);
+        assert_eq!(db_args.write.keep_backups, DEFAULT_KEEP_BACKUPS);
+        let DbCommand::Relocate { search_roots, .. } = db_args.command else {
+            panic!("expected relocate command");
+        };
+        assert_eq!(search_roots, vec!["/mnt/a", "/mnt/b"]);
+    }
+
+    #[test]
+    fn cli_requires_search_roots_for_relocate() {
+        // run
+        let result = Cli::try_parse_from(["mixxx_libhelper", "db", "relocate"]);
+
+        // verify
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cli_rejects_keeping_no_backups() {
+        // run
+        let result = Cli::try_parse_from([
+            "mixxx_libhelper",
+            "db",
+            "check-filetypes",
+            "--keep-backups",
+            "0",
+        ]);
+
+        // verify
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn cli_parses_logfile_anonymize() {
+        // run
+        let cli =
+            Cli::try_parse_from(["mixxx_libhelper", "logfile", "anonymize", "mixxx.log"]).unwrap();
+
+        // verify
+        let Command::Logfile(LogfileCommand::Anonymize { logfile }) = cli.command else {
+            panic!("expected logfile anonymize command");
+        };
+        assert_eq!(logfile, "mixxx.log");
+    }
 
+    #[test]
+    fn cli_parses_genre_normalize() {
+        // run
+        let cli = Cli::try_parse_from([
+            "mixxx_libhelper",
+            "genre",
+            "normalize",
+            "--write-tags",
+            "--taxonomy",
+            "genres.toml",
+        ])
+        .unwrap();
+
+        // verify
+        let Command::Genre(genre_args) = cli.command else {
+            panic!("expected genre command");
+        };
+        assert_eq!(genre_args.taxonomy, Some(String::from("genres.toml")));
+        let GenreCommand::Normalize { write_tags } = genre_args.command;
+        assert!(write_tags);
+    }
+
+    #[test]
+    fn cli_parses_sync_field_directions() {
+        // run
+        let cli = Cli::try_parse_from([
+            "mixxx_libhelper",
+            "db",
+            "sync",
+            "--direction",
+            "to-db",
+            "--field",
+            "genre=to-file",
+            "--field",
+            "BPM=skip",
+            "--crate",
+            "Techno",
+        ])
+        .unwrap();
+
+        // verify
+        let Command::Db(db_args) = cli.command else {
+            panic!("expected db command");
+        };
+        let DbCommand::Sync {
+            direction,
+            field_directions,
+            selection,
+        } = db_args.command
+        else {
+            panic!("expected sync command");
+        };
+        assert_eq!(direction, SyncDirection::ToDb);
+        assert_eq!(
+            field_directions,
+            vec![
+                (TagField::Genre, SyncDirection::ToFile),
+                (TagField::Bpm, SyncDirection::Skip)
+            ]
+        );
+        assert_eq!(selection.crate_name, Some(String::from("Techno")));
+    }
+
+    #[test]
+    fn cli_parses_fingerprint_index() {
+        // run
+        let default_cli = Cli::try_parse_from(["mixxx_libhelper", "fingerprint", "index"]).unwrap();
+        let cli = Cli::try_parse_from([
+            "mixxx_libhelper",
+            "fingerprint",
+            "index",
+            "--algorithm",
+            "acoustic",
+            "--cache",
+            "/tmp/fingerprints.sqlite",
+        ])
+        .unwrap();
+
+        // verify
+        let Command::Fingerprint(default_args) = default_cli.command else {
+            panic!("expected fingerprint command");
+        };
+        let FingerprintCommand::Index { algorithms, .. } = default_args.command else {
+            panic!("expected index command");
+        };
+        assert_eq!(
+            algorithms,
+            vec![
+                FingerprintAlgorithm::SHA265,
+                FingerprintAlgorithm::AudioPayloadSHA256
+            ]
+        );
+
+        let Command::Fingerprint(fingerprint_args) = cli.command else {
+            panic!("expected fingerprint command");
+        };
+        assert_eq!(
+            fingerprint_args.cache,
+            Some(String::from("/tmp/fingerprints.sqlite"))
+        );
+        let FingerprintCommand::Index { algorithms, .. } = fingerprint_args.command else {
+            panic!("expected index command");
+        };
+        assert_eq!(algorithms, vec![FingerprintAlgorithm::Acoustic]);
+    }
+
+    #[test]
+    fn cli_parses_duplicates() {
+        // run
+        let cli = Cli::try_parse_from([
+            "mixxx_libhelper",
+            "db",
+            "duplicates",
+            "--by",
+            "payload",
+            "--merge",
+            "--dry-run",
+        ])
+        .unwrap();
+
+        // verify
+        let Command::Db(db_args) = cli.command else {
+            panic!("expected db command");
+        };
+        assert!(db_args.write.dry_run);
+        let DbCommand::Duplicates { matches, merge, .. } = db_args.command else {
+            panic!("expected duplicates command");
+        };
+        assert_eq!(matches, vec![DuplicateMatch::Payload]);
+        assert!(merge);
+    }
+
+    #[test]
+    fn cli_parses_merge() {
+        // run
+        let cli = Cli::try_parse_from([
+            "mixxx_libhelper",
+            "db",
+            "merge",
+            "1",
+            "2",
+            "3",
+            "--keep-rating",
+        ])
+        .unwrap();
+
+        // verify
+        let Command::Db(db_args) = cli.command else {
+            panic!("expected db command");
+        };
+        let DbCommand::Merge {
+            keep_id,
+            drop_ids,
+            policy,
+        } = db_args.command
+        else {
+            panic!("expected merge command");
+        };
+        assert_eq!(keep_id, 1);
+        assert_eq!(drop_ids, vec![2, 3]);
+        assert_eq!(
+            get_merge_policy(&policy),
+            MergePolicy {
+                max_rating: false,
+                ..MergePolicy::default()
+            }
+        );
+    }
+
+    #[test]
+    fn resolve_db_path_rejects_missing_file() {
         // run
-        let db_path = get_db_path(&vec);
+        let result = resolve_db_path(Some("/does/not/exist/mixxxdb.sqlite"));
 
         // verify
-        assert_eq!(db_path, "~/mixxxdb.sqlite");
+        assert!(result.is_err());
     }
 
     #[test]
-    fn get_db_path_gets_path_from_args() {
+    fn resolve_db_path_gets_path_from_args() {
         // setup
-        let vec: Vec<String> = vec![String::from("test"), String::from("db.sqlite")];
+        let db_dir = tempfile::tempdir().unwrap();
+        let db_path = db_dir.path().join("db.sqlite");
+        std::fs::write(&db_path, "").unwrap();
 
         // run
-        let db_path = get_db_path(&vec);
+        let resolved = resolve_db_path(db_path.to_str()).unwrap();
 
         // verify
-        assert_eq!(db_path, "db.sqlite");
+        assert_eq!(resolved, db_path.to_str().unwrap());
     }
 }